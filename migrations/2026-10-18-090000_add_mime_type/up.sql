@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN mime_type VARCHAR NOT NULL DEFAULT 'application/octet-stream'
@@ -0,0 +1,3 @@
+ALTER TABLE files DROP COLUMN scanned_at;
+ALTER TABLE files DROP COLUMN scan_verdict;
+ALTER TABLE files DROP COLUMN scan_status;
@@ -0,0 +1,3 @@
+ALTER TABLE files ADD COLUMN scan_status VARCHAR NOT NULL DEFAULT 'unscanned';
+ALTER TABLE files ADD COLUMN scan_verdict VARCHAR;
+ALTER TABLE files ADD COLUMN scanned_at TIMESTAMP;
@@ -0,0 +1,9 @@
+CREATE TABLE thumbnails (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  file_id INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+  size INTEGER NOT NULL,
+  mime_type VARCHAR NOT NULL,
+  file_path VARCHAR NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (file_id, size)
+)
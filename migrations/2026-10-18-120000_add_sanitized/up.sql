@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN sanitized BOOLEAN NOT NULL DEFAULT 0
@@ -0,0 +1,27 @@
+-- No foreign keys, events must outlive the users and files they mention
+CREATE TABLE audit_events (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  created_at TIMESTAMP NOT NULL,
+  event VARCHAR NOT NULL,
+  user_id INTEGER,
+  token_id INTEGER,
+  client_ip VARCHAR,
+  user_agent VARCHAR,
+  file_id INTEGER,
+  file_hash VARCHAR,
+  detail VARCHAR
+);
+
+CREATE INDEX audit_events_created_at ON audit_events (created_at);
+CREATE INDEX audit_events_file_id ON audit_events (file_id);
+CREATE INDEX audit_events_user_id ON audit_events (user_id);
+
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+BEGIN
+  SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
+
+CREATE TRIGGER audit_events_no_delete BEFORE DELETE ON audit_events
+BEGIN
+  SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
@@ -0,0 +1,5 @@
+DROP TABLE daily_downloads;
+ALTER TABLE files DROP COLUMN last_downloaded_at;
+ALTER TABLE files DROP COLUMN bytes_served;
+ALTER TABLE files DROP COLUMN download_count;
+ALTER TABLE files DROP COLUMN owner_id
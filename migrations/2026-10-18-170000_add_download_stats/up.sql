@@ -0,0 +1,11 @@
+ALTER TABLE files ADD COLUMN owner_id INTEGER REFERENCES users(id);
+ALTER TABLE files ADD COLUMN download_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN bytes_served BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN last_downloaded_at TIMESTAMP;
+
+CREATE TABLE daily_downloads (
+  file_id INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  downloads INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (file_id, day)
+)
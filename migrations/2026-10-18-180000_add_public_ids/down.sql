@@ -0,0 +1,4 @@
+DROP INDEX files_slug;
+ALTER TABLE files DROP COLUMN slug;
+DROP INDEX files_public_id;
+ALTER TABLE files DROP COLUMN public_id;
@@ -0,0 +1,7 @@
+-- Links used to carry the storage hash, keep them working as the public id of existing files
+ALTER TABLE files ADD COLUMN public_id TEXT NOT NULL DEFAULT '';
+UPDATE files SET public_id = file_hash;
+CREATE UNIQUE INDEX files_public_id ON files (public_id);
+
+ALTER TABLE files ADD COLUMN slug TEXT;
+CREATE UNIQUE INDEX files_slug ON files (slug);
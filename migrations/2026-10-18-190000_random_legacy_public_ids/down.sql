@@ -0,0 +1,3 @@
+UPDATE files SET public_id = legacy_id WHERE legacy_id IS NOT NULL;
+DROP INDEX files_legacy_id;
+ALTER TABLE files DROP COLUMN legacy_id;
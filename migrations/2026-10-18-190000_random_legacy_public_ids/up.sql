@@ -0,0 +1,5 @@
+-- Files from before public ids kept their storage hash as id. Give them a random one,
+-- the hash is only kept so their old download links can redirect.
+ALTER TABLE files ADD COLUMN legacy_id TEXT;
+UPDATE files SET legacy_id = public_id, public_id = lower(hex(randomblob(8))) WHERE public_id = file_hash;
+CREATE UNIQUE INDEX files_legacy_id ON files (legacy_id);
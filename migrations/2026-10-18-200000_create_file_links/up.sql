@@ -0,0 +1,10 @@
+-- Public ids and slugs share one namespace, kept unique by the primary key
+CREATE TABLE file_links (
+  link TEXT PRIMARY KEY NOT NULL,
+  file_id INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE
+);
+
+-- A slug that was set to another file's public id loses to the id
+UPDATE files SET slug = NULL WHERE slug IN (SELECT public_id FROM files);
+INSERT INTO file_links (link, file_id) SELECT public_id, id FROM files;
+INSERT INTO file_links (link, file_id) SELECT slug, id FROM files WHERE slug IS NOT NULL;
@@ -0,0 +1,33 @@
+-- Files of 2 GiB and more don't fit a 32 bit size. SQLite can't change a column's
+-- type, so the table is rebuilt with the same columns in the same order.
+CREATE TABLE files_new (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  file_hash VARCHAR NOT NULL,
+  file_name VARCHAR NOT NULL,
+  file_path VARCHAR NOT NULL,
+  size BIGINT NOT NULL,
+  private BOOLEAN NOT NULL DEFAULT 1,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  mime_type VARCHAR NOT NULL DEFAULT 'application/octet-stream',
+  scan_status VARCHAR NOT NULL DEFAULT 'unscanned',
+  scan_verdict VARCHAR,
+  scanned_at TIMESTAMP,
+  sanitized BOOLEAN NOT NULL DEFAULT 0,
+  language VARCHAR,
+  sha256 VARCHAR,
+  owner_id INTEGER REFERENCES users(id),
+  download_count INTEGER NOT NULL DEFAULT 0,
+  bytes_served BIGINT NOT NULL DEFAULT 0,
+  last_downloaded_at TIMESTAMP,
+  public_id TEXT NOT NULL DEFAULT '',
+  slug TEXT,
+  legacy_id TEXT
+);
+
+INSERT INTO files_new SELECT * FROM files;
+DROP TABLE files;
+ALTER TABLE files_new RENAME TO files;
+
+CREATE UNIQUE INDEX files_public_id ON files (public_id);
+CREATE UNIQUE INDEX files_slug ON files (slug);
+CREATE UNIQUE INDEX files_legacy_id ON files (legacy_id);
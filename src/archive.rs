@@ -0,0 +1,290 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use zip::ZipArchive;
+
+/// Entries listed per archive, the rest are reported as truncated.
+pub const MAX_LISTED_ENTRIES: usize = 10_000;
+
+/// Largest member that can be extracted on its own.
+pub const MAX_MEMBER_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+// Compressed tarballs are read from the start, so reaching a member may mean
+// decompressing everything before it. Stop once the output is far larger than
+// any honest archive of that size would produce.
+const MAX_COMPRESSION_RATIO: u64 = 1_000;
+const MIN_DECOMPRESSION_BUDGET: u64 = 64 * 1024 * 1024;
+
+// A member whose declared size is this much larger than its compressed size is treated as a bomb
+const MAX_MEMBER_RATIO: u64 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// Recognises an archive from its sniffed type, compressed tarballs also need a tarball extension.
+    pub fn for_file(mime_type: &str, file_name: &str) -> Option<ArchiveKind> {
+        let name = file_name.to_ascii_lowercase();
+        match mime_type {
+            "application/zip" => Some(ArchiveKind::Zip),
+            "application/x-tar" => Some(ArchiveKind::Tar),
+            "application/gzip" if name.ends_with(".tar.gz") || name.ends_with(".tgz") => Some(ArchiveKind::TarGz),
+            "application/zstd" if name.ends_with(".tar.zst") || name.ends_with(".tzst") => Some(ArchiveKind::TarZst),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: u64,
+    /// Only known for ZIP archives, tarballs are compressed as a whole.
+    pub compressed_size: Option<u64>,
+    pub modified: Option<NaiveDateTime>,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveListing {
+    pub entries: Vec<ArchiveEntry>,
+    pub truncated: bool,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+    NotFound,
+    /// The member is too large or compressed suspiciously well.
+    TooLarge,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "archive I/O error: {}", e),
+            ArchiveError::Zip(e) => write!(f, "zip error: {}", e),
+            ArchiveError::NotFound => write!(f, "no such entry in the archive"),
+            ArchiveError::TooLarge => write!(f, "archive entry exceeds the extraction limits"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ArchiveError::Zip(e)
+    }
+}
+
+/// Normalises an entry name to a relative `/`-separated path.
+///
+/// Returns `None` for names that would escape the extraction directory, such as
+/// absolute paths, drive letters or `..` components.
+pub fn safe_entry_path(name: &str) -> Option<String> {
+    if name.contains('\0') {
+        return None;
+    }
+
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') || name.as_bytes().get(1) == Some(&b':') {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Lists the entries of a stored archive without extracting anything.
+///
+/// Entries with unsafe paths are left out, as they can't be extracted either.
+pub fn list_entries(path: &Path, kind: ArchiveKind) -> Result<ArchiveListing, ArchiveError> {
+    let mut listing = ArchiveListing {
+        entries: Vec::new(),
+        truncated: false,
+    };
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+            for index in 0..archive.len() {
+                if listing.entries.len() == MAX_LISTED_ENTRIES {
+                    listing.truncated = true;
+                    break;
+                }
+                // Raw access reads the headers only, encrypted entries can still be listed
+                let entry = archive.by_index_raw(index)?;
+                let Some(entry_path) = safe_entry_path(entry.name()) else {
+                    continue;
+                };
+                listing.entries.push(ArchiveEntry {
+                    path: entry_path,
+                    size: entry.size(),
+                    compressed_size: Some(entry.compressed_size()),
+                    modified: entry.last_modified().and_then(|time| time.try_into().ok()),
+                    is_dir: entry.is_dir(),
+                });
+            }
+        }
+        _ => {
+            let mut archive = open_tarball(path, kind)?;
+            for entry in archive.entries()? {
+                if listing.entries.len() == MAX_LISTED_ENTRIES {
+                    listing.truncated = true;
+                    break;
+                }
+                let entry = entry?;
+                let header = entry.header();
+                let Some(entry_path) = safe_entry_path(&String::from_utf8_lossy(&entry.path_bytes())) else {
+                    continue;
+                };
+                listing.entries.push(ArchiveEntry {
+                    path: entry_path,
+                    size: entry.size(),
+                    compressed_size: None,
+                    modified: header
+                        .mtime()
+                        .ok()
+                        .and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+                        .map(|time| time.naive_utc()),
+                    is_dir: header.entry_type().is_dir(),
+                });
+            }
+        }
+    }
+
+    Ok(listing)
+}
+
+/// Finds a regular file member and decompresses it into the writer `open` returns.
+///
+/// `open` is given the member's size and only called once the member passes the
+/// extraction limits, so callers learn of a missing member before any output is
+/// produced. Never writes more than the declared size.
+pub fn extract_member<W: Write>(path: &Path, kind: ArchiveKind, member: &str, open: impl FnOnce(u64) -> W) -> Result<u64, ArchiveError> {
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+            let index = zip_member_index(&mut archive, member)?;
+            let entry = archive.by_index(index)?;
+            let size = check_member_size(entry.size(), Some(entry.compressed_size()))?;
+            copy_exact(entry, size, &mut open(size))
+        }
+        _ => {
+            // Tarballs are decompressed once, the member is streamed as soon as it is reached
+            let mut archive = open_tarball(path, kind)?;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if is_tar_member(&entry, member) {
+                    let size = check_member_size(entry.size(), None)?;
+                    return copy_exact(entry, size, &mut open(size));
+                }
+            }
+            Err(ArchiveError::NotFound)
+        }
+    }
+}
+
+fn zip_member_index(archive: &mut ZipArchive<fs::File>, member: &str) -> Result<usize, ArchiveError> {
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.is_file() && safe_entry_path(entry.name()).as_deref() == Some(member) {
+            return Ok(index);
+        }
+    }
+    Err(ArchiveError::NotFound)
+}
+
+fn is_tar_member<R: Read>(entry: &tar::Entry<'_, R>, member: &str) -> bool {
+    entry.header().entry_type().is_file()
+        && safe_entry_path(&String::from_utf8_lossy(&entry.path_bytes())).as_deref() == Some(member)
+}
+
+fn check_member_size(size: u64, compressed_size: Option<u64>) -> Result<u64, ArchiveError> {
+    let suspicious_ratio = compressed_size
+        .is_some_and(|compressed| size > MIN_DECOMPRESSION_BUDGET && size / compressed.max(1) > MAX_MEMBER_RATIO);
+    if size > MAX_MEMBER_SIZE || suspicious_ratio {
+        return Err(ArchiveError::TooLarge);
+    }
+    Ok(size)
+}
+
+// Copies a member, failing if it doesn't match its declared size
+fn copy_exact<R: Read, W: Write>(reader: R, size: u64, output: &mut W) -> Result<u64, ArchiveError> {
+    let copied = io::copy(&mut reader.take(size + 1), output)?;
+    if copied > size {
+        return Err(ArchiveError::TooLarge);
+    }
+    if copied < size {
+        return Err(ArchiveError::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(copied)
+}
+
+fn open_tarball(path: &Path, kind: ArchiveKind) -> Result<tar::Archive<Box<dyn Read>>, ArchiveError> {
+    let file = fs::File::open(path)?;
+    let budget = file
+        .metadata()?
+        .len()
+        .saturating_mul(MAX_COMPRESSION_RATIO)
+        .max(MIN_DECOMPRESSION_BUDGET);
+    let file = io::BufReader::new(file);
+
+    let reader: Box<dyn Read> = match kind {
+        ArchiveKind::Tar => Box::new(file),
+        ArchiveKind::TarGz => Box::new(LimitedReader::new(GzDecoder::new(file), budget)),
+        ArchiveKind::TarZst => Box::new(LimitedReader::new(zstd::Decoder::with_buffer(file)?, budget)),
+        ArchiveKind::Zip => unreachable!("zip archives are not tarballs"),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Fails reads once more than `remaining` bytes have been produced.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R> LimitedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        LimitedReader { inner, remaining: limit }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !buf.is_empty() {
+            return Err(io::Error::other("decompressed archive exceeds the size limit"));
+        }
+        let max = buf.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
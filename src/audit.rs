@@ -0,0 +1,260 @@
+use std::io::Write;
+
+use diesel::prelude::*;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::auth::{self, TOKEN_PREFIX};
+use crate::config::Config;
+use crate::establish_connection;
+use crate::models::{AuditEntry, NewAuditEntry};
+use crate::ratelimit::{bearer_token, request_client_ip};
+
+/// Largest page the admin API returns.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// What an audit event records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    Upload,
+    Download,
+    Delete,
+    /// Access was granted or taken away, such as an API token being issued.
+    PermissionChange,
+    /// A request was refused for lack of credentials or because the file is blocked.
+    AccessDenied,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::Upload => "upload",
+            AuditEvent::Download => "download",
+            AuditEvent::Delete => "delete",
+            AuditEvent::PermissionChange => "permission_change",
+            AuditEvent::AccessDenied => "access_denied",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<AuditEvent> {
+        match value {
+            "upload" => Some(AuditEvent::Upload),
+            "download" => Some(AuditEvent::Download),
+            "delete" => Some(AuditEvent::Delete),
+            "permission_change" => Some(AuditEvent::PermissionChange),
+            "access_denied" => Some(AuditEvent::AccessDenied),
+            _ => None,
+        }
+    }
+}
+
+/// Who made a request, as far as the server can tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Actor {
+    pub user_id: Option<i32>,
+    pub token_id: Option<i32>,
+    /// Unset for the admin CLI.
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Actor {
+    /// Changes made with the admin CLI on the server itself.
+    pub fn local() -> Actor {
+        Actor {
+            user_agent: Some("netdrop-admin".to_string()),
+            ..Actor::default()
+        }
+    }
+}
+
+/// Identifies the caller from its address, `User-Agent` and API token, if any.
+///
+/// Resolved once per request, unknown tokens leave the request anonymous.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let actor = req
+            .local_cache_async(async {
+                let mut actor = Actor {
+                    client_ip: Some(request_client_ip(req).to_string()),
+                    user_agent: req.headers().get_one("User-Agent").map(|agent| agent.chars().take(512).collect()),
+                    ..Actor::default()
+                };
+
+                let token = req.headers().get_one("Authorization").and_then(bearer_token);
+                if let (Some(token), Some(config)) = (token.filter(|token| token.starts_with(TOKEN_PREFIX)), req.rocket().state::<Config>()) {
+                    // Looking the token up also updates its last use, keep both off the async workers
+                    let (database_url, token) = (config.database_url.clone(), token.to_string());
+                    let found = tokio::task::spawn_blocking(move || auth::authenticate_token(&mut establish_connection(&database_url), &token)).await;
+                    match found {
+                        Ok(Ok(Some((token_id, user)))) => {
+                            actor.user_id = Some(user.id);
+                            actor.token_id = Some(token_id);
+                        }
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => tracing::error!(error = %e, "failed to look up API token"),
+                        Err(e) => tracing::error!(error = %e, "API token lookup task failed"),
+                    }
+                }
+                actor
+            })
+            .await;
+        Outcome::Success(actor.clone())
+    }
+}
+
+/// Appends an event about the file a link with `file` leads to, if one is given.
+///
+/// The file's public id is stored even when the link used its slug.
+pub fn record(
+    conn: &mut SqliteConnection,
+    event: AuditEvent,
+    actor: &Actor,
+    file: Option<&str>,
+    detail: Option<&str>,
+) -> QueryResult<()> {
+    use crate::schema::{audit_events, files};
+
+    let _timer = crate::metrics::global().db_timer("audit_record");
+    let found = match file {
+        Some(id) => files::table
+            .filter(files::public_id.eq(id).or(files::slug.eq(id)))
+            .select((files::id, files::public_id))
+            .first::<(i32, String)>(conn)
+            .optional()?,
+        None => None,
+    };
+    let (file_id, file_hash) = match &found {
+        Some((file_id, public_id)) => (Some(*file_id), Some(public_id.as_str())),
+        None => (None, file),
+    };
+
+    diesel::insert_into(audit_events::table)
+        .values(&NewAuditEntry {
+            created_at: chrono::Utc::now().naive_utc(),
+            event: event.as_str(),
+            user_id: actor.user_id,
+            token_id: actor.token_id,
+            client_ip: actor.client_ip.as_deref(),
+            user_agent: actor.user_agent.as_deref(),
+            file_id,
+            file_hash,
+            detail,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records an event from a request handler. Failures are logged, never shown to the client.
+///
+/// The write runs on a blocking thread, log lines land in the caller's span.
+pub async fn record_or_log(config: &Config, event: AuditEvent, actor: &Actor, file: Option<&str>, detail: Option<&str>) {
+    let database_url = config.database_url.clone();
+    let (actor, owned_file, owned_detail) = (actor.clone(), file.map(String::from), detail.map(String::from));
+    let result = tokio::task::spawn_blocking(move || {
+        record(&mut establish_connection(&database_url), event, &actor, owned_file.as_deref(), owned_detail.as_deref())
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!(event = event.as_str(), file, error = %e, "failed to write audit event"),
+        Err(e) => tracing::error!(event = event.as_str(), file, error = %e, "audit task failed"),
+    }
+}
+
+/// Narrows which events are returned. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub event: Option<AuditEvent>,
+    pub user_id: Option<i32>,
+    pub file_hash: Option<String>,
+    pub client_ip: Option<String>,
+    /// Inclusive, in UTC.
+    pub since: Option<chrono::NaiveDateTime>,
+    /// Exclusive, in UTC.
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+impl AuditFilter {
+    fn query(&self) -> crate::schema::audit_events::BoxedQuery<'_, diesel::sqlite::Sqlite> {
+        use crate::schema::audit_events;
+
+        let mut query = audit_events::table.into_boxed();
+        if let Some(event) = self.event {
+            query = query.filter(audit_events::event.eq(event.as_str()));
+        }
+        if let Some(user_id) = self.user_id {
+            query = query.filter(audit_events::user_id.eq(user_id));
+        }
+        if let Some(file_hash) = &self.file_hash {
+            query = query.filter(audit_events::file_hash.eq(file_hash));
+        }
+        if let Some(client_ip) = &self.client_ip {
+            query = query.filter(audit_events::client_ip.eq(client_ip));
+        }
+        if let Some(since) = self.since {
+            query = query.filter(audit_events::created_at.ge(since));
+        }
+        if let Some(until) = self.until {
+            query = query.filter(audit_events::created_at.lt(until));
+        }
+        query
+    }
+}
+
+/// Matching events newest first, starting below the id `before` when paging.
+pub fn query(conn: &mut SqliteConnection, filter: &AuditFilter, before: Option<i32>, limit: i64) -> QueryResult<Vec<AuditEntry>> {
+    use crate::schema::audit_events;
+
+    let mut query = filter.query();
+    if let Some(before) = before {
+        query = query.filter(audit_events::id.lt(before));
+    }
+    query
+        .order(audit_events::id.desc())
+        .limit(limit.clamp(1, MAX_PAGE_SIZE))
+        .select(AuditEntry::as_select())
+        .load(conn)
+}
+
+/// Writes every matching event oldest first as JSON lines, returning how many were written.
+pub fn export(
+    conn: &mut SqliteConnection,
+    filter: &AuditFilter,
+    output: &mut impl Write,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    use crate::schema::audit_events;
+
+    let mut written = 0;
+    let mut after = 0;
+    loop {
+        let page = filter
+            .query()
+            .filter(audit_events::id.gt(after))
+            .order(audit_events::id.asc())
+            .limit(MAX_PAGE_SIZE)
+            .select(AuditEntry::as_select())
+            .load(conn)?;
+
+        for entry in &page {
+            writeln!(output, "{}", rocket::serde::json::to_string(entry)?)?;
+        }
+        written += page.len();
+        match page.last() {
+            Some(last) if page.len() as i64 == MAX_PAGE_SIZE => after = last.id,
+            _ => return Ok(written),
+        }
+    }
+}
+
+/// Parses `2026-10-18` or an RFC 3339 time such as `2026-10-18T09:30:00Z` into UTC.
+pub fn parse_time(value: &str) -> Option<chrono::NaiveDateTime> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0);
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.naive_utc())
+}
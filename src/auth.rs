@@ -0,0 +1,133 @@
+use std::fmt;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::models::{ApiToken, NewApiToken, NewUser, User};
+
+/// Prefix of API tokens, so leaked tokens are easy to recognise.
+pub const TOKEN_PREFIX: &str = "nd_";
+
+const TOKEN_BYTES: usize = 32;
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidUsername,
+    UsernameTaken,
+    UnknownUser,
+    Database(DieselError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidUsername => write!(
+                f,
+                "usernames are 1 to 32 lowercase letters, digits, '.', '_' or '-' and start with a letter or digit"
+            ),
+            AuthError::UsernameTaken => write!(f, "username is already taken"),
+            AuthError::UnknownUser => write!(f, "no such user"),
+            AuthError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<DieselError> for AuthError {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => AuthError::UsernameTaken,
+            e => AuthError::Database(e),
+        }
+    }
+}
+
+pub fn is_valid_username(username: &str) -> bool {
+    (1..=32).contains(&username.len())
+        && username.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+}
+
+pub fn create_user(conn: &mut SqliteConnection, username: &str) -> Result<User, AuthError> {
+    use crate::schema::users;
+
+    if !is_valid_username(username) {
+        return Err(AuthError::InvalidUsername);
+    }
+
+    Ok(diesel::insert_into(users::table)
+        .values(&NewUser { username })
+        .returning(User::as_returning())
+        .get_result(conn)?)
+}
+
+pub fn find_user(conn: &mut SqliteConnection, username: &str) -> QueryResult<Option<User>> {
+    use crate::schema::users;
+
+    users::table
+        .filter(users::username.eq(username))
+        .first::<User>(conn)
+        .optional()
+}
+
+/// A new random token, e.g. `nd_3q2-…`, with 256 bits of entropy.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Tokens are stored hashed, they are long and random enough that a plain SHA-256 suffices.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates an API token for `username`, returning the stored row and the token to hand out.
+pub fn create_token(conn: &mut SqliteConnection, username: &str, name: &str) -> Result<(ApiToken, String), AuthError> {
+    use crate::schema::api_tokens;
+
+    let user = find_user(conn, username)?.ok_or(AuthError::UnknownUser)?;
+    let token = generate_token();
+    let row = diesel::insert_into(api_tokens::table)
+        .values(&NewApiToken {
+            user_id: user.id,
+            name,
+            token_hash: &hash_token(&token),
+        })
+        .returning(ApiToken::as_returning())
+        .get_result(conn)
+        .map_err(AuthError::Database)?;
+
+    Ok((row, token))
+}
+
+/// Looks up the user a token belongs to, recording that the token was used.
+pub fn authenticate(conn: &mut SqliteConnection, token: &str) -> QueryResult<Option<User>> {
+    Ok(authenticate_token(conn, token)?.map(|(_, user)| user))
+}
+
+/// Like [`authenticate`], also returning the id of the token that was used.
+pub fn authenticate_token(conn: &mut SqliteConnection, token: &str) -> QueryResult<Option<(i32, User)>> {
+    use crate::schema::{api_tokens, users};
+
+    let found = api_tokens::table
+        .inner_join(users::table)
+        .filter(api_tokens::token_hash.eq(hash_token(token)))
+        .select((api_tokens::id, User::as_select()))
+        .first::<(i32, User)>(conn)
+        .optional()?;
+
+    if let Some((token_id, _)) = &found {
+        diesel::update(api_tokens::table.find(token_id))
+            .set(api_tokens::last_used_at.eq(Some(chrono::Utc::now().naive_utc())))
+            .execute(conn)?;
+    }
+    Ok(found)
+}
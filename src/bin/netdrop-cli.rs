@@ -0,0 +1,151 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use netdrop::client::{self, Client, ClientError};
+
+#[derive(Parser)]
+#[command(name = "netdrop-cli", version, about = "Upload to and download from a netdrop server")]
+struct Cli {
+    /// Base URL of the server
+    #[arg(long, env = "NETDROP_SERVER", default_value = "http://localhost:8000")]
+    server: String,
+    /// API token sent as a bearer token
+    #[arg(long, env = "NETDROP_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// How many times to retry a transfer after a network error
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+    /// Don't draw progress bars
+    #[arg(long, short)]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload files, directories (as .tar.gz) or `-` for stdin, printing a share URL for each
+    Upload {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// File name to upload stdin as
+        #[arg(long, default_value = "stdin")]
+        name: String,
+        /// Print one JSON object per upload instead of the URL
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download share links or file hashes, resuming partial downloads
+    Download {
+        #[arg(required = true)]
+        links: Vec<String>,
+        /// Directory or file name to save to
+        #[arg(long, short, default_value = ".")]
+        output: PathBuf,
+    },
+}
+
+fn progress_bar(quiet: bool, len: Option<u64>, label: &str) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let bar = match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} {eta}")
+                .expect("valid template")
+                .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{msg} {spinner} {bytes} {bytes_per_sec}").expect("valid template")),
+    };
+    bar.with_message(label.to_string())
+}
+
+fn upload(client: &Client, path: &Path, stdin_name: &str, quiet: bool) -> Result<client::Uploaded, ClientError> {
+    // Stdin and directories are staged in a temporary file so they have a length and can be retried
+    let (staged, name) = if path == Path::new("-") {
+        let mut staged = tempfile::NamedTempFile::new()?;
+        io::copy(&mut io::stdin().lock(), &mut staged)?;
+        (Some(staged), stdin_name.to_string())
+    } else if path.is_dir() {
+        let name = path
+            .canonicalize()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "upload".to_string());
+        (Some(client::pack_directory(path)?), format!("{}.tar.gz", name))
+    } else {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        (None, name)
+    };
+
+    let source = staged.as_ref().map(|staged| staged.path()).unwrap_or(path);
+    let bar = progress_bar(quiet, Some(std::fs::metadata(source)?.len()), &name);
+    let result = client.upload_file(source, &name, {
+        let bar = bar.clone();
+        move |sent| bar.set_position(sent)
+    });
+    bar.finish_and_clear();
+    result
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut client = match Client::new(&cli.server, cli.token) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    client.retries = cli.retries;
+
+    let mut failed = false;
+    match cli.command {
+        Command::Upload { paths, name, json } => {
+            for path in &paths {
+                match upload(&client, path, &name, cli.quiet) {
+                    Ok(uploaded) if json => println!(
+                        "{}",
+                        rocket::serde::json::json!({
+                            "path": path,
+                            "file_hash": uploaded.file_hash,
+                            "sha256": uploaded.sha256,
+                            "url": uploaded.url,
+                        })
+                    ),
+                    Ok(uploaded) => println!("{}", uploaded.url),
+                    Err(e) => {
+                        eprintln!("{}: {}", path.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+        }
+        Command::Download { links, output } => {
+            for link in &links {
+                let bar = progress_bar(cli.quiet, None, link);
+                let result = client.download(link, &output, &|received, total| {
+                    if let Some(total) = total.filter(|&total| bar.length() != Some(total)) {
+                        bar.set_style(progress_bar(false, Some(total), link).style());
+                        bar.set_length(total);
+                    }
+                    bar.set_position(received)
+                });
+                bar.finish_and_clear();
+                match result {
+                    Ok(path) => eprintln!("Saved {}", path.display()),
+                    Err(e) => {
+                        eprintln!("{}: {}", link, e);
+                        failed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
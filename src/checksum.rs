@@ -0,0 +1,86 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+pub type Sha256Digest = [u8; 32];
+
+// Uploads are written in chunks so the digest is computed as the bytes hit the disk
+const WRITE_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumError {
+    /// The client sent a SHA-256 digest that couldn't be decoded.
+    Malformed,
+    Mismatch,
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::Malformed => write!(f, "Malformed SHA-256 digest"),
+            ChecksumError::Mismatch => write!(f, "SHA-256 digest does not match the uploaded file"),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+/// Reads the expected SHA-256 from `Content-Digest` (RFC 9530), falling back to `Digest` (RFC 3230).
+///
+/// Returns `Ok(None)` when neither header carries a SHA-256 digest.
+pub fn from_headers(content_digest: Option<&str>, digest: Option<&str>) -> Result<Option<Sha256Digest>, ChecksumError> {
+    if let Some(expected) = content_digest.and_then(parse_content_digest) {
+        return expected.map(Some);
+    }
+    digest.and_then(parse_digest).transpose()
+}
+
+/// Parses `sha-256=:<base64>:` out of a `Content-Digest` dictionary.
+pub fn parse_content_digest(value: &str) -> Option<Result<Sha256Digest, ChecksumError>> {
+    find_algorithm(value, "sha-256").map(|encoded| {
+        encoded
+            .strip_prefix(':')
+            .and_then(|encoded| encoded.strip_suffix(':'))
+            .ok_or(ChecksumError::Malformed)
+            .and_then(decode_base64)
+    })
+}
+
+/// Parses `SHA-256=<base64>` out of a `Digest` header.
+pub fn parse_digest(value: &str) -> Option<Result<Sha256Digest, ChecksumError>> {
+    find_algorithm(value, "sha-256").map(decode_base64)
+}
+
+/// Parses a digest given as 64 hex characters.
+pub fn parse_hex(value: &str) -> Result<Sha256Digest, ChecksumError> {
+    let bytes = hex::decode(value.trim()).map_err(|_| ChecksumError::Malformed)?;
+    bytes.try_into().map_err(|_| ChecksumError::Malformed)
+}
+
+fn find_algorithm<'a>(value: &'a str, algorithm: &str) -> Option<&'a str> {
+    value.split(',').find_map(|entry| {
+        let (name, encoded) = entry.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case(algorithm)
+            .then(|| encoded.trim())
+    })
+}
+
+fn decode_base64(encoded: &str) -> Result<Sha256Digest, ChecksumError> {
+    let bytes = STANDARD.decode(encoded).map_err(|_| ChecksumError::Malformed)?;
+    bytes.try_into().map_err(|_| ChecksumError::Malformed)
+}
+
+/// Writes `data` to `writer` in chunks, returning the SHA-256 of everything written.
+pub fn write_hashed<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<Sha256Digest> {
+    let mut hasher = Sha256::new();
+    for chunk in data.chunks(WRITE_CHUNK_SIZE) {
+        writer.write_all(chunk)?;
+        hasher.update(chunk);
+    }
+    writer.flush()?;
+    Ok(hasher.finalize().into())
+}
@@ -0,0 +1,275 @@
+use clap::{Args, Parser, Subcommand};
+use diesel_migrations::MigrationHarness;
+use netdrop::audit::{self, Actor, AuditEvent};
+use netdrop::config::Config;
+use netdrop::fsck::{self, FsckOptions, GcOptions};
+use netdrop::logging;
+use netdrop::scan::{self, ScanStatus, UploadScanner};
+use netdrop::{auth, delete_file, establish_connection, get_file_by_hash, get_file_by_public_id, list_files, MIGRATIONS};
+
+#[derive(Parser)]
+#[command(name = "netdrop", version, about = "Self-hosted file sharing")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Apply pending database migrations
+    Migrate,
+    /// List stored files, newest first
+    List(ListArgs),
+    /// Delete a file, its thumbnails and its database row
+    Delete {
+        /// Public id or slug from its link, or its storage hash
+        id: String,
+    },
+    /// Remove files no row refers to, once they are an hour old
+    Gc {
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Also delete rows whose file is missing
+        #[arg(long)]
+        remove_missing: bool,
+    },
+    /// Check stored files against the database
+    Fsck(FsckArgs),
+    /// Scan files again whose scan failed, e.g. while clamd was down
+    Rescan,
+    /// Manage users
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manage API tokens
+    #[command(subcommand)]
+    Token(TokenCommand),
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Maximum number of files to show
+    #[arg(long, default_value_t = 50)]
+    limit: i64,
+    /// Print one JSON object per file
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+pub struct FsckArgs {
+    /// Only check that files exist with the right size, without re-hashing them
+    #[arg(long)]
+    skip_hashes: bool,
+    /// Delete rows whose file is missing
+    #[arg(long)]
+    remove_dangling: bool,
+    /// Move files no row refers to into the quarantine directory, once they are an hour old
+    #[arg(long)]
+    quarantine_orphans: bool,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user
+    Add { username: String },
+}
+
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    /// Create an API token for a user and print it
+    Create {
+        username: String,
+        /// What the token is for, e.g. the machine it is used on
+        #[arg(long, default_value = "cli")]
+        name: String,
+    },
+}
+
+/// Runs an admin command, returning the process exit code.
+pub fn run(command: Command) -> i32 {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            return 2;
+        }
+    };
+    if let Err(e) = logging::init(&config.logging) {
+        eprintln!("Invalid log configuration: {}", e);
+        return 2;
+    }
+    let mut connection = establish_connection(&config.database_url);
+
+    // Every command needs an up to date schema, `migrate` just reports what it did
+    let applied = match connection.run_pending_migrations(MIGRATIONS) {
+        Ok(applied) => applied,
+        Err(e) => {
+            eprintln!("Failed to run migrations: {}", e);
+            return 2;
+        }
+    };
+
+    let result = match command {
+        Command::Serve => unreachable!("the server is started by main"),
+        Command::Migrate => {
+            for migration in &applied {
+                println!("Applied {}", migration);
+            }
+            println!("{} migrations applied", applied.len());
+            Ok(0)
+        }
+        Command::List(args) => list(&mut connection, args),
+        Command::Delete { id } => delete(&mut connection, &id),
+        Command::Gc { dry_run, remove_missing } => gc(&mut connection, &config, GcOptions { dry_run, remove_missing }),
+        Command::Fsck(args) => run_fsck(&mut connection, &config, args),
+        Command::Rescan => rescan(&mut connection, &config),
+        Command::User(UserCommand::Add { username }) => add_user(&mut connection, &username),
+        Command::Token(TokenCommand::Create { username, name }) => create_token(&mut connection, &username, &name),
+    };
+
+    result.unwrap_or_else(|e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("Error: {}", e);
+        1
+    })
+}
+
+type CommandResult = Result<i32, Box<dyn std::error::Error + Send + Sync>>;
+
+fn list(connection: &mut diesel::SqliteConnection, args: ListArgs) -> CommandResult {
+    for file in list_files(connection, args.limit)? {
+        if args.json {
+            let entry = rocket::serde::json::json!({
+                "public_id": file.public_id,
+                "slug": file.slug,
+                "file_hash": file.file_hash,
+                "file_name": file.file_name,
+                "size": file.size,
+                "mime_type": file.mime_type,
+                "scan_status": file.scan_status,
+                "sha256": file.sha256,
+                "created_at": file.created_at,
+            });
+            println!("{}", entry);
+        } else {
+            println!(
+                "{}  {:>12}  {:<24}  {}  {:<9}  {}",
+                file.public_id,
+                file.size,
+                file.mime_type,
+                file.created_at.format("%Y-%m-%d %H:%M"),
+                file.scan_status,
+                file.file_name
+            );
+        }
+    }
+    Ok(0)
+}
+
+fn delete(connection: &mut diesel::SqliteConnection, id: &str) -> CommandResult {
+    let Some(file) = get_file_by_public_id(connection, id).or_else(|| get_file_by_hash(connection, id)) else {
+        eprintln!("No file with id {}", id);
+        return Ok(1);
+    };
+
+    // Recorded first, the event links to the row by id
+    audit::record(connection, AuditEvent::Delete, &Actor::local(), Some(&file.public_id), Some(&file.file_name))?;
+    delete_file(connection, &file)?;
+    println!("Deleted {} ({})", file.public_id, file.file_name);
+    Ok(0)
+}
+
+fn add_user(connection: &mut diesel::SqliteConnection, username: &str) -> CommandResult {
+    let user = auth::create_user(connection, username)?;
+    let detail = format!("created user {}", user.username);
+    audit::record(connection, AuditEvent::PermissionChange, &Actor::local(), None, Some(&detail))?;
+
+    println!("Created user {} (id {})", user.username, user.id);
+    Ok(0)
+}
+
+fn create_token(connection: &mut diesel::SqliteConnection, username: &str, name: &str) -> CommandResult {
+    let (row, token) = auth::create_token(connection, username, name)?;
+    let detail = format!("created token {} '{}' for {}", row.id, row.name, username);
+    audit::record(connection, AuditEvent::PermissionChange, &Actor::local(), None, Some(&detail))?;
+
+    // Only the hash is stored, so this is the one chance to copy it
+    println!("{}", token);
+    Ok(0)
+}
+
+fn gc(connection: &mut diesel::SqliteConnection, config: &Config, options: GcOptions) -> CommandResult {
+    let report = fsck::collect_garbage(connection, &config.upload_dir(), &config.thumbnail_dir(), options)?;
+
+    let verb = if options.dry_run { "Would remove" } else { "Removed" };
+    for file_hash in &report.removed_rows {
+        println!("{} row {}", verb, file_hash);
+    }
+    for path in &report.removed_files {
+        println!("{} {}", verb, path);
+    }
+    println!(
+        "{} {} rows and {} files, {} bytes",
+        verb,
+        report.removed_rows.len(),
+        report.removed_files.len(),
+        report.freed_bytes
+    );
+    Ok(0)
+}
+
+/// Runs `netdrop rescan`, exiting with 1 if any file still couldn't be scanned.
+///
+/// Pending files are left alone, a running server may be scanning them and the
+/// server resumes the rest when it starts.
+fn rescan(connection: &mut diesel::SqliteConnection, config: &Config) -> CommandResult {
+    let Some(scanner) = UploadScanner::from_config(&config.scanner)?.0 else {
+        eprintln!("No scanner is configured");
+        return Ok(1);
+    };
+
+    let mut failed = 0;
+    for file in scan::files_to_rescan(connection, &[ScanStatus::Error], chrono::Utc::now().naive_utc())? {
+        let status = scan::scan_and_record(connection, scanner.as_ref(), &file, &config.quarantine_dir())?;
+        failed += usize::from(status == ScanStatus::Error);
+        println!("{}  {:<9}  {}", file.public_id, status.as_str(), file.file_name);
+    }
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+/// Runs `netdrop fsck`, exiting with 1 if any problems were found.
+fn run_fsck(connection: &mut diesel::SqliteConnection, config: &Config, args: FsckArgs) -> CommandResult {
+    let options = FsckOptions {
+        verify_hashes: !args.skip_hashes,
+        remove_dangling_rows: args.remove_dangling,
+        quarantine_orphans: args.quarantine_orphans,
+    };
+    let report = fsck::check(connection, &config.upload_dir(), &config.quarantine_dir(), options)?;
+
+    if args.json {
+        println!("{}", rocket::serde::json::to_string(&report)?);
+    } else {
+        for issue in &report.issues {
+            println!("{:?} {} {}: {}", issue.kind, issue.file_hash, issue.file_path, issue.detail);
+        }
+        for orphan in &report.orphans {
+            println!("Orphan {}", orphan);
+        }
+        println!(
+            "Checked {} files ({} without a checksum): {} problems, {} orphans, {} rows removed, {} orphans quarantined",
+            report.checked,
+            report.unhashed,
+            report.issues.len(),
+            report.orphans.len(),
+            report.removed_rows,
+            report.quarantined_orphans
+        );
+    }
+
+    Ok(if report.is_clean() { 0 } else { 1 })
+}
@@ -0,0 +1,368 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::multipart::{Form, Part};
+use reqwest::blocking::{Client as HttpClient, RequestBuilder, Response};
+use reqwest::header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Io(io::Error),
+    /// The server answered but refused the request.
+    Server(String),
+    InvalidLink(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Io(e) => write!(f, "I/O error: {}", e),
+            ClientError::Server(message) => write!(f, "server error: {}", message),
+            ClientError::InvalidLink(link) => write!(f, "not a netdrop link: {}", link),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl ClientError {
+    // Only failures that might succeed on a second attempt are retried
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Http(e) => e.is_connect() || e.is_timeout() || e.is_body() || e.is_request(),
+            ClientError::Server(_) | ClientError::InvalidLink(_) | ClientError::Io(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadReply {
+    success: bool,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+    file_hash: Option<String>,
+    sha256: Option<String>,
+}
+
+/// A completed upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uploaded {
+    pub file_hash: String,
+    pub sha256: Option<String>,
+    pub url: String,
+}
+
+/// Talks to a netdrop server over its HTTP API.
+pub struct Client {
+    http: HttpClient,
+    server: String,
+    token: Option<String>,
+    /// Extra attempts after a transient failure.
+    pub retries: u32,
+}
+
+impl Client {
+    pub fn new(server: &str, token: Option<String>) -> Result<Client, ClientError> {
+        let http = HttpClient::builder()
+            .user_agent(concat!("netdrop-cli/", env!("CARGO_PKG_VERSION")))
+            .timeout(None)
+            .connect_timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(Client {
+            http,
+            server: server.trim_end_matches('/').to_string(),
+            token,
+            retries: 3,
+        })
+    }
+
+    pub fn share_url(&self, file_hash: &str) -> String {
+        format!("{}/download/{}", self.server, file_hash)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    fn with_retries<T>(&self, mut attempt: impl FnMut() -> Result<T, ClientError>) -> Result<T, ClientError> {
+        let mut tries = 0;
+        loop {
+            match attempt() {
+                Err(e) if e.is_transient() && tries < self.retries => {
+                    tries += 1;
+                    thread::sleep(Duration::from_secs(1 << tries.min(5)));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Uploads a file through `/api/v1/upload`, sending its SHA-256 so the server verifies it.
+    ///
+    /// The server has no partial uploads, so an interrupted upload is retried from the start.
+    pub fn upload_file<P>(&self, path: &Path, name: &str, progress: P) -> Result<Uploaded, ClientError>
+    where
+        P: Fn(u64) + Clone + Send + 'static,
+    {
+        let sha256 = hash_file(path)?;
+        let len = fs::metadata(path)?.len();
+
+        self.with_retries(|| {
+            progress(0);
+            let reader = ProgressReader {
+                inner: fs::File::open(path)?,
+                read: 0,
+                progress: progress.clone(),
+            };
+            let form = Form::new()
+                .text("sha256", sha256.clone())
+                .part("file", Part::reader_with_length(reader, len).file_name(name.to_string()));
+
+            let response = self
+                .authorize(self.http.post(format!("{}/api/v1/upload", self.server)))
+                .multipart(form)
+                .send()?;
+            self.read_upload_reply(response)
+        })
+    }
+
+    fn read_upload_reply(&self, response: Response) -> Result<Uploaded, ClientError> {
+        let status = response.status();
+        let reply: UploadReply = response
+            .json()
+            .map_err(|_| ClientError::Server(format!("unexpected response ({})", status)))?;
+
+        match reply {
+            UploadReply { success: true, file_hash: Some(file_hash), sha256, .. } => Ok(Uploaded {
+                url: self.share_url(&file_hash),
+                file_hash,
+                sha256,
+            }),
+            reply => Err(ClientError::Server(
+                reply.error.or(reply.message).unwrap_or_else(|| status.to_string()),
+            )),
+        }
+    }
+
+    /// Downloads a link or hash into `output`, a directory or a file path.
+    ///
+    /// Bytes are written to `<name>.part` first, so an interrupted download resumes
+    /// where it stopped, on a later attempt or the next run.
+    /// `progress` gets the bytes received so far and the file's size, when the server says.
+    pub fn download(&self, link: &str, output: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<PathBuf, ClientError> {
+        let url = self.download_url(link)?;
+
+        // Learn the file's name and size before deciding where the partial file goes
+        let head = self.authorize(self.http.head(&url)).send()?;
+        if !head.status().is_success() {
+            return Err(ClientError::Server(format!("{} for {}", head.status(), url)));
+        }
+        let name = head
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(filename_from_disposition)
+            .unwrap_or_else(|| url.rsplit('/').next().unwrap_or("download").to_string());
+        // `content_length()` describes the (empty) body of a HEAD response, not the file
+        let total = head
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        let target = if output.is_dir() { output.join(&name) } else { output.to_path_buf() };
+        let mut partial_name = target.file_name().unwrap_or_default().to_os_string();
+        partial_name.push(".part");
+        let partial = target.with_file_name(partial_name);
+
+        self.with_retries(|| self.download_into(&url, &partial, &|received| progress(received, total)))?;
+        fs::rename(&partial, &target)?;
+        Ok(target)
+    }
+
+    fn download_into(&self, url: &str, partial: &Path, progress: &dyn Fn(u64)) -> Result<(), ClientError> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(partial)?;
+        let have = file.metadata()?.len();
+
+        let mut request = self.authorize(self.http.get(url));
+        if have > 0 {
+            request = request.header(RANGE, format!("bytes={}-", have));
+        }
+        let mut response = request.send()?;
+
+        let mut written = match response.status() {
+            StatusCode::PARTIAL_CONTENT if content_range_start(&response) == Some(have) => have,
+            // A previous run already fetched everything
+            StatusCode::RANGE_NOT_SATISFIABLE if content_range_total(&response) == Some(have) => return Ok(()),
+            status if status.is_success() => {
+                // The server sent the whole file, start over
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                0
+            }
+            status => return Err(ClientError::Server(format!("{} for {}", status, url))),
+        };
+        progress(written);
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = response.read(&mut buffer).map_err(|e| match e.into_inner() {
+                Some(e) => match e.downcast::<reqwest::Error>() {
+                    Ok(e) => ClientError::Http(*e),
+                    Err(e) => ClientError::Io(io::Error::other(e)),
+                },
+                None => ClientError::Io(io::ErrorKind::Other.into()),
+            })?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            written += read as u64;
+            progress(written);
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Builds the download URL for a share link, `/view/` link or bare hash.
+    pub fn download_url(&self, link: &str) -> Result<String, ClientError> {
+        let link = link.trim();
+        if !link.contains('/') {
+            if link.is_empty() || !link.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(ClientError::InvalidLink(link.to_string()));
+            }
+            return Ok(self.share_url(link));
+        }
+
+        let url = reqwest::Url::parse(link).map_err(|_| ClientError::InvalidLink(link.to_string()))?;
+        let segments: Vec<&str> = url.path_segments().map(|s| s.filter(|s| !s.is_empty()).collect()).unwrap_or_default();
+        let file_hash = match segments.as_slice() {
+            [.., route, hash] if matches!(*route, "download" | "view" | "raw" | "f") => *hash,
+            _ => return Err(ClientError::InvalidLink(link.to_string())),
+        };
+
+        let origin = url.origin().ascii_serialization();
+        let prefix = &segments[..segments.len() - 2];
+        let base = if prefix.is_empty() { origin } else { format!("{}/{}", origin, prefix.join("/")) };
+        Ok(format!("{}/download/{}", base, file_hash))
+    }
+}
+
+/// Reports how much of the request body has been sent.
+struct ProgressReader<R, P> {
+    inner: R,
+    read: u64,
+    progress: P,
+}
+
+impl<R: Read, P: Fn(u64)> Read for ProgressReader<R, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        (self.progress)(self.read);
+        Ok(read)
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn content_range_start(response: &Response) -> Option<u64> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    value.strip_prefix("bytes ")?.split('-').next()?.parse().ok()
+}
+
+// The `len` of a 416's `Content-Range: bytes */len`
+fn content_range_total(response: &Response) -> Option<u64> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    value.strip_prefix("bytes */")?.parse().ok()
+}
+
+/// Packs a directory into a gzipped tarball in a temporary file, for uploading as one file.
+pub fn pack_directory(dir: &Path) -> io::Result<tempfile::NamedTempFile> {
+    let tarball = tempfile::NamedTempFile::new()?;
+    let encoder = flate2::write::GzEncoder::new(tarball.reopen()?, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    let root = dir.canonicalize()?;
+    let name = root.file_name().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("upload"));
+    builder.append_dir_all(&name, &root)?;
+    builder.into_inner()?.finish()?.sync_all()?;
+    Ok(tarball)
+}
+
+/// Extracts a safe file name from a `Content-Disposition` header, preferring `filename*`.
+pub fn filename_from_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in value.split(';').skip(1) {
+        let Some((key, raw)) = param.trim().split_once('=') else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                extended = raw
+                    .trim()
+                    .strip_prefix("UTF-8''")
+                    .or_else(|| raw.trim().strip_prefix("utf-8''"))
+                    .and_then(percent_decode);
+            }
+            "filename" => plain = Some(raw.trim().trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+
+    // Never let the server pick a directory to write into
+    extended
+        .or(plain)
+        .map(|name| name.rsplit(['/', '\\']).next().unwrap_or_default().to_string())
+        .filter(|name| !name.is_empty() && name != "." && name != "..")
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,203 @@
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use rocket::data::ByteUnit;
+use rocket::figment::providers::{Env, Format, Toml};
+use rocket::figment::{self, Figment};
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::cors::CorsConfig;
+use crate::filetype::UploadPolicy;
+use crate::health::HealthConfig;
+use crate::logging::LogConfig;
+use crate::metadata::MetadataConfig;
+use crate::metrics::MetricsConfig;
+use crate::public_id::{self, PublicIdConfig};
+use crate::ratelimit::{self, RateLimitConfig};
+use crate::scan::{ScannerConfig, UploadScanner};
+use crate::stats::DownloadStatsConfig;
+use crate::throttle::ThrottleConfig;
+
+/// Where the configuration file is read from unless `NETDROP_CONFIG` says otherwise.
+pub const DEFAULT_CONFIG_FILE: &str = "netdrop.toml";
+
+/// Server settings, loaded once at startup and shared as managed state.
+///
+/// Values come from, lowest priority first: Rocket's own sources (`Rocket.toml`,
+/// `ROCKET_*`), the TOML file, `NETDROP_*` environment variables and finally the
+/// older unprefixed variables such as `DATA_DIR` and `DATABASE_URL`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    /// Holds `uploads/`, `thumbnails/` and `quarantine/`.
+    pub data_dir: PathBuf,
+    /// SQLite database, `<data_dir>/netdrop.db` when empty.
+    pub database_url: String,
+    /// Largest accepted upload body.
+    pub max_upload_size: ByteUnit,
+    /// Scheme and host links are built on, otherwise taken from the request's `Host`.
+    pub public_url: Option<String>,
+    /// Bearer token for the admin API, which is disabled when unset.
+    pub admin_token: Option<String>,
+    pub cors: CorsConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LogConfig,
+    pub health: HealthConfig,
+    pub download_stats: DownloadStatsConfig,
+    pub public_ids: PublicIdConfig,
+    pub rate_limit: RateLimitConfig,
+    /// Download speed limits.
+    pub throttle: ThrottleConfig,
+    /// Which file types may be uploaded.
+    pub upload_policy: UploadPolicy,
+    /// Malware scanner uploads are checked with.
+    pub scanner: ScannerConfig,
+    pub metadata: MetadataConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("data"),
+            database_url: String::new(),
+            max_upload_size: ByteUnit::Megabyte(1000),
+            public_url: None,
+            admin_token: None,
+            cors: CorsConfig::default(),
+            metrics: MetricsConfig::default(),
+            logging: LogConfig::default(),
+            health: HealthConfig::default(),
+            download_stats: DownloadStatsConfig::default(),
+            public_ids: PublicIdConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            throttle: ThrottleConfig::default(),
+            upload_policy: UploadPolicy::default(),
+            scanner: ScannerConfig::default(),
+            metadata: MetadataConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A value is missing, of the wrong type or the file is malformed.
+    Figment(Box<figment::Error>),
+    Invalid { key: &'static str, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Figment(e) => {
+                let errors: Vec<String> = (**e).clone().into_iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("; "))
+            }
+            ConfigError::Invalid { key, message } => write!(f, "invalid `{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<figment::Error> for ConfigError {
+    fn from(e: figment::Error) -> Self {
+        ConfigError::Figment(Box::new(e))
+    }
+}
+
+impl Config {
+    /// The combined sources, also used to configure Rocket itself.
+    pub fn figment() -> Figment {
+        dotenvy::dotenv().ok();
+        let file = env::var("NETDROP_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+
+        Figment::from(rocket::Config::figment())
+            .merge(Toml::file(file))
+            .merge(Env::prefixed("NETDROP_").ignore(&["CONFIG"]).split("__").global())
+            .merge(Env::raw().only(&["DATA_DIR", "DATABASE_URL", "PUBLIC_URL", "ADMIN_TOKEN"]).global())
+    }
+
+    /// Reads the configuration from all sources.
+    pub fn load() -> Result<Config, ConfigError> {
+        Config::from_figment(&Config::figment())
+    }
+
+    pub fn from_figment(figment: &Figment) -> Result<Config, ConfigError> {
+        figment.extract::<Config>()?.validated()
+    }
+
+    /// Checks values and fills in ones derived from others.
+    pub fn validated(mut self) -> Result<Config, ConfigError> {
+        if self.data_dir.as_os_str().is_empty() {
+            return Err(invalid("data_dir", "must not be empty"));
+        }
+        if self.data_dir.exists() && !self.data_dir.is_dir() {
+            return Err(invalid("data_dir", format!("{} is not a directory", self.data_dir.display())));
+        }
+        if self.database_url.trim().is_empty() {
+            self.database_url = self.data_dir.join("netdrop.db").to_string_lossy().into_owned();
+        }
+        if self.max_upload_size == 0 {
+            return Err(invalid("max_upload_size", "must be greater than zero"));
+        }
+        // Sizes are stored as signed 64 bit integers
+        if self.max_upload_size > i64::MAX as u64 {
+            return Err(invalid("max_upload_size", "must be less than 8 EiB"));
+        }
+        if self.download_stats.flush_interval == 0 {
+            return Err(invalid("download_stats.flush_interval", "must be greater than zero"));
+        }
+        if !(public_id::MIN_LENGTH..=public_id::MAX_LENGTH).contains(&self.public_ids.length) {
+            let message = format!("must be between {} and {}", public_id::MIN_LENGTH, public_id::MAX_LENGTH);
+            return Err(invalid("public_ids.length", message));
+        }
+
+        // An empty variable is how deployments usually unset a value
+        self.public_url = self.public_url.filter(|url| !url.trim().is_empty());
+        if let Some(url) = &mut self.public_url {
+            let trimmed = url.trim().trim_end_matches('/');
+            let host = trimmed.strip_prefix("https://").or_else(|| trimmed.strip_prefix("http://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains(char::is_whitespace)) {
+                return Err(invalid("public_url", format!("'{}' is not an http(s) URL", url)));
+            }
+            *url = trimmed.to_string();
+        }
+        self.admin_token = self.admin_token.filter(|token| !token.is_empty());
+        self.metrics.token = self.metrics.token.filter(|token| !token.is_empty());
+        self.cors.validate().map_err(|message| invalid("cors", message))?;
+        self.logging.env_filter().map_err(|message| invalid("logging.filter", message))?;
+        ratelimit::parse_trusted_proxies(&self.rate_limit.trusted_proxies)
+            .map_err(|message| invalid("rate_limit.trusted_proxies", message))?;
+        self.upload_policy.normalize().map_err(|message| invalid("upload_policy", message))?;
+        UploadScanner::from_config(&self.scanner).map_err(|message| invalid("scanner", message))?;
+
+        Ok(self)
+    }
+
+    /// Scheme, host and port of `public_url`.
+    pub fn public_origin(&self) -> Option<&str> {
+        let url = self.public_url.as_deref()?;
+        let authority_start = url.find("://")? + 3;
+        Some(match url[authority_start..].find('/') {
+            Some(path_start) => &url[..authority_start + path_start],
+            None => url,
+        })
+    }
+
+    pub fn upload_dir(&self) -> PathBuf {
+        self.data_dir.join("uploads")
+    }
+
+    pub fn thumbnail_dir(&self) -> PathBuf {
+        self.data_dir.join("thumbnails")
+    }
+
+    pub fn quarantine_dir(&self) -> PathBuf {
+        self.data_dir.join("quarantine")
+    }
+}
+
+fn invalid(key: &'static str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { key, message: message.into() }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use rocket::http::Method;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions};
+
+/// Cross-origin access to the API.
+///
+/// With no `allowed_origins` the server sends no CORS headers at all, so browsers
+/// only let pages served by netdrop itself use the API.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CorsConfig {
+    /// Exact origins such as `https://app.example.com`, or `*` for any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers cross-origin pages may send, or `*` for any.
+    pub allowed_headers: Vec<String>,
+    /// Response headers cross-origin pages may read.
+    pub expose_headers: Vec<String>,
+    /// Whether cookies and HTTP auth are sent with cross-origin requests.
+    pub allow_credentials: bool,
+    /// Seconds browsers may cache a preflight response.
+    pub max_age: Option<usize>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]),
+            allowed_headers: strings(&["Accept", "Authorization", "Content-Type", "Content-Digest", "Digest", "Range"]),
+            expose_headers: strings(&["Content-Disposition", "Content-Range", "Accept-Ranges", "Retry-After"]),
+            allow_credentials: false,
+            max_age: Some(3600),
+        }
+    }
+}
+
+impl CorsConfig {
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Checks the policy is one browsers will honour, returning what is wrong with it.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allows_any_origin() && self.allow_credentials {
+            return Err("allow_credentials can't be combined with the `*` origin".to_string());
+        }
+        for origin in self.allowed_origins.iter().filter(|origin| *origin != "*") {
+            let host = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                return Err(format!("'{}' is not an origin like https://example.com", origin));
+            }
+        }
+        for method in &self.allowed_methods {
+            if Method::from_str(&method.to_ascii_uppercase()).is_err() {
+                return Err(format!("unknown method '{}'", method));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the CORS fairing, or `None` when only same-origin requests are allowed.
+    ///
+    /// `own_origin` is always allowed, since browsers send `Origin` on same-origin
+    /// POSTs too and those would otherwise be refused.
+    pub fn fairing(&self, own_origin: Option<&str>) -> Result<Option<Cors>, rocket_cors::Error> {
+        if self.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let allowed_origins = if self.allows_any_origin() {
+            AllowedOrigins::all()
+        } else {
+            let mut origins = self.allowed_origins.clone();
+            origins.extend(own_origin.map(String::from));
+            AllowedOrigins::some_exact(&origins)
+        };
+        let allowed_headers = if self.allowed_headers.iter().any(|header| header == "*") {
+            AllowedHeaders::all()
+        } else {
+            AllowedHeaders::some(&self.allowed_headers.iter().map(String::as_str).collect::<Vec<_>>())
+        };
+        let allowed_methods = self
+            .allowed_methods
+            .iter()
+            .filter_map(|method| Method::from_str(&method.to_ascii_uppercase()).ok())
+            .map(From::from)
+            .collect();
+
+        CorsOptions::default()
+            .allowed_origins(allowed_origins)
+            .allowed_methods(allowed_methods)
+            .allowed_headers(allowed_headers)
+            .expose_headers(self.expose_headers.iter().cloned().collect::<HashSet<_>>())
+            .allow_credentials(self.allow_credentials)
+            .max_age(self.max_age)
+            // Refused requests are rerouted to `/cors/<status>`, which must outrank `static_files`
+            .fairing_route_rank(-20)
+            .to_cors()
+            .map(Some)
+    }
+}
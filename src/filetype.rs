@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use rocket::http::ContentType;
+use rocket::serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+// Bytes of the upload inspected when detecting its type
+pub const SNIFF_LEN: usize = 8192;
+
+/// Detects the MIME type of an upload from its leading bytes.
+///
+/// Magic bytes take precedence over the client supplied filename, which is only
+/// consulted for formats without a signature (plain text, CSV, source code, ...).
+pub fn sniff_mime_type(head: &[u8], filename: &str) -> String {
+    let head = &head[..head.len().min(SNIFF_LEN)];
+
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+
+    // Formats without a signature are textual, so binary data keeps the generic type
+    if head.is_empty() || !looks_like_text(head) {
+        return DEFAULT_MIME_TYPE.to_string();
+    }
+
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension)
+        .map(|content_type| format!("{}/{}", content_type.top(), content_type.sub()))
+        .unwrap_or_else(|| "text/plain".to_string())
+}
+
+/// Whether files of this type are text that can be shown as-is.
+pub fn is_textual(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || mime_type.ends_with("+xml")
+        || mime_type.ends_with("+json")
+        || matches!(
+            mime_type,
+            "application/json" | "application/xml" | "application/javascript" | "application/x-sh"
+        )
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sniffed window may end in the middle of a multi-byte character
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    Blocked(String),
+    NotAllowed(String),
+}
+
+impl std::fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyError::Blocked(mime) => write!(f, "Files of type {} are blocked", mime),
+            PolicyError::NotAllowed(mime) => write!(f, "Files of type {} are not allowed", mime),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Which file types may be uploaded.
+///
+/// Entries are MIME types (`application/x-executable`), wildcards (`image/*`) or
+/// filename extensions (`.exe`). The block list wins over the allow list, and an
+/// empty allow list allows everything that isn't blocked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UploadPolicy {
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl UploadPolicy {
+    /// Lowercases the entries, returning the first one that isn't a type or extension.
+    pub fn normalize(&mut self) -> Result<(), String> {
+        for entry in self.allowed.iter_mut().chain(self.blocked.iter_mut()) {
+            *entry = entry.trim().to_ascii_lowercase();
+            let well_formed = entry == "*"
+                || entry.strip_prefix('.').is_some_and(|ext| !ext.is_empty() && !ext.contains('/'))
+                || entry.split_once('/').is_some_and(|(top, sub)| !top.is_empty() && !sub.is_empty());
+            if !well_formed {
+                return Err(format!("'{}' is not a MIME type or .extension", entry));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, mime_type: &str, filename: &str) -> Result<(), PolicyError> {
+        let matches = |pattern: &String| type_matches(pattern, mime_type, filename);
+
+        if self.blocked.iter().any(matches) {
+            return Err(PolicyError::Blocked(mime_type.to_string()));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(matches) {
+            return Err(PolicyError::NotAllowed(mime_type.to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn type_matches(pattern: &str, mime_type: &str, filename: &str) -> bool {
+    let mime_type = mime_type.to_ascii_lowercase();
+
+    if pattern.starts_with('.') {
+        filename.to_ascii_lowercase().ends_with(pattern)
+    } else if let Some(top) = pattern.strip_suffix("/*") {
+        mime_type.split('/').next() == Some(top)
+    } else {
+        pattern == "*" || pattern == mime_type
+    }
+}
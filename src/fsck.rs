@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::delete_file;
+use crate::models::File;
+
+/// Files without a row are left alone until they are this old, as uploads and
+/// thumbnails are written before the row that refers to them.
+pub const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// What to fix while checking.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsckOptions {
+    /// Re-hash every blob that has a recorded SHA-256. Slow on large instances.
+    pub verify_hashes: bool,
+    /// Delete rows whose blob is missing.
+    pub remove_dangling_rows: bool,
+    /// Move blobs no row refers to into the quarantine directory.
+    pub quarantine_orphans: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// The row's blob doesn't exist.
+    Missing,
+    SizeMismatch,
+    HashMismatch,
+    Unreadable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckIssue {
+    pub kind: IssueKind,
+    pub file_hash: String,
+    pub file_path: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    pub checked: usize,
+    /// Rows uploaded before checksums were recorded, only their size was checked.
+    pub unhashed: usize,
+    pub issues: Vec<FsckIssue>,
+    /// Blobs in the uploads directory that no row refers to, see [`ORPHAN_GRACE_PERIOD`].
+    pub orphans: Vec<String>,
+    pub removed_rows: usize,
+    pub quarantined_orphans: usize,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty() && self.orphans.is_empty()
+    }
+}
+
+/// Checks every row of `files` against its blob and looks for blobs without a row.
+///
+/// Repairs only touch missing blobs and orphans. Corrupt blobs are reported and
+/// left alone, as the row is the only record of what they should contain.
+pub fn check(
+    conn: &mut SqliteConnection,
+    upload_dir: &Path,
+    quarantine_dir: &Path,
+    options: FsckOptions,
+) -> Result<FsckReport, Box<dyn std::error::Error + Send + Sync>> {
+    use crate::schema::files;
+
+    if options.remove_dangling_rows {
+        ensure_readable(upload_dir)?;
+    }
+
+    let mut report = FsckReport::default();
+    let rows = files::table.order(files::id).load::<File>(conn)?;
+    let referenced: HashSet<PathBuf> = rows.iter().map(|file| PathBuf::from(&file.file_path)).collect();
+
+    for file in &rows {
+        report.checked += 1;
+        if file.sha256.is_none() {
+            report.unhashed += 1;
+        }
+
+        let Some((kind, detail)) = check_blob(file, options.verify_hashes) else {
+            continue;
+        };
+
+        if kind == IssueKind::Missing && options.remove_dangling_rows {
+            delete_file(conn, file)?;
+            report.removed_rows += 1;
+        }
+        report.issues.push(FsckIssue {
+            kind,
+            file_hash: file.file_hash.clone(),
+            file_path: file.file_path.clone(),
+            detail,
+        });
+    }
+
+    for path in orphaned_files(upload_dir, &referenced)? {
+        if options.quarantine_orphans {
+            let orphan_dir = quarantine_dir.join("orphans");
+            fs::create_dir_all(&orphan_dir)?;
+            fs::rename(&path, orphan_dir.join(path.file_name().unwrap_or_default()))?;
+            report.quarantined_orphans += 1;
+        }
+        report.orphans.push(path.to_string_lossy().into_owned());
+    }
+
+    Ok(report)
+}
+
+fn check_blob(file: &File, verify_hash: bool) -> Option<(IssueKind, String)> {
+    let metadata = match fs::metadata(&file.file_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Some((IssueKind::Missing, "blob does not exist".to_string()));
+        }
+        Err(e) => return Some((IssueKind::Unreadable, e.to_string())),
+    };
+
+    if metadata.len() != file.size as u64 {
+        return Some((
+            IssueKind::SizeMismatch,
+            format!("expected {} bytes, found {}", file.size, metadata.len()),
+        ));
+    }
+
+    let expected = file.sha256.as_deref().filter(|_| verify_hash)?;
+    match hash_file(Path::new(&file.file_path)) {
+        Ok(actual) if actual.eq_ignore_ascii_case(expected) => None,
+        Ok(actual) => Some((IssueKind::HashMismatch, format!("expected sha256 {}, found {}", expected, actual))),
+        Err(e) => Some((IssueKind::Unreadable, e.to_string())),
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Files directly in `dir` that aren't in `referenced` and are older than
+/// [`ORPHAN_GRACE_PERIOD`], sorted by path.
+fn orphaned_files(dir: &Path, referenced: &HashSet<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let now = SystemTime::now();
+    let mut orphans = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_file() && !referenced.contains(&path) && !is_recent(&entry.metadata()?, now) {
+            orphans.push(path);
+        }
+    }
+    orphans.sort();
+    Ok(orphans)
+}
+
+// Files modified in the future, or whose age can't be told, count as recent
+fn is_recent(metadata: &fs::Metadata, now: SystemTime) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok())
+        .is_none_or(|age| age < ORPHAN_GRACE_PERIOD)
+}
+
+/// What `collect_garbage` may remove.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcOptions {
+    /// Only report what would be removed.
+    pub dry_run: bool,
+    /// Also delete rows whose blob is missing. Off by default, as a missing blob
+    /// may just mean the uploads directory isn't mounted.
+    pub remove_missing: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// Rows whose blob was missing.
+    pub removed_rows: Vec<String>,
+    /// Blobs and thumbnails no row refers to.
+    pub removed_files: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+/// Deletes blobs and thumbnails that no row refers to and, with
+/// `remove_missing`, rows whose blob is gone.
+///
+/// Files newer than [`ORPHAN_GRACE_PERIOD`] are kept, they may belong to an
+/// upload that hasn't written its row yet. Nothing is touched when the uploads
+/// directory can't be read, as every row would look dangling.
+///
+/// With `dry_run` nothing is changed and the report lists what would be removed.
+pub fn collect_garbage(
+    conn: &mut SqliteConnection,
+    upload_dir: &Path,
+    thumbnail_dir: &Path,
+    options: GcOptions,
+) -> Result<GcReport, Box<dyn std::error::Error + Send + Sync>> {
+    use crate::schema::{files, thumbnails};
+
+    ensure_readable(upload_dir)?;
+    let mut report = GcReport::default();
+
+    if options.remove_missing {
+        for file in files::table.order(files::id).load::<File>(conn)? {
+            if Path::new(&file.file_path).exists() {
+                continue;
+            }
+            if !options.dry_run {
+                delete_file(conn, &file)?;
+            }
+            report.removed_rows.push(file.file_hash);
+        }
+    }
+
+    let blobs: HashSet<PathBuf> = files::table
+        .select(files::file_path)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let thumbs: HashSet<PathBuf> = thumbnails::table
+        .select(thumbnails::file_path)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+
+    let orphans = orphaned_files(upload_dir, &blobs)?
+        .into_iter()
+        .chain(orphaned_files(thumbnail_dir, &thumbs)?);
+    for path in orphans {
+        report.freed_bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if !options.dry_run {
+            fs::remove_file(&path)?;
+        }
+        report.removed_files.push(path.to_string_lossy().into_owned());
+    }
+
+    Ok(report)
+}
+
+// A missing or unreadable uploads directory makes every row look dangling
+fn ensure_readable(upload_dir: &Path) -> io::Result<()> {
+    fs::read_dir(upload_dir).map(drop).map_err(|e| {
+        io::Error::new(e.kind(), format!("uploads directory {} can't be read: {}", upload_dir.display(), e))
+    })
+}
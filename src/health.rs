@@ -0,0 +1,107 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+use rocket::data::ByteUnit;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::MIGRATIONS;
+
+/// Thresholds for `/readyz`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HealthConfig {
+    /// Free space the uploads directory needs for the server to take traffic.
+    pub min_free_space: ByteUnit,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            min_free_space: ByteUnit::Megabyte(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What failed, or a measurement worth showing when it passed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn from_result(name: &'static str, result: Result<Option<String>, String>) -> Check {
+        match result {
+            Ok(detail) => Check { name, ok: true, detail },
+            Err(detail) => Check { name, ok: false, detail: Some(detail) },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+/// Runs every readiness check. Blocks on the database and filesystem.
+pub fn check_readiness(config: &Config) -> Readiness {
+    let upload_dir = config.upload_dir();
+    let checks = vec![
+        Check::from_result("database", check_database(&config.database_url)),
+        Check::from_result("migrations", check_migrations(&config.database_url)),
+        Check::from_result("uploads_writable", check_writable(&upload_dir)),
+        Check::from_result("free_space", check_free_space(&upload_dir, config.health.min_free_space)),
+    ];
+
+    Readiness {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+fn connect(database_url: &str) -> Result<SqliteConnection, String> {
+    SqliteConnection::establish(database_url).map_err(|e| e.to_string())
+}
+
+fn check_database(database_url: &str) -> Result<Option<String>, String> {
+    let mut connection = connect(database_url)?;
+    diesel::sql_query("SELECT 1").execute(&mut connection).map_err(|e| e.to_string())?;
+    Ok(None)
+}
+
+fn check_migrations(database_url: &str) -> Result<Option<String>, String> {
+    let mut connection = connect(database_url)?;
+    let pending = connection.pending_migrations(MIGRATIONS).map_err(|e| e.to_string())?;
+    if !pending.is_empty() {
+        return Err(format!("{} pending", pending.len()));
+    }
+    Ok(None)
+}
+
+/// Creates and removes a probe file, which catches read-only mounts and full inodes too.
+fn check_writable(dir: &Path) -> Result<Option<String>, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut probe = tempfile::Builder::new()
+        .prefix(".readyz-")
+        .tempfile_in(dir)
+        .map_err(|e| e.to_string())?;
+    probe.write_all(b"ok").and_then(|_| probe.flush()).map_err(|e| e.to_string())?;
+    Ok(None)
+}
+
+fn check_free_space(dir: &Path, required: ByteUnit) -> Result<Option<String>, String> {
+    let available = ByteUnit::from(fs4::available_space(dir).map_err(|e| e.to_string())?);
+    if available < required {
+        return Err(format!("{:.1} available, {:.1} required", available, required));
+    }
+    Ok(Some(format!("{:.1} available", available)))
+}
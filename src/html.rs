@@ -0,0 +1,39 @@
+/// Escapes text for use in HTML element content and quoted attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Pages rendered from user content may only use their own inline styles and images
+pub const PAGE_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src 'self' data:";
+
+const BASE_STYLE: &str = "body{margin:0;font-family:system-ui,-apple-system,sans-serif;color:#1f2937;background:#fff}\
+header{display:flex;gap:1rem;align-items:center;padding:.75rem 1rem;border-bottom:1px solid #e5e7eb}\
+header h1{font-size:1rem;margin:0;flex:1;overflow:hidden;text-overflow:ellipsis;white-space:nowrap}\
+header a{color:#2563eb;text-decoration:none}main{padding:1rem}";
+
+/// Wraps `body` in a minimal standalone HTML document.
+///
+/// `title` is escaped, `head` and `body` must already be safe HTML.
+pub fn page(title: &str, head: &str, body: &str) -> String {
+    format!(
+        "<!doctype html><html lang=\"en\"><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+<meta name=\"robots\" content=\"noindex\"><title>{}</title><style>{}</style>{}</head>\
+<body>{}</body></html>",
+        escape(title),
+        BASE_STYLE,
+        head,
+        body
+    )
+}
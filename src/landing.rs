@@ -0,0 +1,78 @@
+use rocket::data::ByteUnit;
+
+use crate::html::escape;
+
+const LANDING_STYLE: &str = "main{max-width:32rem;margin:2rem auto;text-align:center}\
+main img{max-width:100%;max-height:20rem;border-radius:.5rem;background:#f3f4f6}\
+main h2{font-size:1.25rem;margin:1rem 0 .25rem;word-break:break-all}main p{color:#6b7280;margin:0 0 1.5rem}\
+main a.button{display:inline-block;padding:.6rem 1.5rem;border-radius:.375rem;background:#2563eb;color:#fff;text-decoration:none}\
+main a.secondary{margin-left:1rem;color:#2563eb}";
+
+/// What the `/f/<hash>` page shows. Links are absolute so chat apps can follow them.
+pub struct Landing<'a> {
+    pub file_name: &'a str,
+    pub size: u64,
+    pub mime_type: &'a str,
+    pub page_url: &'a str,
+    pub download_url: &'a str,
+    /// Opens the file in the browser, for types that are safe to show inline.
+    pub view_url: Option<&'a str>,
+    pub thumbnail_url: Option<&'a str>,
+    /// Photo metadata (EXIF, GPS) was stripped from the stored copy.
+    pub sanitized: bool,
+}
+
+/// Renders the landing page for a shared link, with OpenGraph and Twitter card tags.
+pub fn render_landing_page(landing: &Landing) -> String {
+    let description = format!("{:.1} · {}", ByteUnit::from(landing.size), landing.mime_type);
+
+    let mut meta = vec![
+        ("og:type", "website"),
+        ("og:site_name", "Netdrop"),
+        ("og:title", landing.file_name),
+        ("og:description", &description),
+        ("og:url", landing.page_url),
+        ("twitter:title", landing.file_name),
+        ("twitter:description", &description),
+    ];
+    match landing.thumbnail_url {
+        Some(thumbnail_url) => meta.extend([
+            ("og:image", thumbnail_url),
+            ("twitter:card", "summary_large_image"),
+            ("twitter:image", thumbnail_url),
+        ]),
+        None => meta.push(("twitter:card", "summary")),
+    }
+
+    let mut head = format!("<style>{}</style>", LANDING_STYLE);
+    for (name, content) in meta {
+        // OpenGraph uses `property`, Twitter cards use `name`
+        let attribute = if name.starts_with("og:") { "property" } else { "name" };
+        head.push_str(&format!("<meta {}=\"{}\" content=\"{}\">", attribute, name, escape(content)));
+    }
+
+    let preview = match landing.thumbnail_url {
+        Some(thumbnail_url) => format!("<img src=\"{}\" alt=\"\">", escape(thumbnail_url)),
+        None => String::new(),
+    };
+    let view_link = match landing.view_url {
+        Some(view_url) => format!("<a class=\"secondary\" href=\"{}\">Open in browser</a>", escape(view_url)),
+        None => String::new(),
+    };
+    let details = if landing.sanitized {
+        format!("{} · metadata removed", description)
+    } else {
+        description.clone()
+    };
+    let body = format!(
+        "<header><h1>Netdrop</h1><a href=\"/\">Upload a file</a></header>\
+<main>{}<h2>{}</h2><p>{}</p><a class=\"button\" href=\"{}\">Download</a>{}</main>",
+        preview,
+        escape(landing.file_name),
+        escape(&details),
+        escape(landing.download_url),
+        view_link
+    );
+
+    crate::html::page(landing.file_name, &head, &body)
+}
@@ -1,48 +1,157 @@
+#[macro_use] extern crate rocket;
+
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod checksum;
+#[cfg(feature = "cli")]
+pub mod client;
+pub mod config;
+pub mod cors;
+pub mod filetype;
+pub mod fsck;
+pub mod health;
+pub mod html;
+pub mod landing;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
 pub mod models;
+pub mod paste;
+pub mod preview;
+pub mod public_id;
+pub mod range;
+pub mod ratelimit;
+pub mod render;
+pub mod scan;
 pub mod schema;
+pub mod server;
+pub mod spool;
+pub mod stats;
+pub mod throttle;
+pub mod thumbnail;
 
 #[cfg(test)]
 mod tests;
 
 use diesel::prelude::*;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use dotenvy::dotenv;
-use std::env;
 
 use crate::models::{NewFile, File};
 
 // Embed migrations at compile time
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/");
 
-pub fn establish_connection() -> SqliteConnection {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+pub fn establish_connection(database_url: &str) -> SqliteConnection {
+    let mut connection = SqliteConnection::establish(database_url)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    // Wait for writes on other connections, such as the thumbnail worker's, instead of failing at once
+    diesel::sql_query("PRAGMA busy_timeout = 5000")
+        .execute(&mut connection)
+        .unwrap_or_else(|e| panic!("Error configuring {}: {}", database_url, e));
+    connection
 }
 
-pub fn run_migrations() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let mut connection = establish_connection();
+pub fn run_migrations(database_url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut connection = establish_connection(database_url);
     connection.run_pending_migrations(MIGRATIONS)?;
     Ok(())
 }
 
-pub fn create_file(conn: &mut SqliteConnection, new_file: NewFile<'_>) -> File {
+pub fn create_file(conn: &mut SqliteConnection, new_file: NewFile<'_>) -> QueryResult<File> {
+    use crate::schema::{file_links, files};
+
+    let _timer = metrics::global().db_timer("create_file");
+    conn.transaction(|conn| {
+        let file = diesel::insert_into(files::table)
+            .values(&new_file)
+            .returning(File::as_returning())
+            .get_result(conn)?;
+        // Claims the public id, so no slug can take it
+        diesel::insert_into(file_links::table)
+            .values((file_links::link.eq(&file.public_id), file_links::file_id.eq(file.id)))
+            .execute(conn)?;
+        Ok(file)
+    })
+}
+
+/// Lists files, newest first.
+pub fn list_files(conn: &mut SqliteConnection, limit: i64) -> QueryResult<Vec<File>> {
     use crate::schema::files;
 
-    diesel::insert_into(files::table)
-        .values(&new_file)
-        .returning(File::as_returning())
-        .get_result(conn)
-        .expect("Error saving file")
+    let _timer = metrics::global().db_timer("list_files");
+    files::table
+        .order(files::id.desc())
+        .limit(limit)
+        .load::<File>(conn)
+}
+
+/// Deletes a file's row and thumbnails, then removes what they stored on disk.
+pub fn delete_file(conn: &mut SqliteConnection, file: &File) -> QueryResult<()> {
+    use crate::schema::{daily_downloads, file_links, files, thumbnails};
+
+    let timer = metrics::global().db_timer("delete_file");
+    let thumbnail_paths = conn.transaction(|conn| {
+        // SQLite only cascades when foreign keys are enabled, which they aren't by default
+        let paths = thumbnails::table
+            .filter(thumbnails::file_id.eq(file.id))
+            .select(thumbnails::file_path)
+            .load::<String>(conn)?;
+        diesel::delete(thumbnails::table.filter(thumbnails::file_id.eq(file.id))).execute(conn)?;
+        diesel::delete(daily_downloads::table.filter(daily_downloads::file_id.eq(file.id))).execute(conn)?;
+        diesel::delete(file_links::table.filter(file_links::file_id.eq(file.id))).execute(conn)?;
+        diesel::delete(files::table.find(file.id)).execute(conn)?;
+        Ok::<_, diesel::result::Error>(paths)
+    })?;
+    drop(timer);
+
+    // The rows are gone, so a blob left behind is only an orphan for gc to collect
+    for path in thumbnail_paths.iter().chain([&file.file_path]) {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
 }
 
 pub fn get_file_by_hash(conn: &mut SqliteConnection, hash: &str) -> Option<File> {
     use crate::schema::files::dsl::*;
 
+    let _timer = metrics::global().db_timer("get_file_by_hash");
     files
         .filter(file_hash.eq(hash))
         .first::<File>(conn)
         .ok()
 }
+
+/// Finds the file a link points to, by its public id or its slug.
+pub fn get_file_by_public_id(conn: &mut SqliteConnection, id: &str) -> Option<File> {
+    use crate::schema::files;
+
+    let _timer = metrics::global().db_timer("get_file_by_public_id");
+    files::table
+        .filter(files::public_id.eq(id).or(files::slug.eq(id)))
+        .first::<File>(conn)
+        .ok()
+}
+
+/// Finds a file uploaded before random public ids by the storage hash its old links carry.
+pub fn get_file_by_legacy_id(conn: &mut SqliteConnection, id: &str) -> Option<File> {
+    use crate::schema::files;
+
+    let _timer = metrics::global().db_timer("get_file_by_legacy_id");
+    files::table
+        .filter(files::legacy_id.eq(id))
+        .first::<File>(conn)
+        .ok()
+}
+
+/// Counts stored files and their total size in bytes.
+pub fn file_stats(conn: &mut SqliteConnection) -> QueryResult<(i64, i64)> {
+    use crate::schema::files;
+
+    let _timer = metrics::global().db_timer("file_stats");
+    let (count, total) = files::table
+        // Diesel types a sum of BIGINTs as NUMERIC, SQLite sums integers as integers
+        .select((diesel::dsl::count_star(), diesel::dsl::sql::<diesel::sql_types::Nullable<diesel::sql_types::BigInt>>("SUM(size)")))
+        .first::<(i64, Option<i64>)>(conn)?;
+    Ok((count, total.unwrap_or(0)))
+}
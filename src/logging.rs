@@ -0,0 +1,154 @@
+use std::time::Instant;
+
+use rand::RngCore;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Data, Response};
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+use crate::ratelimit::request_client_ip;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Readable lines for a terminal.
+    #[default]
+    Human,
+    /// One JSON object per line for log collectors.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Which events to keep, in `RUST_LOG` syntax such as `info,netdrop=debug`.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::default(),
+            // Requests are logged by `RequestTracing`, Rocket's own request lines would repeat them
+            filter: "info,rocket=warn,rocket::launch=info".to_string(),
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn env_filter(&self) -> Result<EnvFilter, String> {
+        EnvFilter::builder()
+            .parse(&self.filter)
+            .map_err(|e| format!("'{}' is not a valid filter: {}", self.filter, e))
+    }
+}
+
+/// Installs the global subscriber, which also receives Rocket's `log` records.
+///
+/// Only the first call has an effect, so tests can build several servers.
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(config.env_filter()?)
+        .with_writer(std::io::stderr);
+
+    // This only fails when an earlier call already installed a subscriber
+    let _ = match config.format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).with_span_list(false).try_init(),
+    };
+    Ok(())
+}
+
+/// Identifies a request in logs, taken from `X-Request-Id` or generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Keeps a caller's id if it is short and plain, so it can't inject into log lines.
+    pub fn from_header(value: Option<&str>) -> RequestId {
+        match value.map(str::trim) {
+            Some(id)
+                if !id.is_empty()
+                    && id.len() <= 128
+                    && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) =>
+            {
+                RequestId(id.to_string())
+            }
+            _ => RequestId::generate(),
+        }
+    }
+
+    pub fn generate() -> RequestId {
+        let mut bytes = [0u8; 16];
+        rand::rng().fill_bytes(&mut bytes);
+        RequestId(hex::encode(bytes))
+    }
+}
+
+/// The span a request's events are recorded in, see [`RequestTracing`].
+#[derive(Clone)]
+pub struct RequestSpan(pub Span);
+
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestSpan {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(req.local_cache(|| RequestSpan(Span::none())).clone())
+    }
+}
+
+/// Opens a span per request with its id, method, path and client, echoes the id
+/// in the response and logs how the request ended.
+pub struct RequestTracing;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let request_id = RequestId::from_header(req.headers().get_one(REQUEST_ID_HEADER));
+
+        let client_ip = request_client_ip(req);
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id.0,
+            method = %req.method(),
+            uri = %req.uri(),
+            client_ip = %client_ip,
+        );
+        req.local_cache(|| RequestSpan(span));
+        req.local_cache(|| request_id);
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let request_id = req.local_cache(RequestId::generate);
+        res.set_header(Header::new(REQUEST_ID_HEADER, request_id.0.clone()));
+
+        let span = &req.local_cache(|| RequestSpan(Span::none())).0;
+        let elapsed_ms = req.local_cache(|| RequestStart(Instant::now())).0.elapsed().as_secs_f64() * 1000.0;
+        let status = res.status().code;
+
+        if status >= 500 {
+            tracing::error!(parent: span, status, elapsed_ms, "request failed");
+        } else {
+            tracing::info!(parent: span, status, elapsed_ms, "request finished");
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+
+use rocket::serde::{Deserialize, Serialize};
+
+/// When uploaded photos have their metadata removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "kebab-case")]
+pub enum MetadataPolicy {
+    /// Never strip, even if the uploader asks.
+    Never,
+    /// Strip only when the uploader asks.
+    #[default]
+    OptIn,
+    /// Strip unless the uploader opts out.
+    OptOut,
+    /// Always strip.
+    Always,
+}
+
+impl MetadataPolicy {
+    pub fn parse(value: &str) -> Option<MetadataPolicy> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "never" => Some(MetadataPolicy::Never),
+            "opt-in" => Some(MetadataPolicy::OptIn),
+            "opt-out" => Some(MetadataPolicy::OptOut),
+            "always" => Some(MetadataPolicy::Always),
+            _ => None,
+        }
+    }
+
+    /// Whether to strip an upload, given the uploader's choice if they made one.
+    pub fn should_strip(&self, requested: Option<bool>) -> bool {
+        match self {
+            MetadataPolicy::Never => false,
+            MetadataPolicy::OptIn => requested.unwrap_or(false),
+            MetadataPolicy::OptOut => requested.unwrap_or(true),
+            MetadataPolicy::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MetadataConfig {
+    /// One of `never`, `opt-in`, `opt-out` or `always`.
+    pub strip: MetadataPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataError(pub &'static str);
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed image: {}", self.0)
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// Whether metadata can be stripped from files of this type.
+pub fn is_supported(mime_type: &str) -> bool {
+    matches!(mime_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+/// Removes EXIF, XMP, IPTC and text metadata from an image without re-encoding it.
+///
+/// Returns `Ok(None)` for unsupported formats. Colour profiles are kept so the
+/// pixels look the same, and a JPEG's orientation is kept in a minimal EXIF block
+/// so photos aren't shown on their side.
+pub fn strip_metadata(data: &[u8], mime_type: &str) -> Result<Option<Vec<u8>>, MetadataError> {
+    match mime_type {
+        "image/jpeg" => strip_jpeg(data).map(Some),
+        "image/png" => strip_png(data).map(Some),
+        "image/webp" => strip_webp(data).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>, MetadataError> {
+    const APP0: u8 = 0xE0;
+    const APP1: u8 = 0xE1;
+    const APP2: u8 = 0xE2;
+    const APP14: u8 = 0xEE;
+    const SOS: u8 = 0xDA;
+    const EOI: u8 = 0xD9;
+
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(MetadataError("missing JPEG start of image"));
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    let mut kept_orientation = false;
+
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if data.get(pos) != Some(&0xFF) {
+            return Err(MetadataError("expected JPEG marker"));
+        }
+        let marker = *data.get(pos + 1).ok_or(MetadataError("truncated JPEG"))?;
+
+        if marker == EOI {
+            // Anything after the image, such as MPF preview images, is dropped
+            output.extend_from_slice(&[0xFF, EOI]);
+            return Ok(output);
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD7) {
+            // Standalone markers carry no length or payload
+            output.extend_from_slice(&data[pos..pos + 2]);
+            pos += 2;
+            continue;
+        }
+
+        let length = data
+            .get(pos + 2..pos + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or(MetadataError("truncated JPEG segment"))?;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(MetadataError("JPEG segment overruns the file"));
+        }
+        let payload = &data[pos + 4..end];
+
+        // Only the orientation survives, so the photo is still shown the right way up
+        if marker == APP1
+            && !kept_orientation
+            && let Some(orientation) = exif_orientation(payload).filter(|&orientation| orientation != 1)
+        {
+            output.extend_from_slice(&orientation_segment(orientation));
+            kept_orientation = true;
+        }
+
+        let keep = match marker {
+            // JFIF, ICC profiles and Adobe colour transforms affect how pixels are decoded
+            APP0 | APP14 => true,
+            APP2 => payload.starts_with(b"ICC_PROFILE\0"),
+            // EXIF, XMP, IPTC and other application data, and comments
+            0xE1..=0xEF | 0xFE => false,
+            _ => true,
+        };
+        if keep {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+
+        if marker == SOS {
+            // Copy the entropy-coded scan up to the next marker that isn't a stuffed byte or restart
+            let scan_start = pos;
+            loop {
+                match (data.get(pos), data.get(pos + 1)) {
+                    (Some(0xFF), Some(0x00)) | (Some(0xFF), Some(0xD0..=0xD7)) => pos += 2,
+                    (Some(0xFF), Some(_)) => break,
+                    (Some(_), _) => pos += 1,
+                    (None, _) => return Err(MetadataError("truncated JPEG scan")),
+                }
+            }
+            output.extend_from_slice(&data[scan_start..pos]);
+        }
+    }
+}
+
+const EXIF_ORIENTATION: u16 = 0x0112;
+const EXIF_SHORT: u16 = 3;
+
+/// Reads the Orientation tag, 1 to 8, from the first IFD of an EXIF APP1 payload.
+pub fn exif_orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(b"Exif\0\0")?;
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read = |at: usize, len: usize| {
+        let bytes = tiff.get(at..at.checked_add(len)?)?;
+        let fold = |value: u32, byte: &u8| value << 8 | *byte as u32;
+        Some(if little_endian { bytes.iter().rev().fold(0, fold) } else { bytes.iter().fold(0, fold) })
+    };
+
+    let ifd = read(4, 4)? as usize;
+    let entries = read(ifd, 2)? as usize;
+    (0..entries)
+        .find_map(|index| {
+            let entry = ifd + 2 + index * 12;
+            (read(entry, 2)? == EXIF_ORIENTATION as u32 && read(entry + 2, 2)? == EXIF_SHORT as u32)
+                .then(|| read(entry + 8, 2))
+                .flatten()
+        })
+        .map(|orientation| orientation as u16)
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// An APP1 segment whose EXIF holds nothing but the orientation.
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut segment = vec![0xFF, 0xE1, 0, 34];
+    segment.extend_from_slice(b"Exif\0\0");
+    // Big-endian TIFF header with the first IFD right after it
+    segment.extend_from_slice(b"MM\0\x2A\0\0\0\x08");
+    segment.extend_from_slice(&1u16.to_be_bytes());
+    segment.extend_from_slice(&EXIF_ORIENTATION.to_be_bytes());
+    segment.extend_from_slice(&EXIF_SHORT.to_be_bytes());
+    segment.extend_from_slice(&1u32.to_be_bytes());
+    segment.extend_from_slice(&orientation.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    // No further IFDs
+    segment.extend_from_slice(&0u32.to_be_bytes());
+    segment
+}
+
+fn strip_png(data: &[u8]) -> Result<Vec<u8>, MetadataError> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    // EXIF, text and timestamp chunks. Unknown ancillary chunks are kept, they may be animation data
+    const METADATA_CHUNKS: &[&[u8; 4]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+    if !data.starts_with(SIGNATURE) {
+        return Err(MetadataError("missing PNG signature"));
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).ok_or(MetadataError("truncated PNG chunk"))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = &header[4..8];
+        let end = pos
+            .checked_add(12 + length)
+            .filter(|&end| end <= data.len())
+            .ok_or(MetadataError("PNG chunk overruns the file"))?;
+
+        if !METADATA_CHUNKS.iter().any(|chunk| &chunk[..] == kind) {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(output)
+}
+
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>, MetadataError> {
+    const VP8X_EXIF: u8 = 0x08;
+    const VP8X_XMP: u8 = 0x04;
+
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(MetadataError("missing WebP RIFF header"));
+    }
+
+    let riff_end = (u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize)
+        .checked_add(8)
+        .filter(|&end| end <= data.len())
+        .ok_or(MetadataError("RIFF size overruns the file"))?;
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+    let mut pos = 12;
+
+    while pos + 8 <= riff_end {
+        let kind = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        // Chunks are padded to an even size
+        let end = pos
+            .checked_add(8 + size + (size & 1))
+            .filter(|&end| end <= riff_end)
+            .ok_or(MetadataError("WebP chunk overruns the file"))?;
+
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if size >= 1 => {
+                let flags_at = output.len() + 8;
+                output.extend_from_slice(&data[pos..end]);
+                output[flags_at] &= !(VP8X_EXIF | VP8X_XMP);
+            }
+            _ => output.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(output)
+}
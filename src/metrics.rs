@@ -0,0 +1,252 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::LazyLock;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use rocket::serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::stats::CountedDownload;
+
+/// Who may read `/metrics`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Bearer token scrapers must send, anyone can scrape when unset.
+    pub token: Option<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: true,
+            token: None,
+        }
+    }
+}
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    RateLimited,
+    TooLarge,
+    FileType,
+    ChecksumMismatch,
+    Unauthorized,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::RateLimited => "rate_limited",
+            RejectReason::TooLarge => "too_large",
+            RejectReason::FileType => "file_type",
+            RejectReason::ChecksumMismatch => "checksum_mismatch",
+            RejectReason::Unauthorized => "unauthorized",
+        }
+    }
+}
+
+/// Everything exported on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// By `status`: `success`, `rejected` or `checksum_mismatch`.
+    pub uploads: IntCounterVec,
+    /// By `route` and HTTP `status` code.
+    pub downloads: IntCounterVec,
+    pub bytes_received: IntCounter,
+    pub bytes_sent: IntCounter,
+    pub upload_duration: Histogram,
+    /// From the response being ready until its body has been sent.
+    pub download_duration: Histogram,
+    /// By `direction`: `upload` or `download`.
+    pub active_transfers: IntGaugeVec,
+    pub stored_files: IntGauge,
+    pub stored_bytes: IntGauge,
+    /// By `query`, the function that ran it.
+    pub db_query_duration: HistogramVec,
+    /// By `reason`, see [`RejectReason`].
+    pub rejected_requests: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("netdrop".to_string()), None).expect("valid prefix");
+        // Transfers run from milliseconds to hours
+        let transfer_buckets = exponential_buckets(0.01, 4.0, 10).expect("valid buckets");
+
+        let metrics = Metrics {
+            uploads: IntCounterVec::new(Opts::new("uploads_total", "Uploads by outcome"), &["status"]).unwrap(),
+            downloads: IntCounterVec::new(Opts::new("downloads_total", "Downloads by route and status"), &["route", "status"])
+                .unwrap(),
+            bytes_received: IntCounter::new("received_bytes_total", "Bytes of upload bodies received").unwrap(),
+            bytes_sent: IntCounter::new("sent_bytes_total", "Bytes of file content sent").unwrap(),
+            upload_duration: Histogram::with_opts(
+                HistogramOpts::new("upload_duration_seconds", "Time to receive and store an upload")
+                    .buckets(transfer_buckets.clone()),
+            )
+            .unwrap(),
+            download_duration: Histogram::with_opts(
+                HistogramOpts::new("download_duration_seconds", "Time to send a file").buckets(transfer_buckets),
+            )
+            .unwrap(),
+            active_transfers: IntGaugeVec::new(Opts::new("active_transfers", "Transfers in progress"), &["direction"])
+                .unwrap(),
+            stored_files: IntGauge::new("stored_files", "Files in the database").unwrap(),
+            stored_bytes: IntGauge::new("stored_bytes", "Total size of stored files").unwrap(),
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new("db_query_duration_seconds", "Database query latency")
+                    .buckets(exponential_buckets(0.0001, 4.0, 8).expect("valid buckets")),
+                &["query"],
+            )
+            .unwrap(),
+            rejected_requests: IntCounterVec::new(Opts::new("rejected_requests_total", "Requests refused, by reason"), &["reason"])
+                .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 11] = [
+            Box::new(metrics.uploads.clone()),
+            Box::new(metrics.downloads.clone()),
+            Box::new(metrics.bytes_received.clone()),
+            Box::new(metrics.bytes_sent.clone()),
+            Box::new(metrics.upload_duration.clone()),
+            Box::new(metrics.download_duration.clone()),
+            Box::new(metrics.active_transfers.clone()),
+            Box::new(metrics.stored_files.clone()),
+            Box::new(metrics.stored_bytes.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+            Box::new(metrics.rejected_requests.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric names are unique");
+        }
+        metrics
+    }
+
+    /// Times a database query until the returned timer is dropped.
+    pub fn db_timer(&self, query: &str) -> HistogramTimer {
+        self.db_query_duration.with_label_values(&[query]).start_timer()
+    }
+
+    pub fn reject(&self, reason: RejectReason) {
+        self.rejected_requests.with_label_values(&[reason.as_str()]).inc();
+    }
+
+    pub fn record_download(&self, route: &str, status: u16) {
+        self.downloads.with_label_values(&[route, &status.to_string()]).inc();
+    }
+
+    /// Counts an upload as active until the guard is dropped.
+    pub fn upload_started(&'static self) -> ActiveTransfer {
+        ActiveTransfer::new(self.active_transfers.with_label_values(&["upload"]))
+    }
+
+    /// The registry in Prometheus' text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding can't fail");
+        String::from_utf8(buffer).expect("text format is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// The process-wide metrics, shared by handlers and the database functions.
+pub fn global() -> &'static Metrics {
+    &METRICS
+}
+
+/// Keeps `active_transfers` raised while alive.
+pub struct ActiveTransfer {
+    gauge: IntGauge,
+    started: Instant,
+}
+
+impl ActiveTransfer {
+    fn new(gauge: IntGauge) -> ActiveTransfer {
+        gauge.inc();
+        ActiveTransfer {
+            gauge,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn elapsed_secs(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+}
+
+impl Drop for ActiveTransfer {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// Counts bytes sent through `inner` and times the download until the body is dropped.
+pub struct MeteredReader<R> {
+    inner: R,
+    transfer: ActiveTransfer,
+    sent: u64,
+    download: Option<CountedDownload>,
+}
+
+impl<R> MeteredReader<R> {
+    pub fn new(inner: R) -> MeteredReader<R> {
+        MeteredReader {
+            inner,
+            transfer: ActiveTransfer::new(global().active_transfers.with_label_values(&["download"])),
+            sent: 0,
+            download: None,
+        }
+    }
+
+    /// Also records `download` in the file's statistics with the bytes sent, once the body is dropped.
+    pub fn count_download(&mut self, download: CountedDownload) {
+        self.download = Some(download);
+    }
+}
+
+impl<R> Drop for MeteredReader<R> {
+    fn drop(&mut self) {
+        global().download_duration.observe(self.transfer.elapsed_secs());
+        if let Some(download) = self.download.take() {
+            download.finish(self.sent);
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for MeteredReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        global().bytes_sent.inc_by(read);
+        self.sent += read;
+        result
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for MeteredReader<R> {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
@@ -1,4 +1,4 @@
-use super::schema::files;
+use super::schema::{api_tokens, audit_events, files, thumbnails, users};
 use diesel::prelude::*;
 
 #[derive(Queryable, Selectable)]
@@ -9,9 +9,27 @@ pub struct File {
     pub file_hash: String,
     pub file_name: String,
     pub file_path: String,
-    pub size: i32,
+    pub size: i64,
     pub private: bool,
     pub created_at: chrono::NaiveDateTime,
+    pub mime_type: String,
+    pub scan_status: String,
+    pub scan_verdict: Option<String>,
+    pub scanned_at: Option<chrono::NaiveDateTime>,
+    pub sanitized: bool,
+    pub language: Option<String>,
+    pub sha256: Option<String>,
+    /// The user whose token uploaded the file, unset for anonymous uploads.
+    pub owner_id: Option<i32>,
+    pub download_count: i32,
+    pub bytes_served: i64,
+    pub last_downloaded_at: Option<chrono::NaiveDateTime>,
+    /// Identifies the file in links, unlike `file_hash` which names it on disk.
+    pub public_id: String,
+    /// Vanity name the owner chose, which links may use instead of `public_id`.
+    pub slug: Option<String>,
+    /// The storage hash that was the id of files uploaded before random ids, old links redirect.
+    pub legacy_id: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -20,6 +38,101 @@ pub struct NewFile<'a> {
     pub file_hash: &'a str,
     pub file_name: &'a str,
     pub file_path: &'a str,
-    pub size: i32,
+    pub size: i64,
     pub private: bool,
+    pub mime_type: &'a str,
+    pub scan_status: &'a str,
+    pub sanitized: bool,
+    pub language: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    pub owner_id: Option<i32>,
+    pub public_id: &'a str,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = thumbnails)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Thumbnail {
+    pub id: i32,
+    pub file_id: i32,
+    pub size: i32,
+    pub mime_type: String,
+    pub file_path: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = thumbnails)]
+pub struct NewThumbnail<'a> {
+    pub file_id: i32,
+    pub size: i32,
+    pub mime_type: &'a str,
+    pub file_path: &'a str,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = users)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = api_tokens)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// SHA-256 of the token, the token itself is only shown when created.
+    pub token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_tokens)]
+pub struct NewApiToken<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub token_hash: &'a str,
+}
+
+#[derive(Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = audit_events)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub event: String,
+    pub user_id: Option<i32>,
+    pub token_id: Option<i32>,
+    /// Unset for changes made with the admin CLI.
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub file_id: Option<i32>,
+    pub file_hash: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct NewAuditEntry<'a> {
+    pub created_at: chrono::NaiveDateTime,
+    pub event: &'a str,
+    pub user_id: Option<i32>,
+    pub token_id: Option<i32>,
+    pub client_ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub file_id: Option<i32>,
+    pub file_hash: Option<&'a str>,
+    pub detail: Option<&'a str>,
 }
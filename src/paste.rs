@@ -0,0 +1,93 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::html::escape;
+
+/// Largest paste accepted by `POST /api/v1/paste`.
+pub const MAX_PASTE_SIZE: u64 = 10 * 1024 * 1024;
+
+// Larger pastes are shown without highlighting to keep rendering cheap
+const MAX_HIGHLIGHT_SIZE: usize = 512 * 1024;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    let mut themes = ThemeSet::load_defaults();
+    themes.themes.remove("InspiredGitHub").expect("bundled theme")
+});
+
+const PASTE_STYLE: &str = "table{border-collapse:collapse;font:13px/1.5 ui-monospace,SFMono-Regular,Menlo,monospace;width:100%}\
+td{padding:0 .75rem;vertical-align:top}td.n{text-align:right;user-select:none;width:1%;border-right:1px solid #e5e7eb}\
+td.n a{color:#9ca3af;text-decoration:none}td.c{white-space:pre-wrap;word-break:break-all}\
+tr:target{background:#fef9c3}";
+
+/// Validates a language name given by the uploader, e.g. `rust`, `c++` or `js`.
+pub fn is_valid_language(language: &str) -> bool {
+    (1..=32).contains(&language.len())
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '#' | '.' | '_' | '-'))
+}
+
+/// Finds the syntax for a language name or extension, falling back to the filename.
+fn find_syntax(language: Option<&str>, file_name: &str) -> &'static SyntaxReference {
+    let by_language = language.and_then(|language| {
+        SYNTAXES
+            .find_syntax_by_token(language)
+            .or_else(|| SYNTAXES.find_syntax_by_token(&language.to_ascii_lowercase()))
+    });
+    let by_extension = || {
+        Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| SYNTAXES.find_syntax_by_extension(ext))
+    };
+
+    by_language
+        .or_else(by_extension)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text())
+}
+
+/// Renders text as table rows with line number anchors (`#L12`) and highlighted code.
+pub fn highlight_lines(text: &str, language: Option<&str>, file_name: &str) -> String {
+    let syntax = find_syntax(language, file_name);
+    let mut highlighter = (text.len() <= MAX_HIGHLIGHT_SIZE).then(|| HighlightLines::new(syntax, &THEME));
+
+    let mut rows = String::with_capacity(text.len() * 2);
+    for (index, line) in LinesWithEndings::from(text).enumerate() {
+        let number = index + 1;
+        let code = highlighter
+            .as_mut()
+            .and_then(|h| h.highlight_line(line, &SYNTAXES).ok())
+            .and_then(|ranges| styled_line_to_highlighted_html(&ranges, IncludeBackground::No).ok())
+            .unwrap_or_else(|| escape(line));
+
+        let _ = write!(
+            rows,
+            "<tr id=\"L{n}\"><td class=\"n\"><a href=\"#L{n}\">{n}</a></td><td class=\"c\">{code}</td></tr>",
+            n = number,
+            code = code.trim_end_matches(['\n', '\r'])
+        );
+    }
+    rows
+}
+
+/// Renders the `/p/<hash>` page for a paste.
+pub fn render_paste_page(text: &str, language: Option<&str>, file_name: &str, raw_url: &str) -> String {
+    let syntax = find_syntax(language, file_name);
+    let body = format!(
+        "<header><h1>{}</h1><span>{}</span><a href=\"{}\">Raw</a></header><main><table>{}</table></main>",
+        escape(file_name),
+        escape(&syntax.name),
+        escape(raw_url),
+        highlight_lines(text, language, file_name)
+    );
+
+    crate::html::page(file_name, &format!("<style>{}</style>", PASTE_STYLE), &body)
+}
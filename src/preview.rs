@@ -0,0 +1,147 @@
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// How a file is presented to the browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+// Content Security Policy for user content: nothing may load or run, and the
+// document gets a unique origin so it can't reach the app's cookies or storage
+pub const SANDBOX_CSP: &str =
+    "default-src 'none'; img-src 'self'; media-src 'self'; style-src 'unsafe-inline'; sandbox";
+
+// Browsers refuse to run their PDF viewer in a sandboxed document
+pub const PDF_CSP: &str = "default-src 'none'; object-src 'self'; style-src 'unsafe-inline'";
+
+const INLINE_IMAGE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/avif",
+    "image/vnd.microsoft.icon",
+    "image/x-icon",
+];
+
+// Types a browser would execute or render as a document if served inline
+const RISKY_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/xml",
+    "application/xml",
+    "text/javascript",
+    "application/javascript",
+    "text/xsl",
+    "application/x-shockwave-flash",
+];
+
+/// Returns the `Content-Type` to serve a file inline with, or `None` if it must be downloaded.
+///
+/// Textual formats are served as plain text so they are shown rather than interpreted.
+pub fn inline_content_type(mime_type: &str) -> Option<String> {
+    let mime_type = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if RISKY_TYPES.contains(&mime_type.as_str()) || mime_type.ends_with("+xml") {
+        return None;
+    }
+
+    if INLINE_IMAGE_TYPES.contains(&mime_type.as_str())
+        || mime_type == "application/pdf"
+        || mime_type.starts_with("video/")
+        || mime_type.starts_with("audio/")
+    {
+        return Some(mime_type);
+    }
+
+    if mime_type.starts_with("text/") || mime_type == "application/json" {
+        return Some("text/plain; charset=utf-8".to_string());
+    }
+
+    None
+}
+
+/// The Content Security Policy for serving a file of `content_type`.
+pub fn content_security_policy(content_type: &str) -> &'static str {
+    if content_type.starts_with("application/pdf") {
+        PDF_CSP
+    } else {
+        SANDBOX_CSP
+    }
+}
+
+/// Builds a `Content-Disposition` value that can't be broken out of by the filename.
+///
+/// Non-ASCII names are sent RFC 6266 style with an ASCII fallback.
+pub fn content_disposition(disposition: Disposition, filename: &str) -> String {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+
+    if fallback == filename {
+        return format!("{}; filename=\"{}\"", kind, fallback);
+    }
+
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect();
+
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", kind, fallback, encoded)
+}
+
+/// Interprets query flags such as `?inline=1`, `?inline=true` or a bare `?inline`.
+pub fn flag_enabled(value: Option<&str>) -> bool {
+    match value {
+        Some(value) => matches!(
+            value.to_ascii_lowercase().as_str(),
+            "" | "1" | "true" | "yes" | "on"
+        ),
+        None => false,
+    }
+}
+
+/// The `If-None-Match` request header, for answering conditional requests.
+pub struct IfNoneMatch(pub Option<String>);
+
+impl IfNoneMatch {
+    /// Whether the client already has the representation tagged `etag`.
+    pub fn matches(&self, etag: &str) -> bool {
+        self.0.as_deref().is_some_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(req.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
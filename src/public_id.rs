@@ -0,0 +1,132 @@
+use std::fmt;
+
+use diesel::prelude::*;
+use rand::distr::{Alphanumeric, SampleString};
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::models::File;
+
+/// Shortest allowed `public_ids.length`, about 48 bits of randomness.
+pub const MIN_LENGTH: usize = 8;
+pub const MAX_LENGTH: usize = 64;
+
+/// First path segments of the server's own routes, and names likely to mislead.
+const RESERVED_SLUGS: &[&str] = &[
+    "admin", "api", "archive", "assets", "download", "f", "favicon.ico", "healthz", "index", "login",
+    "logout", "metrics", "netdrop", "p", "raw", "readyz", "robots.txt", "static", "thumb", "upload", "view",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PublicIdConfig {
+    /// Characters in generated ids, each worth almost 6 bits.
+    pub length: usize,
+}
+
+impl Default for PublicIdConfig {
+    fn default() -> Self {
+        PublicIdConfig { length: 16 }
+    }
+}
+
+/// A random id of letters and digits, safe in any part of a URL.
+pub fn generate(length: usize) -> String {
+    Alphanumeric.sample_string(&mut rand::rng(), length)
+}
+
+/// Generates an id no file uses yet, as either its public id or its slug.
+pub fn generate_unused(conn: &mut SqliteConnection, length: usize) -> QueryResult<String> {
+    loop {
+        let id = generate(length);
+        if !is_taken(conn, &id)? {
+            return Ok(id);
+        }
+    }
+}
+
+/// Whether a link with `id` already leads to a file.
+pub fn is_taken(conn: &mut SqliteConnection, id: &str) -> QueryResult<bool> {
+    use crate::schema::file_links;
+
+    let count = file_links::table
+        .find(id)
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SlugError {
+    Invalid,
+    Reserved,
+    Taken,
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for SlugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlugError::Invalid => write!(f, "slugs are 3 to 64 lowercase letters, digits and inner hyphens"),
+            SlugError::Reserved => write!(f, "slug is reserved"),
+            SlugError::Taken => write!(f, "slug is already in use"),
+            SlugError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SlugError {}
+
+impl From<diesel::result::Error> for SlugError {
+    fn from(e: diesel::result::Error) -> Self {
+        SlugError::Database(e)
+    }
+}
+
+/// Checks the form of a slug, not whether it is free.
+pub fn validate_slug(slug: &str) -> Result<(), SlugError> {
+    let well_formed = (3..=64).contains(&slug.len())
+        && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.contains("--");
+    if !well_formed {
+        return Err(SlugError::Invalid);
+    }
+    if RESERVED_SLUGS.contains(&slug) {
+        return Err(SlugError::Reserved);
+    }
+    Ok(())
+}
+
+/// Gives `file` a vanity slug, or removes it with `None`.
+pub fn set_slug(conn: &mut SqliteConnection, file: &File, slug: Option<&str>) -> Result<(), SlugError> {
+    use crate::schema::{file_links, files};
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    if let Some(slug) = slug {
+        validate_slug(slug)?;
+    }
+    conn.transaction(|conn| {
+        // Every link but the public id is the old slug
+        diesel::delete(
+            file_links::table
+                .filter(file_links::file_id.eq(file.id))
+                .filter(file_links::link.ne(&file.public_id)),
+        )
+        .execute(conn)?;
+        // Claiming the link is the check, so two requests can't both take a slug
+        if let Some(slug) = slug {
+            diesel::insert_into(file_links::table)
+                .values((file_links::link.eq(slug), file_links::file_id.eq(file.id)))
+                .execute(conn)
+                .map_err(|e| match e {
+                    Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => SlugError::Taken,
+                    e => SlugError::Database(e),
+                })?;
+        }
+        diesel::update(files::table.find(file.id))
+            .set(files::slug.eq(slug))
+            .execute(conn)?;
+        Ok(())
+    })
+}
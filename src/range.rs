@@ -0,0 +1,142 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use rocket::request::{FromRequest, Outcome, Request};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, ReadBuf};
+
+/// What part of a file a request asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    /// Inclusive byte offsets, as in `Content-Range`.
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Interprets a `Range` header for a file of `len` bytes.
+    ///
+    /// Only a single `bytes` range is honoured. Multiple ranges and malformed
+    /// headers get the whole file, which RFC 9110 allows.
+    pub fn parse(header: Option<&str>, len: u64) -> ByteRange {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Full;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+
+        let range = match (first.trim(), last.trim()) {
+            // The last N bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return ByteRange::Unsatisfiable,
+                Ok(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+                Err(_) => return ByteRange::Full,
+            },
+            (start, "") => match start.parse::<u64>() {
+                Ok(start) => (start, len.saturating_sub(1)),
+                Err(_) => return ByteRange::Full,
+            },
+            (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+                _ => return ByteRange::Full,
+            },
+        };
+
+        match range {
+            (start, _) if start >= len => ByteRange::Unsatisfiable,
+            (0, end) if end + 1 == len => ByteRange::Full,
+            (start, end) => ByteRange::Partial { start, end },
+        }
+    }
+}
+
+/// The `Range` request header.
+pub struct RangeHeader(pub Option<String>);
+
+impl RangeHeader {
+    pub fn range(&self, len: u64) -> ByteRange {
+        ByteRange::parse(self.0.as_deref(), len)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RangeHeader(req.headers().get_one("Range").map(String::from)))
+    }
+}
+
+/// A window of `len` bytes starting at `start` of the inner reader, which appears
+/// to be the whole stream so it can be served as a sized body.
+pub struct FileSlice<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<R: AsyncSeek + Unpin> FileSlice<R> {
+    pub async fn new(mut inner: R, start: u64, len: u64) -> io::Result<FileSlice<R>> {
+        inner.seek(SeekFrom::Start(start)).await?;
+        Ok(FileSlice {
+            inner,
+            start,
+            len,
+            position: 0,
+        })
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for FileSlice<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let remaining = self.len.saturating_sub(self.position);
+        if remaining == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let before = buf.filled().len();
+        let result = if remaining >= buf.remaining() as u64 {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        } else {
+            // Only the end of the slice is smaller than the caller's buffer, copy through a small one
+            let mut tail = vec![0u8; remaining as usize];
+            let mut tail_buf = ReadBuf::new(&mut tail);
+            let result = Pin::new(&mut self.inner).poll_read(cx, &mut tail_buf);
+            buf.put_slice(tail_buf.filled());
+            result
+        };
+
+        let read = buf.filled().len() - before;
+        self.position += read as u64;
+        result
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for FileSlice<R> {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let target = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek out of range"))?;
+        let start = self.start;
+        Pin::new(&mut self.inner).start_seek(SeekFrom::Start(start + target))
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let result = Pin::new(&mut self.inner).poll_complete(cx);
+        if let Poll::Ready(Ok(offset)) = result {
+            self.position = offset.saturating_sub(self.start);
+            return Poll::Ready(Ok(self.position));
+        }
+        result
+    }
+}
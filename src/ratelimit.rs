@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::audit::Actor;
+
+// Once this many clients are tracked, buckets that have fully refilled are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A classic token bucket. Tokens refill continuously up to `capacity`.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_sec: f64, now: Instant) -> Self {
+        TokenBucket {
+            capacity,
+            refill_per_sec,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Takes `amount` tokens, or returns how long to wait until they are available.
+    pub fn try_take(&mut self, amount: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            Ok(())
+        } else {
+            Err(self.time_until(amount))
+        }
+    }
+
+    /// Takes `amount` tokens unconditionally, letting the bucket go into debt.
+    pub fn charge(&mut self, amount: f64, now: Instant) {
+        self.refill(now);
+        self.tokens -= amount;
+    }
+
+    /// Returns how long until the bucket is out of debt, if it is in debt.
+    pub fn debt_wait(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        (self.tokens < 0.0).then(|| self.time_until(0.0))
+    }
+
+    /// Returns the tokens currently available, which is negative while in debt.
+    pub fn available(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens
+    }
+
+    /// Returns how long until `amount` tokens are available.
+    pub fn wait_for(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.time_until(amount)
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+
+    fn time_until(&self, amount: f64) -> Duration {
+        if self.refill_per_sec <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64((amount - self.tokens).max(0.0) / self.refill_per_sec)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RateLimitConfig {
+    /// Requests allowed per minute per client, 0 disables request limiting.
+    pub requests_per_minute: u32,
+    /// Requests a client may make in a burst before being throttled.
+    pub request_burst: u32,
+    /// Bytes transferred per minute per client, 0 disables bandwidth limiting.
+    pub bytes_per_minute: u64,
+    /// Proxies allowed to set `X-Forwarded-For`, as addresses or CIDR ranges.
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_minute: 60,
+            request_burst: 20,
+            bytes_per_minute: 0,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// Parses addresses and CIDR ranges, naming the first entry that is neither.
+pub fn parse_trusted_proxies(entries: &[String]) -> Result<Vec<IpNet>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let entry = entry.trim();
+            entry
+                .parse::<IpNet>()
+                .ok()
+                .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+                .ok_or_else(|| format!("'{}' is not an address or CIDR range", entry))
+        })
+        .collect()
+}
+
+/// Resolves the address of the client behind any trusted proxies.
+///
+/// `X-Forwarded-For` is only honoured when the peer is a trusted proxy, and is
+/// walked right to left so a client can't spoof its address by prepending entries.
+pub fn resolve_client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    let Some(forwarded_for) = forwarded_for else {
+        return peer;
+    };
+
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !is_trusted(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    Ip(IpAddr),
+    /// The id of an API token the request was authenticated with.
+    Token(i32),
+}
+
+struct ClientBuckets {
+    requests: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl ClientBuckets {
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.requests.as_mut().is_none_or(|b| b.is_full(now))
+            && self.bytes.as_mut().is_none_or(|b| b.is_full(now))
+    }
+}
+
+/// Per-client request and bandwidth budgets, shared as managed state.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    trusted_proxies: Vec<IpNet>,
+    clients: Mutex<HashMap<ClientKey, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        // Entries are checked by `Config::validated`
+        let trusted_proxies = parse_trusted_proxies(&config.trusted_proxies).unwrap_or_default();
+        RateLimiter {
+            config,
+            trusted_proxies,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    fn new_buckets(&self, now: Instant) -> ClientBuckets {
+        let requests = (self.config.requests_per_minute > 0).then(|| {
+            let burst = self.config.request_burst.max(1) as f64;
+            TokenBucket::new(burst, self.config.requests_per_minute as f64 / 60.0, now)
+        });
+        // Bandwidth budgets allow a full minute's worth of transfer in one go
+        let bytes = (self.config.bytes_per_minute > 0).then(|| {
+            let per_minute = self.config.bytes_per_minute as f64;
+            TokenBucket::new(per_minute, per_minute / 60.0, now)
+        });
+        ClientBuckets { requests, bytes }
+    }
+
+    /// Admits a request for all `keys`, or returns how long the client must wait.
+    pub fn check(&self, keys: &[ClientKey], now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, buckets| !buckets.is_idle(now));
+        }
+
+        // Refuse while any key is out of budget, without spending tokens on the others
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let buckets = clients
+                .entry(key.clone())
+                .or_insert_with(|| self.new_buckets(now));
+            if let Some(debt) = buckets.bytes.as_mut().and_then(|b| b.debt_wait(now)) {
+                wait = wait.max(debt);
+            }
+            if let Some(requests) = buckets.requests.as_mut() {
+                requests.refill(now);
+                if requests.tokens < 1.0 {
+                    wait = wait.max(requests.time_until(1.0));
+                }
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys {
+            if let Some(requests) = clients.get_mut(key).and_then(|b| b.requests.as_mut()) {
+                requests.charge(1.0, now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Charges transferred bytes against the bandwidth budget of all `keys`.
+    pub fn charge_bytes(&self, keys: &[ClientKey], amount: u64, now: Instant) {
+        let mut clients = self.clients.lock().unwrap();
+        for key in keys {
+            let buckets = clients
+                .entry(key.clone())
+                .or_insert_with(|| self.new_buckets(now));
+            if let Some(bytes) = buckets.bytes.as_mut() {
+                bytes.charge(amount as f64, now);
+            }
+        }
+    }
+}
+
+/// Extracts the bearer token from an `Authorization` header value.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// The client address of `req`, see [`resolve_client_ip`].
+pub fn request_client_ip(req: &Request<'_>) -> IpAddr {
+    let peer = req
+        .remote()
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    match req.rocket().state::<RateLimiter>() {
+        Some(limiter) => resolve_client_ip(peer, req.headers().get_one("X-Forwarded-For"), &limiter.trusted_proxies),
+        None => peer,
+    }
+}
+
+/// How long a rate limited client should wait, stashed for the 429 catcher.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryAfter(pub Option<Duration>);
+
+impl RetryAfter {
+    /// Whole seconds suitable for a `Retry-After` header.
+    pub fn seconds(&self) -> u64 {
+        self.0
+            .map(|wait| wait.as_secs() + u64::from(wait.subsec_nanos() > 0))
+            .unwrap_or(1)
+            .max(1)
+    }
+}
+
+/// Request guard admitting a request against the client's IP and API token budgets.
+pub struct RateLimit<'r> {
+    limiter: &'r RateLimiter,
+    keys: Vec<ClientKey>,
+    client_ip: IpAddr,
+}
+
+impl RateLimit<'_> {
+    pub fn client_ip(&self) -> IpAddr {
+        self.client_ip
+    }
+
+    /// Whether the request authenticated with an API token, which a bearer header alone doesn't prove.
+    pub fn is_authenticated(&self) -> bool {
+        self.keys.iter().any(|key| matches!(key, ClientKey::Token(_)))
+    }
+
+    /// Charges bytes uploaded or downloaded by this client.
+    pub fn charge_bytes(&self, amount: u64) {
+        self.limiter.charge_bytes(&self.keys, amount, Instant::now());
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let Some(limiter) = req.rocket().state::<RateLimiter>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        let client_ip = request_client_ip(req);
+
+        // Token budgets apply on top of the IP budget, so rotating tokens doesn't lift the limit.
+        // Only tokens that authenticate get one, made up tokens can't add entries.
+        let mut keys = vec![ClientKey::Ip(client_ip)];
+        if let Outcome::Success(Actor { token_id: Some(token_id), .. }) = req.guard::<Actor>().await {
+            keys.push(ClientKey::Token(token_id));
+        }
+
+        match limiter.check(&keys, Instant::now()) {
+            Ok(()) => Outcome::Success(RateLimit { limiter, keys, client_ip }),
+            Err(wait) => {
+                req.local_cache(|| RetryAfter(Some(wait)));
+                Outcome::Error((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::Write;
+use std::io::{self, Read};
+use std::path::Path;
+
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::html::{escape, page};
+
+/// Largest Markdown file rendered as HTML, bigger ones are shown as plain text.
+pub const MAX_MARKDOWN_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Rows shown on each page of a CSV preview.
+pub const CSV_PAGE_SIZE: usize = 100;
+
+/// Last page of a CSV preview, later rows are only in the download.
+pub const MAX_TABLE_PAGE: usize = 10_000;
+
+// A record longer than this fails the preview, so a file without line breaks isn't buffered whole
+const MAX_RECORD_BYTES: u64 = 1024 * 1024;
+
+// Cells are cut short so one huge field can't blow up the page
+const MAX_CELL_CHARS: usize = 1_000;
+
+const MARKDOWN_STYLE: &str = "main{max-width:52rem;margin:0 auto;line-height:1.6}\
+pre{background:#f3f4f6;padding:.75rem;overflow:auto}code{font-family:ui-monospace,Menlo,monospace}\
+table{border-collapse:collapse}th,td{border:1px solid #e5e7eb;padding:.25rem .5rem}img{max-width:100%}";
+
+const TABLE_STYLE: &str = "table{border-collapse:collapse;font-size:13px}\
+th,td{border:1px solid #e5e7eb;padding:.25rem .5rem;text-align:left;vertical-align:top;white-space:pre-wrap}\
+th{background:#f9fafb;position:sticky;top:0}td.n{color:#9ca3af;text-align:right}\
+nav{display:flex;gap:1rem;margin:1rem 0}";
+
+/// How a file is rendered by the preview route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rendering {
+    Markdown,
+    Delimited(u8),
+}
+
+impl Rendering {
+    /// Picks a rendering for a file, TSV is recognised by extension as it has no common MIME type.
+    pub fn for_file(mime_type: &str, file_name: &str) -> Option<Rendering> {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match mime_type {
+            "text/markdown" => Some(Rendering::Markdown),
+            "text/csv" => Some(Rendering::Delimited(b',')),
+            "text/tab-separated-values" => Some(Rendering::Delimited(b'\t')),
+            "text/plain" if extension.as_deref() == Some("tsv") => Some(Rendering::Delimited(b'\t')),
+            _ => None,
+        }
+    }
+}
+
+/// Converts Markdown to HTML with anything that could run script removed.
+pub fn markdown_to_html(text: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_FOOTNOTES);
+
+    let mut unsafe_html = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(text, options));
+    ammonia::clean(&unsafe_html)
+}
+
+/// Renders the preview page for a Markdown file.
+pub fn render_markdown_page(text: &str, file_name: &str, download_url: &str) -> String {
+    let body = format!(
+        "<header><h1>{}</h1><a href=\"{}\">Download</a></header><main>{}</main>",
+        escape(file_name),
+        escape(download_url),
+        markdown_to_html(text)
+    );
+    page(file_name, &format!("<style>{}</style>", MARKDOWN_STYLE), &body)
+}
+
+/// One page of rows read from a delimited file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TablePage {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// 1-based page number.
+    pub page: usize,
+    pub has_next: bool,
+    /// Bytes read from the file to produce the page.
+    pub bytes_read: u64,
+}
+
+/// Reads one page of a CSV or TSV file, streaming past earlier rows rather than loading the file.
+///
+/// The first record is taken as the header row. Rows may have differing lengths.
+/// Returns `None` for pages past the end of the file or past [`MAX_TABLE_PAGE`], and an
+/// error for records longer than 1 MiB.
+pub fn read_table_page<R: Read>(reader: R, delimiter: u8, page: usize) -> Result<Option<TablePage>, csv::Error> {
+    let page = page.max(1);
+    if page > MAX_TABLE_PAGE {
+        return Ok(None);
+    }
+    let skip = (page - 1) * CSV_PAGE_SIZE;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(RecordLimit {
+            inner: reader,
+            read: 0,
+            limit: MAX_RECORD_BYTES,
+        });
+
+    let headers = reader.headers()?.iter().map(truncate_cell).collect();
+    next_record_limit(&mut reader);
+
+    let mut skipped = csv::ByteRecord::new();
+    for _ in 0..skip {
+        let found = reader.read_byte_record(&mut skipped)?;
+        next_record_limit(&mut reader);
+        if !found {
+            return Ok(None);
+        }
+    }
+
+    let mut record = csv::StringRecord::new();
+    let mut rows = Vec::with_capacity(CSV_PAGE_SIZE);
+    while rows.len() < CSV_PAGE_SIZE && reader.read_record(&mut record)? {
+        next_record_limit(&mut reader);
+        rows.push(record.iter().map(truncate_cell).collect());
+    }
+    // The first page exists even when the file has no rows
+    if rows.is_empty() && page > 1 {
+        return Ok(None);
+    }
+    let has_next = page < MAX_TABLE_PAGE && !matches!(reader.read_byte_record(&mut skipped), Ok(false));
+    let bytes_read = reader.position().byte();
+
+    Ok(Some(TablePage {
+        headers,
+        rows,
+        page,
+        has_next,
+        bytes_read,
+    }))
+}
+
+/// Fails reads that run more than [`MAX_RECORD_BYTES`] past the start of the current record.
+struct RecordLimit<R> {
+    inner: R,
+    read: u64,
+    limit: u64,
+}
+
+impl<R: Read> Read for RecordLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let allowed = self.limit.saturating_sub(self.read);
+        if allowed == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "record is too large"));
+        }
+        let len = buf.len().min(usize::try_from(allowed).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..len])?;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+fn next_record_limit<R: Read>(reader: &mut csv::Reader<RecordLimit<R>>) {
+    let start = reader.position().byte();
+    reader.get_mut().limit = start + MAX_RECORD_BYTES;
+}
+
+fn truncate_cell(cell: &str) -> String {
+    match cell.char_indices().nth(MAX_CELL_CHARS) {
+        Some((end, _)) => format!("{}…", &cell[..end]),
+        None => cell.to_string(),
+    }
+}
+
+/// Renders the preview page for one page of a CSV or TSV file.
+///
+/// `page_url` is called with a page number to link to neighbouring pages.
+pub fn render_table_page(table: &TablePage, file_name: &str, download_url: &str, page_url: impl Fn(usize) -> String) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<header><h1>{}</h1><span>Page {}</span><a href=\"{}\">Download</a></header><main><table><thead><tr><th></th>",
+        escape(file_name),
+        table.page,
+        escape(download_url)
+    );
+    for header in &table.headers {
+        let _ = write!(html, "<th>{}</th>", escape(header));
+    }
+    html.push_str("</tr></thead><tbody>");
+
+    let first_row = (table.page - 1) * CSV_PAGE_SIZE + 1;
+    for (index, row) in table.rows.iter().enumerate() {
+        let _ = write!(html, "<tr><td class=\"n\">{}</td>", first_row + index);
+        for cell in row {
+            let _ = write!(html, "<td>{}</td>", escape(cell));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</tbody></table><nav>");
+
+    if table.page > 1 {
+        let _ = write!(html, "<a href=\"{}\">Previous</a>", escape(&page_url(table.page - 1)));
+    }
+    if table.has_next {
+        let _ = write!(html, "<a href=\"{}\">Next</a>", escape(&page_url(table.page + 1)));
+    }
+    html.push_str("</nav></main>");
+
+    page(file_name, &format!("<style>{}</style>", TABLE_STYLE), &html)
+}
@@ -0,0 +1,386 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use rocket::data::ByteUnit;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::models::File;
+
+// clamd's StreamMaxLength limits the whole INSTREAM, the chunk size only has to fit in memory
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// clamd's default `StreamMaxLength` of 25M.
+pub const CLAMD_DEFAULT_MAX_STREAM: ByteUnit = ByteUnit::Mebibyte(25);
+
+/// Scan state of an uploaded file, stored in `files.scan_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStatus {
+    /// No scanner was configured when the file was uploaded.
+    Unscanned,
+    Pending,
+    Clean,
+    Infected,
+    /// The scanner failed, the file is treated as unsafe until rescanned.
+    Error,
+}
+
+impl ScanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStatus::Unscanned => "unscanned",
+            ScanStatus::Pending => "pending",
+            ScanStatus::Clean => "clean",
+            ScanStatus::Infected => "infected",
+            ScanStatus::Error => "error",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ScanStatus> {
+        match value {
+            "unscanned" => Some(ScanStatus::Unscanned),
+            "pending" => Some(ScanStatus::Pending),
+            "clean" => Some(ScanStatus::Clean),
+            "infected" => Some(ScanStatus::Infected),
+            "error" => Some(ScanStatus::Error),
+            _ => None,
+        }
+    }
+
+    /// Whether a file in this state may be downloaded.
+    pub fn is_downloadable(&self) -> bool {
+        matches!(self, ScanStatus::Unscanned | ScanStatus::Clean)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    Infected(String),
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    Io(io::Error),
+    Scanner(String),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Io(e) => write!(f, "scanner I/O error: {}", e),
+            ScanError::Scanner(message) => write!(f, "scanner error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+impl From<io::Error> for ScanError {
+    fn from(e: io::Error) -> Self {
+        ScanError::Io(e)
+    }
+}
+
+/// A malware scanner. Scans are blocking and run off the async runtime.
+pub trait Scanner: Send + Sync {
+    fn scan(&self, path: &Path) -> Result<Verdict, ScanError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClamdAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl ClamdAddress {
+    /// Parses `unix:/run/clamav/clamd.ctl`, `tcp:host:3310` or a bare `host:3310`.
+    pub fn parse(value: &str) -> ClamdAddress {
+        if let Some(path) = value.strip_prefix("unix:") {
+            ClamdAddress::Unix(PathBuf::from(path))
+        } else if value.starts_with('/') {
+            ClamdAddress::Unix(PathBuf::from(value))
+        } else {
+            ClamdAddress::Tcp(value.strip_prefix("tcp:").unwrap_or(value).to_string())
+        }
+    }
+}
+
+/// Scans files with a ClamAV daemon using the INSTREAM command.
+pub struct ClamdScanner {
+    pub address: ClamdAddress,
+    pub timeout: Duration,
+    /// clamd's `StreamMaxLength`, larger files fail to scan without being sent.
+    pub max_stream_size: u64,
+}
+
+impl ClamdScanner {
+    pub fn new(address: ClamdAddress) -> Self {
+        ClamdScanner {
+            address,
+            timeout: Duration::from_secs(60),
+            max_stream_size: CLAMD_DEFAULT_MAX_STREAM.as_u64(),
+        }
+    }
+}
+
+impl Scanner for ClamdScanner {
+    fn scan(&self, path: &Path) -> Result<Verdict, ScanError> {
+        let file = fs::File::open(path)?;
+        // clamd would drop the connection part way through, so say why instead
+        let len = file.metadata()?.len();
+        if len > self.max_stream_size {
+            return Err(ScanError::Scanner(format!(
+                "{} bytes is over clamd's stream limit of {} bytes",
+                len, self.max_stream_size
+            )));
+        }
+
+        match &self.address {
+            #[cfg(unix)]
+            ClamdAddress::Unix(socket) => {
+                let stream = UnixStream::connect(socket)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                clamd_instream(stream, file)
+            }
+            #[cfg(not(unix))]
+            ClamdAddress::Unix(_) => Err(ScanError::Scanner(
+                "unix sockets are not supported on this platform".to_string(),
+            )),
+            ClamdAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                clamd_instream(stream, file)
+            }
+        }
+    }
+}
+
+/// Streams `data` to clamd in length-prefixed chunks and parses its reply.
+pub fn clamd_instream<S: Read + Write, R: Read>(mut stream: S, mut data: R) -> Result<Verdict, ScanError> {
+    stream.write_all(b"zINSTREAM\0")?;
+
+    let mut chunk = vec![0u8; CLAMD_CHUNK_SIZE];
+    loop {
+        let read = data.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes())?;
+        stream.write_all(&chunk[..read])?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    stream.flush()?;
+
+    let mut reply = Vec::new();
+    BufReader::new(stream).read_until(0, &mut reply)?;
+    if reply.last() == Some(&0) {
+        reply.pop();
+    }
+
+    parse_clamd_reply(&String::from_utf8_lossy(&reply))
+}
+
+fn parse_clamd_reply(reply: &str) -> Result<Verdict, ScanError> {
+    let reply = reply.trim();
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(ScanError::Scanner(format!("unexpected clamd reply: {}", reply)))
+    }
+}
+
+/// Runs an external command with the file path appended as the last argument.
+///
+/// Follows the `clamscan` convention: exit status 0 is clean, 1 is infected and
+/// anything else is an error. The first line of output is kept as the verdict.
+pub struct CommandScanner {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl CommandScanner {
+    /// Splits a command line on whitespace, e.g. `clamdscan --no-summary`.
+    pub fn parse(command: &str) -> Option<CommandScanner> {
+        let mut parts = command.split_whitespace().map(str::to_string);
+        let program = parts.next()?;
+        Some(CommandScanner {
+            program,
+            args: parts.collect(),
+        })
+    }
+}
+
+impl Scanner for CommandScanner {
+    fn scan(&self, path: &Path) -> Result<Verdict, ScanError> {
+        let output = Command::new(&self.program).args(&self.args).arg(path).output()?;
+        let first_line = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .map(|line| line.trim().to_string())
+        };
+
+        match output.status.code() {
+            Some(0) => Ok(Verdict::Clean),
+            Some(1) => Ok(Verdict::Infected(
+                first_line(&output.stdout).unwrap_or_else(|| "infected".to_string()),
+            )),
+            code => Err(ScanError::Scanner(format!(
+                "{} exited with {}: {}",
+                self.program,
+                code.map_or("signal".to_string(), |c| c.to_string()),
+                first_line(&output.stderr).unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum ScannerKind {
+    #[default]
+    None,
+    Clamd,
+    Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ScannerConfig {
+    pub kind: ScannerKind,
+    /// `unix:/run/clamav/clamd.ctl`, `tcp:host:3310` or a bare `host:3310`.
+    pub clamd_address: String,
+    /// Must match `StreamMaxLength` in clamd.conf. Larger files end up in `error`.
+    pub clamd_max_stream_size: ByteUnit,
+    /// Run with the file path appended, e.g. `clamdscan --no-summary`.
+    pub command: String,
+}
+
+impl Default for ScannerConfig {
+    fn default() -> Self {
+        ScannerConfig {
+            kind: ScannerKind::None,
+            clamd_address: "127.0.0.1:3310".to_string(),
+            clamd_max_stream_size: CLAMD_DEFAULT_MAX_STREAM,
+            command: String::new(),
+        }
+    }
+}
+
+/// The scanner uploads are checked with, if any, shared as managed state.
+#[derive(Clone, Default)]
+pub struct UploadScanner(pub Option<Arc<dyn Scanner>>);
+
+impl UploadScanner {
+    pub fn from_config(config: &ScannerConfig) -> Result<Self, String> {
+        let scanner: Arc<dyn Scanner> = match config.kind {
+            ScannerKind::None => return Ok(UploadScanner(None)),
+            ScannerKind::Clamd => {
+                if config.clamd_address.trim().is_empty() {
+                    return Err("clamd_address must be set for the clamd scanner".to_string());
+                }
+                Arc::new(ClamdScanner {
+                    max_stream_size: config.clamd_max_stream_size.as_u64(),
+                    ..ClamdScanner::new(ClamdAddress::parse(config.clamd_address.trim()))
+                })
+            }
+            ScannerKind::Command => {
+                Arc::new(CommandScanner::parse(&config.command).ok_or("command must be set for the command scanner")?)
+            }
+        };
+        Ok(UploadScanner(Some(scanner)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Status new uploads start in.
+    pub fn initial_status(&self) -> ScanStatus {
+        if self.is_enabled() {
+            ScanStatus::Pending
+        } else {
+            ScanStatus::Unscanned
+        }
+    }
+}
+
+pub fn update_scan_result(
+    conn: &mut SqliteConnection,
+    id: i32,
+    status: ScanStatus,
+    verdict: Option<&str>,
+    path: &str,
+) -> QueryResult<usize> {
+    use crate::schema::files;
+
+    diesel::update(files::table.find(id))
+        .set((
+            files::scan_status.eq(status.as_str()),
+            files::scan_verdict.eq(verdict),
+            files::scanned_at.eq(Some(chrono::Utc::now().naive_utc())),
+            files::file_path.eq(path),
+        ))
+        .execute(conn)
+}
+
+/// Files in any of `statuses` uploaded before `uploaded_before`, oldest first.
+pub fn files_to_rescan(
+    conn: &mut SqliteConnection,
+    statuses: &[ScanStatus],
+    uploaded_before: NaiveDateTime,
+) -> QueryResult<Vec<File>> {
+    use crate::schema::files;
+
+    let statuses: Vec<&str> = statuses.iter().map(ScanStatus::as_str).collect();
+    files::table
+        .filter(files::scan_status.eq_any(statuses))
+        .filter(files::created_at.lt(uploaded_before))
+        .order(files::id.asc())
+        .load::<File>(conn)
+}
+
+/// Scans a stored upload, quarantines it if infected and records the outcome.
+pub fn scan_and_record(
+    conn: &mut SqliteConnection,
+    scanner: &dyn Scanner,
+    file: &File,
+    quarantine_dir: &Path,
+) -> Result<ScanStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let (status, verdict, path) = match scanner.scan(Path::new(&file.file_path)) {
+        Ok(Verdict::Clean) => (ScanStatus::Clean, None, file.file_path.clone()),
+        Ok(Verdict::Infected(signature)) => {
+            fs::create_dir_all(quarantine_dir)?;
+            let name = Path::new(&file.file_path)
+                .file_name()
+                .ok_or("stored file has no name")?;
+            let quarantined = quarantine_dir.join(name);
+            fs::rename(&file.file_path, &quarantined)?;
+            (
+                ScanStatus::Infected,
+                Some(signature),
+                quarantined.to_string_lossy().into_owned(),
+            )
+        }
+        Err(e) => (ScanStatus::Error, Some(e.to_string()), file.file_path.clone()),
+    };
+
+    update_scan_result(conn, file.id, status, verdict.as_deref(), &path)?;
+    Ok(status)
+}
@@ -1,13 +1,103 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        event -> Text,
+        user_id -> Nullable<Integer>,
+        token_id -> Nullable<Integer>,
+        client_ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        file_id -> Nullable<Integer>,
+        file_hash -> Nullable<Text>,
+        detail -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    daily_downloads (file_id, day) {
+        file_id -> Integer,
+        day -> Date,
+        downloads -> Integer,
+    }
+}
+
+diesel::table! {
+    file_links (link) {
+        link -> Text,
+        file_id -> Integer,
+    }
+}
+
 diesel::table! {
     files (id) {
         id -> Integer,
         file_hash -> Text,
         file_name -> Text,
         file_path -> Text,
-        size -> Integer,
+        size -> BigInt,
         private -> Bool,
         created_at -> Timestamp,
+        mime_type -> Text,
+        scan_status -> Text,
+        scan_verdict -> Nullable<Text>,
+        scanned_at -> Nullable<Timestamp>,
+        sanitized -> Bool,
+        language -> Nullable<Text>,
+        sha256 -> Nullable<Text>,
+        owner_id -> Nullable<Integer>,
+        download_count -> Integer,
+        bytes_served -> BigInt,
+        last_downloaded_at -> Nullable<Timestamp>,
+        public_id -> Text,
+        slug -> Nullable<Text>,
+        legacy_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    thumbnails (id) {
+        id -> Integer,
+        file_id -> Integer,
+        size -> Integer,
+        mime_type -> Text,
+        file_path -> Text,
+        created_at -> Timestamp,
     }
 }
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(daily_downloads -> files (file_id));
+diesel::joinable!(file_links -> files (file_id));
+diesel::joinable!(files -> users (owner_id));
+diesel::joinable!(thumbnails -> files (file_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
+    daily_downloads,
+    file_links,
+    files,
+    thumbnails,
+    users,
+);
@@ -0,0 +1,1825 @@
+use rocket::response::content::RawHtml;
+use include_dir::{include_dir, Dir};
+use rocket::http::ContentType;
+use std::path::{Path, PathBuf};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket::data::{Data, ToByteUnit};
+use multer::Multipart;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use sha2::{Sha256, Digest};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, ConfigError};
+use crate::{establish_connection, create_file, file_stats, get_file_by_legacy_id, get_file_by_public_id, run_migrations, models::{AuditEntry, File, NewFile}};
+use crate::public_id::{self, SlugError};
+use crate::preview::{content_disposition, content_security_policy, flag_enabled, inline_content_type, Disposition, IfNoneMatch, SANDBOX_CSP};
+use crate::range::{ByteRange, FileSlice, RangeHeader};
+use crate::ratelimit::{bearer_token, RateLimit, RateLimiter, RetryAfter};
+use crate::archive::{self, ArchiveEntry, ArchiveError, ArchiveKind};
+use crate::audit::{self, Actor, AuditEvent, AuditFilter};
+use crate::checksum::{self, ChecksumError, Sha256Digest};
+use crate::fsck::{self, FsckOptions, FsckReport};
+use crate::health::{self, Readiness};
+use crate::filetype::{is_textual, sniff_mime_type, UploadPolicy};
+use crate::html::PAGE_CSP;
+use crate::landing::{render_landing_page, Landing};
+use crate::logging::{self, RequestSpan, RequestTracing};
+use crate::paste::{is_valid_language, render_paste_page, MAX_PASTE_SIZE};
+use crate::render::{read_table_page, render_markdown_page, render_table_page, Rendering, MAX_MARKDOWN_SIZE};
+use crate::metadata::{self, strip_metadata, MetadataPolicy};
+use crate::metrics::{self, MeteredReader, RejectReason};
+use crate::scan::{self, scan_and_record, ScanStatus, Scanner, UploadScanner};
+use crate::spool::{Spool, Spooled};
+use crate::stats::{DownloadStats, FileStats};
+use crate::throttle::{Throttle, ThrottledReader};
+use crate::thumbnail::{self, get_thumbnail, nearest_size, ThumbnailQueue, PLACEHOLDER_SVG, THUMBNAIL_SIZES};
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::{request, Build, Request, Rocket, State};
+use rocket::request::FromRequest;
+use rocket::response::{self, Redirect, Responder, Response};
+use rocket::http::{Header, Status};
+use tracing::Instrument;
+
+static ASSETS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/web/netdrop/dist");
+
+#[derive(Serialize)]
+pub struct UploadResponse {
+    success: bool,
+    message: String,
+    file_id: Option<i32>,
+    /// What links to the file are built on.
+    public_id: Option<String>,
+    /// Same as `public_id`, kept for older clients.
+    file_hash: Option<String>,
+    scan_status: Option<String>,
+    sanitized: Option<bool>,
+    /// Bytes stored, after any metadata was removed.
+    size: Option<i64>,
+    /// SHA-256 of the stored content.
+    sha256: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    success: bool,
+    error: String,
+}
+
+#[derive(Responder)]
+pub enum UploadError {
+    Rejected(Json<ErrorResponse>),
+    #[response(status = 422)]
+    ChecksumMismatch(Json<ErrorResponse>),
+    /// The server couldn't store the upload, the cause is logged.
+    #[response(status = 500)]
+    Failed(Json<ErrorResponse>),
+}
+
+impl UploadError {
+    fn message(&self) -> &str {
+        match self {
+            UploadError::Rejected(error) | UploadError::ChecksumMismatch(error) | UploadError::Failed(error) => &error.error,
+        }
+    }
+}
+
+impl From<Json<ErrorResponse>> for UploadError {
+    fn from(error: Json<ErrorResponse>) -> Self {
+        UploadError::Rejected(error)
+    }
+}
+
+fn upload_failed(message: &str) -> UploadError {
+    UploadError::Failed(Json(ErrorResponse {
+        success: false,
+        error: message.to_string(),
+    }))
+}
+
+fn checksum_mismatch() -> UploadError {
+    UploadError::ChecksumMismatch(Json(ErrorResponse {
+        success: false,
+        error: ChecksumError::Mismatch.to_string(),
+    }))
+}
+
+/// The SHA-256 a client expects its upload to have, from `Content-Digest` or `Digest`.
+pub struct UploadDigest(Result<Option<Sha256Digest>, ChecksumError>);
+
+impl UploadDigest {
+    fn expected(self) -> Result<Option<Sha256Digest>, UploadError> {
+        self.0.map_err(|e| {
+            Json(ErrorResponse {
+                success: false,
+                error: e.to_string(),
+            })
+            .into()
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UploadDigest {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        request::Outcome::Success(UploadDigest(checksum::from_headers(
+            headers.get_one("Content-Digest"),
+            headers.get_one("Digest"),
+        )))
+    }
+}
+
+/// Upload settings for raw-body uploads, which have no form fields to carry them.
+///
+/// The checksum comes from `Content-Digest` or `Digest`; metadata stripping from
+/// `?strip_metadata` or an `X-Strip-Metadata` header.
+pub struct RawUploadOptions {
+    digest: UploadDigest,
+    strip_metadata: Option<bool>,
+}
+
+impl RawUploadOptions {
+    fn into_options(self) -> Result<UploadOptions, UploadError> {
+        Ok(UploadOptions {
+            expected_sha256: self.digest.expected()?,
+            strip_metadata: self.strip_metadata,
+            ..UploadOptions::default()
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RawUploadOptions {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let digest = req.guard::<UploadDigest>().await.succeeded();
+        let strip_metadata = match req.query_value::<&str>("strip_metadata") {
+            Some(value) => Some(flag_enabled(value.ok().map(str::trim))),
+            None => req.headers().get_one("X-Strip-Metadata").map(|value| flag_enabled(Some(value.trim()))),
+        };
+        request::Outcome::Success(RawUploadOptions {
+            digest: digest.expect("UploadDigest is infallible"),
+            strip_metadata,
+        })
+    }
+}
+
+#[get("/<file..>")]
+pub async fn static_files(file: PathBuf) -> Option<(ContentType, Vec<u8>)> {
+    let path = file.display().to_string();
+    let file_content = ASSETS.get_file(&path)?;
+    let content_type = ContentType::from_extension(file.extension()?.to_str()?)?;
+
+    Some((content_type, file_content.contents().to_vec()))
+}
+
+#[post("/api/v1/upload", data = "<data>", format = "multipart/form-data")]
+pub async fn upload_file(content_type: &ContentType, data: Data<'_>, digest: UploadDigest, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> Result<Json<UploadResponse>, UploadError> {
+    metered_upload(span, read_multipart_upload(content_type, data, digest, rate_limit, uploads)).await
+}
+
+/// Records an upload's outcome and how long it took, logging within the request's span.
+async fn metered_upload(span: RequestSpan, upload: impl std::future::Future<Output = Result<Json<UploadResponse>, UploadError>>) -> Result<Json<UploadResponse>, UploadError> {
+    let metrics = metrics::global();
+    let transfer = metrics.upload_started();
+    let result = upload.instrument(span.0.clone()).await;
+
+    let _entered = span.0.enter();
+    let status = match &result {
+        Ok(response) => {
+            tracing::info!(public_id = response.public_id.as_deref(), size = response.size, sha256 = response.sha256.as_deref(), "upload stored");
+            "success"
+        }
+        Err(UploadError::Rejected(error)) => {
+            tracing::info!(reason = %error.error, "upload rejected");
+            "rejected"
+        }
+        Err(UploadError::ChecksumMismatch(_)) => {
+            tracing::warn!("upload does not match its checksum");
+            metrics.reject(RejectReason::ChecksumMismatch);
+            "checksum_mismatch"
+        }
+        Err(UploadError::Failed(error)) => {
+            tracing::warn!(reason = %error.error, "upload failed");
+            "failed"
+        }
+    };
+    metrics.uploads.with_label_values(&[status]).inc();
+    metrics.upload_duration.observe(transfer.elapsed_secs());
+    result
+}
+
+async fn read_multipart_upload(content_type: &ContentType, data: Data<'_>, digest: UploadDigest, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>) -> Result<Json<UploadResponse>, UploadError> {
+    // Extract boundary from content type
+    let boundary = content_type
+        .params()
+        .find(|(name, _)| *name == "boundary")
+        .map(|(_, value)| value)
+        .ok_or_else(|| Json(ErrorResponse {
+            success: false,
+            error: "Missing boundary in multipart data".to_string(),
+        }))?;
+
+    // Read the data stream and convert to a format multer can use
+    let stream = data.open(uploads.config.max_upload_size);
+    let reader_stream = ReaderStream::new(stream);
+    let mut multipart = Multipart::new(reader_stream, boundary);
+
+    let mut file_data: Option<Spooled> = None;
+    let mut filename: Option<String> = None;
+    let mut options = UploadOptions {
+        expected_sha256: digest.expected()?,
+        ..UploadOptions::default()
+    };
+
+    // Process multipart fields
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| {
+        tracing::info!(error = %e, "malformed multipart upload");
+        Json(ErrorResponse {
+            success: false,
+            error: "Failed to parse multipart data".to_string(),
+        })
+    })? {
+
+        let field_name = field.name().unwrap_or("").to_string();
+
+        if field_name == "file" {
+            filename = field.file_name().map(|s| s.to_string());
+            let mut spool = open_spool(&uploads)?;
+            while let Some(chunk) = field.chunk().await.map_err(|e| {
+                tracing::info!(error = %e, "failed to read upload body");
+                Json(ErrorResponse {
+                    success: false,
+                    error: "Failed to read file data".to_string(),
+                })
+            })? {
+                spool.write_all(&chunk).await.map_err(spool_failed)?;
+            }
+            file_data = Some(spool.finish().await.map_err(spool_failed)?);
+        } else if field_name == "strip_metadata" {
+            let value = field.text().await.unwrap_or_default();
+            options.strip_metadata = Some(flag_enabled(Some(value.trim())));
+        } else if field_name == "sha256" {
+            let value = field.text().await.unwrap_or_default();
+            options.expected_sha256 = Some(checksum::parse_hex(&value).map_err(|e| Json(ErrorResponse {
+                success: false,
+                error: e.to_string(),
+            }))?);
+        }
+    }
+
+    let upload = file_data.ok_or_else(|| Json(ErrorResponse {
+        success: false,
+        error: "No file data found in multipart upload".to_string(),
+    }))?;
+
+    let original_filename = filename.unwrap_or_else(|| "uploaded_file".to_string());
+
+    rate_limit.charge_bytes(upload.size);
+
+    process_file_upload(upload, original_filename, options, &uploads).await
+}
+
+#[put("/api/v1/upload/<filename>", data = "<data>")]
+pub async fn put_upload(filename: &str, data: Data<'_>, reply: UploadReply, options: RawUploadOptions, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> RawUploadResponse {
+    raw_upload(filename, data, reply, options, rate_limit, uploads, span).await
+}
+
+/// `curl -T file https://host/` sends `PUT /file`.
+#[put("/<filename>", data = "<data>", rank = 2)]
+pub async fn put_upload_root(filename: &str, data: Data<'_>, reply: UploadReply, options: RawUploadOptions, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> RawUploadResponse {
+    raw_upload(filename, data, reply, options, rate_limit, uploads, span).await
+}
+
+#[post("/api/v1/upload?<name>", data = "<data>", format = "application/octet-stream")]
+pub async fn post_raw_upload(name: Option<&str>, data: Data<'_>, reply: UploadReply, options: RawUploadOptions, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> RawUploadResponse {
+    raw_upload(name.unwrap_or("uploaded_file"), data, reply, options, rate_limit, uploads, span).await
+}
+
+/// Reply to a raw-body upload: JSON if the client asked for it, otherwise the download URL as text.
+#[derive(Responder)]
+pub enum RawUploadResponse {
+    Json(Json<UploadResponse>),
+    Text(String),
+    JsonError(UploadError),
+    #[response(status = 400)]
+    TextError(String),
+    #[response(status = 422)]
+    TextMismatch(String),
+    #[response(status = 500)]
+    TextFailed(String),
+}
+
+async fn raw_upload(filename: &str, data: Data<'_>, reply: UploadReply, options: RawUploadOptions, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> RawUploadResponse {
+    let result = metered_upload(span, read_raw_upload(filename, data, options, &rate_limit, &uploads)).await;
+
+    match (result, reply.wants_json) {
+        (Ok(response), true) => RawUploadResponse::Json(response),
+        (Ok(response), false) => {
+            let public_id = response.public_id.as_deref().unwrap_or_default();
+            RawUploadResponse::Text(format!("{}{}\n", reply.base_url, uri!(download_file(public_id, Option::<&str>::None))))
+        }
+        (Err(error), true) => RawUploadResponse::JsonError(error),
+        (Err(error @ UploadError::ChecksumMismatch(_)), false) => RawUploadResponse::TextMismatch(format!("{}\n", error.message())),
+        (Err(error @ UploadError::Failed(_)), false) => RawUploadResponse::TextFailed(format!("{}\n", error.message())),
+        (Err(error), false) => RawUploadResponse::TextError(format!("{}\n", error.message())),
+    }
+}
+
+async fn read_raw_upload(filename: &str, data: Data<'_>, options: RawUploadOptions, rate_limit: &RateLimit<'_>, uploads: &UploadContext<'_>) -> Result<Json<UploadResponse>, UploadError> {
+    let options = options.into_options()?;
+    let filename = filename.trim();
+    if filename.is_empty() {
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "Missing filename".to_string(),
+        }).into());
+    }
+
+    let mut spool = open_spool(uploads)?;
+    let received = data.open(uploads.config.max_upload_size).stream_to(&mut spool).await.map_err(|e| {
+        tracing::info!(error = %e, "failed to read upload body");
+        Json(ErrorResponse {
+            success: false,
+            error: "Failed to read file data".to_string(),
+        })
+    })?;
+
+    if !received.complete {
+        metrics::global().reject(RejectReason::TooLarge);
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "File is too large".to_string(),
+        }).into());
+    }
+
+    rate_limit.charge_bytes(received.written);
+    let upload = spool.finish().await.map_err(spool_failed)?;
+
+    process_file_upload(upload, filename.to_string(), options, uploads).await
+}
+
+/// How to reply to a raw-body upload.
+pub struct UploadReply {
+    /// Scheme and host download links are built on, from `public_url` or the `Host` header.
+    base_url: String,
+    wants_json: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UploadReply {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let base_url = base_url(req);
+        let wants_json = req.accept().is_some_and(|accept| accept.preferred().is_json());
+
+        request::Outcome::Success(UploadReply { base_url, wants_json })
+    }
+}
+
+/// Scheme and host links are built on, from `public_url` or the `Host` header.
+fn base_url(req: &Request<'_>) -> String {
+    match req.rocket().state::<Config>().and_then(|config| config.public_url.clone()) {
+        Some(url) => url,
+        None => req.host().map(|host| format!("http://{}", host)).unwrap_or_default(),
+    }
+}
+
+/// See [`base_url`].
+pub struct BaseUrl(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BaseUrl {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(BaseUrl(base_url(req)))
+    }
+}
+
+/// Per-upload choices made by the uploader.
+#[derive(Default)]
+pub struct UploadOptions {
+    strip_metadata: Option<bool>,
+    /// Stored instead of the sniffed type, for uploads whose type is known.
+    mime_type: Option<String>,
+    language: Option<String>,
+    /// Digest the client says the file has, checked once it is saved.
+    expected_sha256: Option<Sha256Digest>,
+}
+
+/// Managed state used to process uploads.
+pub struct UploadContext<'r> {
+    config: &'r Config,
+    policy: &'r UploadPolicy,
+    scanner: &'r UploadScanner,
+    thumbnails: &'r ThumbnailQueue,
+    metadata: &'r MetadataPolicy,
+    actor: Actor,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UploadContext<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let rocket = req.rocket();
+        match (rocket.state(), rocket.state(), rocket.state(), rocket.state(), rocket.state()) {
+            (Some(config), Some(policy), Some(scanner), Some(thumbnails), Some(metadata)) => {
+                let actor = req.guard::<Actor>().await.expect("Actor is infallible");
+                request::Outcome::Success(UploadContext { config, policy, scanner, thumbnails, metadata, actor })
+            }
+            _ => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// Starts writing an upload to a temporary file in the uploads directory.
+fn open_spool(uploads: &UploadContext<'_>) -> Result<Spool, UploadError> {
+    let upload_dir = uploads.config.upload_dir();
+    fs::create_dir_all(&upload_dir).and_then(|()| Spool::create(&upload_dir)).map_err(spool_failed)
+}
+
+fn spool_failed(error: std::io::Error) -> UploadError {
+    tracing::error!(error = %error, "failed to write upload to disk");
+    upload_failed("Failed to save file to disk")
+}
+
+async fn process_file_upload(mut upload: Spooled, original_filename: String, options: UploadOptions, uploads: &UploadContext<'_>) -> Result<Json<UploadResponse>, UploadError> {
+    metrics::global().bytes_received.inc_by(upload.size);
+
+    // Detect the real type from the file contents and enforce the upload policy
+    let mime_type = match options.mime_type {
+        Some(mime_type) => mime_type,
+        None => sniff_mime_type(&upload.head, &original_filename),
+    };
+    if let Err(e) = uploads.policy.check(&mime_type, &original_filename) {
+        metrics::global().reject(RejectReason::FileType);
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: e.to_string(),
+        }).into());
+    }
+
+    // Remove EXIF, XMP and GPS metadata from photos, the pixels are left untouched
+    let mut sanitized = false;
+    if uploads.metadata.should_strip(options.strip_metadata) && metadata::is_supported(&mime_type) {
+        let photo = tokio::fs::read(upload.path()).await.map_err(spool_failed)?;
+        match strip_metadata(&photo, &mime_type) {
+            Ok(Some(stripped)) => {
+                // The client's digest describes the photo as sent, check it before it changes
+                if options.expected_sha256.is_some_and(|expected| expected != upload.sha256) {
+                    return Err(checksum_mismatch());
+                }
+                let mut spool = open_spool(uploads)?;
+                spool.write_all(&stripped).await.map_err(spool_failed)?;
+                upload = spool.finish().await.map_err(spool_failed)?;
+                sanitized = true;
+            }
+            Ok(None) => {}
+            // Stripping is mandatory, so a photo it can't be removed from isn't stored
+            Err(e) if *uploads.metadata == MetadataPolicy::Always => {
+                tracing::info!(file_name = %original_filename, error = %e, "refusing upload whose metadata can't be stripped");
+                metrics::global().reject(RejectReason::FileType);
+                return Err(Json(ErrorResponse {
+                    success: false,
+                    error: format!("Could not remove metadata: {}", e),
+                }).into());
+            }
+            Err(e) => tracing::warn!(file_name = %original_filename, error = %e, "failed to strip metadata, storing the file as sent"),
+        }
+    }
+
+    if !sanitized && options.expected_sha256.is_some_and(|expected| expected != upload.sha256) {
+        return Err(checksum_mismatch());
+    }
+
+    // Only reachable with a max_upload_size beyond what a file system can hold
+    let Ok(size) = i64::try_from(upload.size) else {
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "File is too large".to_string(),
+        }).into());
+    };
+
+    // Calculate file hash with timestamp to ensure uniqueness
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    let mut hasher = Sha256::new();
+    hasher.update(upload.sha256);
+    hasher.update(timestamp.to_be_bytes()); // Add timestamp to hash
+    let hash_bytes = hasher.finalize();
+    let file_hash = hex::encode(hash_bytes);
+
+    // Use original filename for file_name, hash-based name for storage
+    let short_hash = file_hash[..16].to_string(); // Use first 16 chars of hash for storage
+    let file_path = uploads.config.upload_dir().join(&short_hash).to_string_lossy().into_owned();
+
+    // Move the finished upload into place under its storage name
+    let sha256 = hex::encode(upload.sha256);
+    if let Err(e) = upload.persist(Path::new(&file_path)) {
+        tracing::error!(path = %file_path, error = %e, "failed to save upload");
+        return Err(upload_failed("Failed to save file to disk"));
+    }
+
+    // Save file info to database
+    let mut connection = establish_connection(&uploads.config.database_url);
+
+    // Links get a random id, so they reveal nothing about the file or where it is stored
+    let public_id = match public_id::generate_unused(&mut connection, uploads.config.public_ids.length) {
+        Ok(public_id) => public_id,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to generate public id");
+            let _ = fs::remove_file(&file_path);
+            return Err(upload_failed("Failed to save file"));
+        }
+    };
+
+    let new_file = NewFile {
+        file_hash: &short_hash,        // Store short hash for lookups
+        file_name: &original_filename, // Use original filename
+        file_path: &file_path,         // Use hash-based storage path
+        size,
+        private: true, // Default to private
+        mime_type: &mime_type,
+        scan_status: uploads.scanner.initial_status().as_str(),
+        sanitized,
+        language: options.language.as_deref(),
+        sha256: Some(&sha256),
+        owner_id: uploads.actor.user_id,
+        public_id: &public_id,
+    };
+
+    // Use the create_file function from lib.rs
+    let file = match create_file(&mut connection, new_file) {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::error!(path = %file_path, error = %e, "failed to record upload");
+            let _ = fs::remove_file(&file_path);
+            return Err(upload_failed("Failed to save file"));
+        }
+    };
+    if let Err(e) = audit::record(&mut connection, AuditEvent::Upload, &uploads.actor, Some(&file.public_id), Some(&file.file_name)) {
+        tracing::error!(file_hash = %file.file_hash, error = %e, "failed to write audit event");
+    }
+
+    let response = UploadResponse {
+        success: true,
+        message: "File uploaded successfully".to_string(),
+        file_id: Some(file.id),
+        public_id: Some(file.public_id.clone()),
+        file_hash: Some(file.public_id.clone()),
+        scan_status: Some(file.scan_status.clone()),
+        sanitized: Some(file.sanitized),
+        size: Some(file.size),
+        sha256: file.sha256.clone(),
+    };
+
+    if thumbnail::is_supported(&file.mime_type) {
+        uploads.thumbnails.enqueue(file.id);
+    }
+
+    // Scan in the background, the file can't be downloaded until it is cleared
+    if let Some(scanner) = uploads.scanner.0.clone() {
+        let (database_url, quarantine_dir) = (uploads.config.database_url.clone(), uploads.config.quarantine_dir());
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || span.in_scope(|| {
+            let mut connection = establish_connection(&database_url);
+            if let Err(e) = scan_and_record(&mut connection, scanner.as_ref(), &file, &quarantine_dir) {
+                tracing::error!(file_hash = %file.file_hash, error = %e, "failed to scan file");
+            }
+        }));
+    }
+
+    Ok(Json(response))
+}
+
+pub struct FileDownload {
+    inner: ThrottledReader<MeteredReader<FileSlice<tokio::fs::File>>>,
+    file_id: i32,
+    /// Size of the whole file, not just the part sent.
+    size: i64,
+    /// Offset of the part of the file sent.
+    start: u64,
+    /// `Content-Range` of a partial response.
+    content_range: Option<String>,
+    content_type: ContentType,
+    content_disposition: String,
+    content_security_policy: &'static str,
+}
+
+impl FileDownload {
+    fn status(&self) -> Status {
+        if self.content_range.is_some() { Status::PartialContent } else { Status::Ok }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for FileDownload {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        if let Some(content_range) = self.content_range {
+            response.status(Status::PartialContent).raw_header("Content-Range", content_range);
+        }
+        response
+            .header(self.content_type)
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("Content-Disposition", self.content_disposition)
+            .raw_header("Content-Security-Policy", self.content_security_policy)
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .join(self.inner.respond_to(req)?)
+            .ok()
+    }
+}
+
+/// Answers range requests that start past the end of the file.
+#[derive(Responder)]
+#[response(status = 416)]
+pub struct RangeNotSatisfiable {
+    inner: (),
+    content_range: Header<'static>,
+}
+
+#[get("/download/<public_id>?<inline>")]
+pub async fn download_file(public_id: &str, inline: Option<&str>, range: RangeHeader, rate_limit: RateLimit<'_>, downloads: DownloadContext<'_>) -> Result<FileDownload, DownloadError> {
+    let mut result = open_download(public_id, inline, &range, rate_limit, &downloads).await;
+
+    // Counted as the body is sent, so abandoned downloads only add the bytes that went out
+    if let Ok(download) = &mut result {
+        let counted = downloads.stats.start(download.file_id, download.start == 0, chrono::Utc::now().naive_utc());
+        download.inner.get_mut().count_download(counted);
+    }
+    downloads.record("download", public_id, result.as_ref().map_or_else(DownloadError::status, FileDownload::status), result.as_ref().ok().map(|download| download.size)).await;
+    result
+}
+
+/// Answers HEAD, which clients send before downloading, with the download's headers.
+/// Rocket would otherwise run the GET route, counting and auditing it as a download.
+#[head("/download/<public_id>?<inline>")]
+pub async fn download_file_head(public_id: &str, inline: Option<&str>, range: RangeHeader, rate_limit: RateLimit<'_>, downloads: DownloadContext<'_>) -> Result<FileDownload, DownloadError> {
+    open_download(public_id, inline, &range, rate_limit, &downloads).await
+}
+
+async fn open_download(public_id: &str, inline: Option<&str>, range: &RangeHeader, rate_limit: RateLimit<'_>, downloads: &DownloadContext<'_>) -> Result<FileDownload, DownloadError> {
+    async {
+        let file = match find_servable_file(downloads.config, public_id).await {
+            // Links from before random ids carry the storage hash, send them to the current link
+            Err(status) if status == Status::NotFound => {
+                let current = find_legacy_public_id(downloads.config, public_id).await?.ok_or(Status::NotFound)?;
+                return Err(DownloadError::Moved(Box::new(Redirect::permanent(uri!(download_file(current, inline))))));
+            }
+            file => file?,
+        };
+        let disposition = if flag_enabled(inline) { Disposition::Inline } else { Disposition::Attachment };
+        let (content_type, disposition) = presentation(&file, disposition);
+        serve_file(file, content_type, disposition, range, rate_limit, downloads.throttle).await
+    }
+    .instrument(downloads.span.0.clone())
+    .await
+}
+
+#[derive(Responder)]
+pub enum ViewResponse {
+    File(Box<FileDownload>),
+    Page(HtmlPage),
+}
+
+#[get("/view/<public_id>?<page>")]
+pub async fn view_file(public_id: &str, page: Option<usize>, range: RangeHeader, rate_limit: RateLimit<'_>, downloads: DownloadContext<'_>) -> Result<ViewResponse, DownloadError> {
+    let result = render_view(public_id, page, range, rate_limit, downloads.throttle, downloads.config)
+        .instrument(downloads.span.0.clone())
+        .await;
+    let (status, size) = match &result {
+        Ok(ViewResponse::File(download)) => (download.status(), Some(download.size)),
+        Ok(ViewResponse::Page(_)) => (Status::Ok, None),
+        Err(e) => (e.status(), None),
+    };
+    downloads.record("view", public_id, status, size).await;
+    result
+}
+
+/// What the download routes need besides the request itself.
+pub struct DownloadContext<'r> {
+    config: &'r Config,
+    throttle: &'r Throttle,
+    stats: &'r DownloadStats,
+    actor: Actor,
+    span: RequestSpan,
+}
+
+impl DownloadContext<'_> {
+    /// Counts a download, logs it in the request's span and audits it.
+    async fn record(&self, route: &str, public_id: &str, status: Status, size: Option<i64>) {
+        metrics::global().record_download(route, status.code);
+        tracing::info!(parent: &self.span.0, route, public_id, status = status.code, size, "download");
+        audit_access(&self.span, self.config, &self.actor, route, public_id, status).await;
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DownloadContext<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let rocket = req.rocket();
+        let (Some(config), Some(throttle), Some(stats)) = (rocket.state(), rocket.state(), rocket.state()) else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        let actor = req.guard::<Actor>().await.expect("Actor is infallible");
+        let span = req.guard::<RequestSpan>().await.expect("RequestSpan is infallible");
+        request::Outcome::Success(DownloadContext { config, throttle, stats, actor, span })
+    }
+}
+
+/// Audits content being served, or refused because the file is blocked.
+async fn audit_access(span: &RequestSpan, config: &Config, actor: &Actor, route: &str, public_id: &str, status: Status) {
+    let event = match status.code {
+        200 | 206 => AuditEvent::Download,
+        // Quarantined or not yet scanned
+        403 | 423 => AuditEvent::AccessDenied,
+        _ => return,
+    };
+    audit::record_or_log(config, event, actor, Some(public_id), Some(route)).instrument(span.0.clone()).await;
+}
+
+async fn render_view(public_id: &str, page: Option<usize>, range: RangeHeader, rate_limit: RateLimit<'_>, throttle: &Throttle, config: &Config) -> Result<ViewResponse, DownloadError> {
+    let file = find_servable_file(config, public_id).await?;
+    let download_url = uri!(download_file(public_id, Option::<&str>::None)).to_string();
+
+    match Rendering::for_file(&file.mime_type, &file.file_name) {
+        Some(Rendering::Markdown) if file.size as u64 <= MAX_MARKDOWN_SIZE => {
+            let content = tokio::fs::read(&file.file_path).await.map_err(internal_error("failed to read file"))?;
+            rate_limit.charge_bytes(content.len() as u64);
+
+            let html = tokio::task::spawn_blocking(move || {
+                render_markdown_page(&String::from_utf8_lossy(&content), &file.file_name, &download_url)
+            })
+            .await
+            .map_err(internal_error("rendering task failed"))?;
+            Ok(ViewResponse::Page(HtmlPage::new(html)))
+        }
+        Some(Rendering::Delimited(delimiter)) => {
+            let id = public_id.to_string();
+            let (path, file_name) = (file.file_path.clone(), file.file_name.clone());
+            let table = tokio::task::spawn_blocking(move || {
+                let reader = std::io::BufReader::new(std::fs::File::open(&path)?);
+                let Some(table) = read_table_page(reader, delimiter, page.unwrap_or(1))? else {
+                    return Ok(None);
+                };
+                let html = render_table_page(&table, &file_name, &download_url, |page| {
+                    uri!(view_file(&id, Some(page))).to_string()
+                });
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Some((html, table.bytes_read)))
+            })
+            .await
+            .map_err(internal_error("rendering task failed"))?;
+
+            // Malformed files fall back to being shown as text
+            match table {
+                Ok(Some((html, bytes_read))) => {
+                    rate_limit.charge_bytes(bytes_read);
+                    Ok(ViewResponse::Page(HtmlPage::new(html)))
+                }
+                Ok(None) => Err(Status::NotFound.into()),
+                Err(e) => {
+                    tracing::debug!(error = %e, "showing malformed table as text");
+                    let (content_type, disposition) = presentation(&file, Disposition::Inline);
+                    let download = serve_file(file, content_type, disposition, &range, rate_limit, throttle).await?;
+                    Ok(ViewResponse::File(Box::new(download)))
+                }
+            }
+        }
+        _ => {
+            let (content_type, disposition) = presentation(&file, Disposition::Inline);
+            let download = serve_file(file, content_type, disposition, &range, rate_limit, throttle).await?;
+            Ok(ViewResponse::File(Box::new(download)))
+        }
+    }
+}
+
+#[get("/raw/<public_id>")]
+pub async fn raw_file(public_id: &str, range: RangeHeader, rate_limit: RateLimit<'_>, downloads: DownloadContext<'_>) -> Result<FileDownload, DownloadError> {
+    let result = async {
+        let file = find_servable_file(downloads.config, public_id).await?;
+        if !is_textual(&file.mime_type) {
+            return Err(Status::NotFound.into());
+        }
+        serve_file(file, "text/plain; charset=utf-8".to_string(), Disposition::Inline, &range, rate_limit, downloads.throttle).await
+    }
+    .instrument(downloads.span.0.clone())
+    .await;
+
+    downloads.record("raw", public_id, result.as_ref().map_or_else(DownloadError::status, FileDownload::status), result.as_ref().ok().map(|download| download.size)).await;
+    result
+}
+
+#[derive(Serialize)]
+pub struct ArchiveResponse {
+    success: bool,
+    entries: Vec<ArchiveEntry>,
+    truncated: bool,
+}
+
+#[get("/api/v1/archive/<public_id>")]
+pub async fn list_archive(public_id: &str, _rate_limit: RateLimit<'_>, config: &State<Config>, span: RequestSpan) -> Result<Json<ArchiveResponse>, Status> {
+    async {
+        let file = find_servable_file(config, public_id).await?;
+        let kind = ArchiveKind::for_file(&file.mime_type, &file.file_name).ok_or(Status::NotFound)?;
+
+        let listing = tokio::task::spawn_blocking(move || archive::list_entries(Path::new(&file.file_path), kind))
+            .await
+            .map_err(internal_error("archive listing task failed"))?
+            .map_err(|e| {
+                tracing::warn!(public_id, error = %e, "failed to list archive");
+                Status::UnprocessableEntity
+            })?;
+
+        Ok(Json(ArchiveResponse {
+            success: true,
+            entries: listing.entries,
+            truncated: listing.truncated,
+        }))
+    }
+    .instrument(span.0)
+    .await
+}
+
+pub struct ArchiveMemberDownload {
+    inner: ThrottledReader<MeteredReader<DuplexStream>>,
+    content_disposition: Header<'static>,
+}
+
+impl<'r> Responder<'r, 'static> for ArchiveMemberDownload {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        // The size is only known once extraction finishes, so the body is chunked
+        Response::build()
+            .header(ContentType::Binary)
+            .header(self.content_disposition)
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .streamed_body(self.inner)
+            .ok()
+    }
+}
+
+#[get("/archive/<public_id>?<path>")]
+pub async fn extract_archive_member(public_id: &str, path: &str, rate_limit: RateLimit<'_>, throttle: &State<Throttle>, config: &State<Config>, actor: Actor, span: RequestSpan) -> Result<ArchiveMemberDownload, Status> {
+    let result = async {
+        let file = find_servable_file(config, public_id).await?;
+        let kind = ArchiveKind::for_file(&file.mime_type, &file.file_name).ok_or(Status::NotFound)?;
+        let member = archive::safe_entry_path(path).ok_or(Status::BadRequest)?;
+        let archive_path = PathBuf::from(&file.file_path);
+
+        // Decompress on a blocking thread, piping the output straight to the client. The
+        // lookup happens in the same pass, and the response waits until the member is found.
+        let (reader, writer) = tokio::io::duplex(64 * 1024);
+        let (found_tx, found_rx) = tokio::sync::oneshot::channel();
+        let file_name = member.rsplit('/').next().unwrap_or(&member).to_string();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || span.in_scope(|| {
+            let mut found_tx = Some(found_tx);
+            let result = archive::extract_member(&archive_path, kind, &member, |size| {
+                if let Some(found_tx) = found_tx.take() {
+                    let _ = found_tx.send(Ok(size));
+                }
+                SyncIoBridge::new(writer)
+            });
+            match (result, found_tx) {
+                (Ok(_), _) => {}
+                // Failed before anything was sent, the handler answers with an error status
+                (Err(e), Some(found_tx)) => {
+                    let _ = found_tx.send(Err(e));
+                }
+                (Err(e), None) => {
+                    tracing::warn!(member = %member, path = %archive_path.display(), error = %e, "failed to extract archive member");
+                }
+            }
+        }));
+
+        let size = found_rx
+            .await
+            .map_err(internal_error("archive extraction task failed"))?
+            .map_err(|e| match e {
+                ArchiveError::NotFound => Status::NotFound,
+                ArchiveError::TooLarge => Status::PayloadTooLarge,
+                e => {
+                    tracing::warn!(public_id, error = %e, "failed to read archive");
+                    Status::UnprocessableEntity
+                }
+            })?;
+        rate_limit.charge_bytes(size);
+
+        Ok(ArchiveMemberDownload {
+            inner: throttle.reader(MeteredReader::new(reader), rate_limit.is_authenticated()),
+            content_disposition: Header::new("Content-Disposition", content_disposition(Disposition::Attachment, &file_name)),
+        })
+    }
+    .instrument(span.0.clone())
+    .await;
+
+    audit_access(&span, config, &actor, "archive", public_id, *result.as_ref().err().unwrap_or(&Status::Ok)).await;
+    result
+}
+
+/// Logs why a request failed before answering it with a 500.
+fn internal_error<E: std::fmt::Display>(context: &'static str) -> impl FnOnce(E) -> Status {
+    move |e| {
+        tracing::error!(error = %e, "{}", context);
+        Status::InternalServerError
+    }
+}
+
+/// Looks up a file that may be served to the public, on a blocking thread.
+async fn find_servable_file(config: &Config, public_id: &str) -> Result<File, Status> {
+    // Get file info from database
+    let (database_url, public_id) = (config.database_url.clone(), public_id.to_string());
+    let file = tokio::task::spawn_blocking(move || get_file_by_public_id(&mut establish_connection(&database_url), &public_id))
+        .await
+        .map_err(internal_error("file lookup task failed"))?;
+    let Some(file) = file else {
+        return Err(Status::NotFound);
+    };
+
+    // Only serve files the malware scanner has cleared, if one is configured
+    match ScanStatus::parse(&file.scan_status) {
+        Some(status) if status.is_downloadable() => Ok(file),
+        Some(ScanStatus::Pending) => Err(Status::Locked),
+        _ => Err(Status::Forbidden),
+    }
+}
+
+/// The public id given to a file whose old links used `legacy_id`.
+async fn find_legacy_public_id(config: &Config, legacy_id: &str) -> Result<Option<String>, Status> {
+    let (database_url, legacy_id) = (config.database_url.clone(), legacy_id.to_string());
+    tokio::task::spawn_blocking(move || get_file_by_legacy_id(&mut establish_connection(&database_url), &legacy_id).map(|file| file.public_id))
+        .await
+        .map_err(internal_error("file lookup task failed"))
+}
+
+/// Picks the content type and disposition to serve a file with.
+fn presentation(file: &File, requested: Disposition) -> (String, Disposition) {
+    // Only safe types are shown inline, anything a browser could execute is downloaded
+    match requested {
+        Disposition::Inline => match inline_content_type(&file.mime_type) {
+            Some(content_type) => (content_type, Disposition::Inline),
+            None => (file.mime_type.clone(), Disposition::Attachment),
+        },
+        Disposition::Attachment => (file.mime_type.clone(), Disposition::Attachment),
+    }
+}
+
+async fn serve_file(file: File, content_type: String, disposition: Disposition, range: &RangeHeader, rate_limit: RateLimit<'_>, throttle: &Throttle) -> Result<FileDownload, DownloadError> {
+    // Open file from disk, the body is streamed to the client
+    let file_content = tokio::fs::File::open(&file.file_path)
+        .await
+        .map_err(internal_error("failed to open file"))?;
+
+    // Interrupted downloads can resume from where they stopped
+    let len = file.size as u64;
+    let (start, slice_len, content_range) = match range.range(len) {
+        ByteRange::Full => (0, len, None),
+        ByteRange::Partial { start, end } => (start, end - start + 1, Some(format!("bytes {}-{}/{}", start, end, len))),
+        ByteRange::Unsatisfiable => {
+            return Err(DownloadError::Range(RangeNotSatisfiable {
+                inner: (),
+                content_range: Header::new("Content-Range", format!("bytes */{}", len)),
+            }));
+        }
+    };
+    let slice = FileSlice::new(file_content, start, slice_len)
+        .await
+        .map_err(internal_error("failed to seek file"))?;
+
+    rate_limit.charge_bytes(slice_len);
+
+    // Return file with proper headers
+    Ok(FileDownload {
+        inner: throttle.reader(MeteredReader::new(slice), rate_limit.is_authenticated()),
+        file_id: file.id,
+        size: file.size,
+        start,
+        content_range,
+        content_type: ContentType::parse_flexible(&content_type).unwrap_or(ContentType::Binary),
+        content_disposition: content_disposition(disposition, &file.file_name),
+        content_security_policy: content_security_policy(&content_type),
+    })
+}
+
+#[derive(Responder)]
+pub enum DownloadError {
+    Status(Status),
+    Range(RangeNotSatisfiable),
+    Moved(Box<Redirect>),
+}
+
+impl DownloadError {
+    fn status(&self) -> Status {
+        match self {
+            DownloadError::Status(status) => *status,
+            DownloadError::Range(_) => Status::RangeNotSatisfiable,
+            DownloadError::Moved(_) => Status::PermanentRedirect,
+        }
+    }
+}
+
+impl From<Status> for DownloadError {
+    fn from(status: Status) -> Self {
+        DownloadError::Status(status)
+    }
+}
+
+#[derive(Responder)]
+pub struct HtmlPage {
+    inner: RawHtml<String>,
+    content_security_policy: Header<'static>,
+    content_type_options: Header<'static>,
+}
+
+impl HtmlPage {
+    fn new(html: String) -> Self {
+        HtmlPage {
+            inner: RawHtml(html),
+            content_security_policy: Header::new("Content-Security-Policy", PAGE_CSP),
+            content_type_options: Header::new("X-Content-Type-Options", "nosniff"),
+        }
+    }
+}
+
+/// Page for sharing a file, which chat apps can preview instead of starting a download.
+#[get("/f/<public_id>")]
+pub async fn landing_page(public_id: &str, _rate_limit: RateLimit<'_>, base_url: BaseUrl, config: &State<Config>, span: RequestSpan) -> Result<HtmlPage, Status> {
+    async {
+        let file = find_servable_file(config, public_id).await?;
+        let BaseUrl(base) = base_url;
+
+        let page_url = format!("{}{}", base, uri!(landing_page(public_id)));
+        let download_url = format!("{}{}", base, uri!(download_file(public_id, Option::<&str>::None)));
+        let view_url = inline_content_type(&file.mime_type).map(|_| format!("{}{}", base, uri!(view_file(public_id, Option::<usize>::None))));
+        let thumbnail_url = thumbnail::is_supported(&file.mime_type)
+            .then(|| format!("{}{}", base, uri!(thumbnail_file(public_id, Some(THUMBNAIL_SIZES[2])))));
+
+        let html = render_landing_page(&Landing {
+            file_name: &file.file_name,
+            size: file.size as u64,
+            mime_type: &file.mime_type,
+            page_url: &page_url,
+            download_url: &download_url,
+            view_url: view_url.as_deref(),
+            thumbnail_url: thumbnail_url.as_deref(),
+            sanitized: file.sanitized,
+        });
+        Ok(HtmlPage::new(html))
+    }
+    .instrument(span.0)
+    .await
+}
+
+#[derive(Responder)]
+pub enum PasteView {
+    Page(HtmlPage),
+    Raw(Redirect),
+}
+
+#[get("/p/<public_id>")]
+pub async fn view_paste(public_id: &str, rate_limit: RateLimit<'_>, config: &State<Config>, actor: Actor, span: RequestSpan) -> Result<PasteView, Status> {
+    let result = async {
+        let file = find_servable_file(config, public_id).await?;
+        if !is_textual(&file.mime_type) {
+            return Err(Status::NotFound);
+        }
+
+        // Huge files aren't worth rendering, send them to the plain text view
+        if file.size as u64 > MAX_PASTE_SIZE {
+            return Ok(PasteView::Raw(Redirect::to(uri!(raw_file(public_id)))));
+        }
+
+        let content = tokio::fs::read(&file.file_path).await.map_err(internal_error("failed to read file"))?;
+        rate_limit.charge_bytes(content.len() as u64);
+
+        let text = String::from_utf8_lossy(&content).into_owned();
+        let raw_url = uri!(raw_file(public_id)).to_string();
+
+        // Highlighting is CPU bound, keep it off the async workers
+        let html = tokio::task::spawn_blocking(move || render_paste_page(&text, file.language.as_deref(), &file.file_name, &raw_url))
+            .await
+            .map_err(internal_error("highlighting task failed"))?;
+
+        Ok(PasteView::Page(HtmlPage::new(html)))
+    }
+    .instrument(span.0.clone())
+    .await;
+
+    // Redirects to the raw view are audited there
+    let status = match &result {
+        Ok(PasteView::Page(_)) => Status::Ok,
+        Ok(PasteView::Raw(_)) => Status::SeeOther,
+        Err(status) => *status,
+    };
+    audit_access(&span, config, &actor, "paste", public_id, status).await;
+    result
+}
+
+#[post("/api/v1/paste?<language>&<name>", data = "<data>")]
+pub async fn create_paste(language: Option<&str>, name: Option<&str>, data: Data<'_>, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>, span: RequestSpan) -> Result<Json<UploadResponse>, UploadError> {
+    metered_upload(span, read_paste(language, name, data, rate_limit, uploads)).await
+}
+
+async fn read_paste(language: Option<&str>, name: Option<&str>, data: Data<'_>, rate_limit: RateLimit<'_>, uploads: UploadContext<'_>) -> Result<Json<UploadResponse>, UploadError> {
+    if language.is_some_and(|language| !is_valid_language(language)) {
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "Invalid language".to_string(),
+        }).into());
+    }
+
+    let body = data.open(MAX_PASTE_SIZE.bytes()).into_bytes().await.map_err(|e| {
+        tracing::warn!(error = %e, "failed to read paste body");
+        Json(ErrorResponse {
+            success: false,
+            error: "Failed to read paste".to_string(),
+        })
+    })?;
+
+    if !body.is_complete() {
+        metrics::global().reject(RejectReason::TooLarge);
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "Paste is too large".to_string(),
+        }).into());
+    }
+    let text = String::from_utf8(body.into_inner()).map_err(|e| {
+        tracing::warn!(error = %e, "paste is not UTF-8");
+        Json(ErrorResponse {
+            success: false,
+            error: "Paste must be valid UTF-8 text".to_string(),
+        })
+    })?;
+    if text.is_empty() {
+        return Err(Json(ErrorResponse {
+            success: false,
+            error: "Paste is empty".to_string(),
+        }).into());
+    }
+
+    rate_limit.charge_bytes(text.len() as u64);
+
+    let options = UploadOptions {
+        mime_type: Some("text/plain".to_string()),
+        language: language.map(str::to_string),
+        ..UploadOptions::default()
+    };
+    let file_name = name.filter(|name| !name.trim().is_empty()).unwrap_or("paste.txt").to_string();
+
+    let mut spool = open_spool(&uploads)?;
+    spool.write_all(text.as_bytes()).await.map_err(spool_failed)?;
+    let upload = spool.finish().await.map_err(spool_failed)?;
+
+    process_file_upload(upload, file_name, options, &uploads).await
+}
+
+#[derive(Responder)]
+pub struct ThumbnailImage {
+    inner: Vec<u8>,
+    content_type: ContentType,
+    cache_control: Header<'static>,
+    etag: Header<'static>,
+    content_security_policy: Header<'static>,
+}
+
+#[derive(Responder)]
+pub enum ThumbnailResponse {
+    Image(Box<ThumbnailImage>),
+    #[response(status = 304)]
+    NotModified(()),
+}
+
+#[get("/thumb/<public_id>?<size>")]
+pub async fn thumbnail_file(public_id: &str, size: Option<u32>, if_none_match: IfNoneMatch, config: &State<Config>, span: RequestSpan) -> Result<ThumbnailResponse, Status> {
+    async {
+        // Thumbnails reveal the content, so they follow the same rules as downloads
+        let file = find_servable_file(config, public_id).await?;
+
+        let size = nearest_size(size);
+        let (database_url, file_id) = (config.database_url.clone(), file.id);
+        let thumbnail = tokio::task::spawn_blocking(move || get_thumbnail(&mut establish_connection(&database_url), file_id, size))
+            .await
+            .map_err(internal_error("thumbnail lookup task failed"))?;
+
+        // Unsupported types always get the placeholder, supported ones only until the worker catches up
+        let (etag, cache_control) = match &thumbnail {
+            Some(_) => (format!("\"{}-{}\"", file.public_id, size), "public, max-age=31536000, immutable"),
+            None if thumbnail::is_supported(&file.mime_type) => ("\"placeholder\"".to_string(), "no-cache"),
+            None => ("\"placeholder\"".to_string(), "public, max-age=86400"),
+        };
+
+        if if_none_match.matches(&etag) {
+            return Ok(ThumbnailResponse::NotModified(()));
+        }
+
+        let (inner, content_type) = match thumbnail {
+            Some(thumbnail) => {
+                let data = tokio::fs::read(&thumbnail.file_path).await.map_err(internal_error("failed to read thumbnail"))?;
+                (data, ContentType::parse_flexible(&thumbnail.mime_type).unwrap_or(ContentType::Binary))
+            }
+            None => (PLACEHOLDER_SVG.as_bytes().to_vec(), ContentType::SVG),
+        };
+
+        Ok(ThumbnailResponse::Image(Box::new(ThumbnailImage {
+            inner,
+            content_type,
+            cache_control: Header::new("Cache-Control", cache_control),
+            etag: Header::new("ETag", etag),
+            content_security_policy: Header::new("Content-Security-Policy", SANDBOX_CSP),
+        })))
+    }
+    .instrument(span.0)
+    .await
+}
+
+/// Requests carrying the configured `admin_token` as a bearer token. Admin routes are hidden when it isn't set.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(expected) = req.rocket().state::<Config>().and_then(|config| config.admin_token.as_deref()) else {
+            return request::Outcome::Error((Status::NotFound, ()));
+        };
+
+        if holds_admin_token(req, expected) {
+            return request::Outcome::Success(Admin);
+        }
+        metrics::global().reject(RejectReason::Unauthorized);
+        audit_refusal(req, "admin api").await;
+        request::Outcome::Error((Status::Unauthorized, ()))
+    }
+}
+
+fn holds_admin_token(req: &Request<'_>, expected: &str) -> bool {
+    let token = req.headers().get_one("Authorization").and_then(bearer_token);
+    // Compare digests so the time taken doesn't reveal how much of the token matched
+    token.is_some_and(|token| Sha256::digest(token) == Sha256::digest(expected))
+}
+
+/// The caller of an endpoint restricted to a file's owner, which admins may also use.
+#[derive(Clone)]
+pub struct Caller {
+    actor: Actor,
+    is_admin: bool,
+}
+
+impl Caller {
+    fn may_manage(&self, file: &File) -> bool {
+        self.is_admin || (self.actor.user_id.is_some() && self.actor.user_id == file.owner_id)
+    }
+
+    /// Refuses callers who may not manage `file`, with 401 unless they sent credentials.
+    fn check_manage(&self, file: &File) -> Result<(), Status> {
+        if self.may_manage(file) {
+            return Ok(());
+        }
+        // Anonymous callers may still be the owner once they authenticate
+        Err(if self.actor.user_id.is_none() && !self.is_admin { Status::Unauthorized } else { Status::Forbidden })
+    }
+
+    /// Counts and audits a refusal from [`Caller::check_manage`].
+    async fn audit_refusal(&self, span: &RequestSpan, config: &Config, public_id: &str, status: Status, detail: &str) {
+        if matches!(status.code, 401 | 403) {
+            metrics::global().reject(RejectReason::Unauthorized);
+            audit::record_or_log(config, AuditEvent::AccessDenied, &self.actor, Some(public_id), Some(detail)).instrument(span.0.clone()).await;
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let actor = req.guard::<Actor>().await.expect("Actor is infallible");
+        let admin_token = req.rocket().state::<Config>().and_then(|config| config.admin_token.as_deref());
+        let is_admin = admin_token.is_some_and(|expected| holds_admin_token(req, expected));
+        request::Outcome::Success(Caller { actor, is_admin })
+    }
+}
+
+#[derive(Serialize)]
+pub struct FileInfo {
+    success: bool,
+    public_id: String,
+    slug: Option<String>,
+    file_name: String,
+    size: i64,
+    mime_type: String,
+    created_at: chrono::NaiveDateTime,
+    /// Files are kept until deleted, so this is never set yet.
+    expires_at: Option<chrono::NaiveDateTime>,
+    private: bool,
+    sha256: Option<String>,
+    /// Photo metadata was stripped on upload, so `sha256` is of the cleaned copy.
+    sanitized: bool,
+    /// Only shown to the file's owner and admins.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_count: Option<i32>,
+}
+
+/// Details of a file, for anyone who may download it.
+#[get("/api/v1/files/<public_id>")]
+pub async fn file_info(public_id: &str, _rate_limit: RateLimit<'_>, caller: Caller, config: &State<Config>, stats: &State<DownloadStats>, span: RequestSpan) -> Result<Json<FileInfo>, Status> {
+    async {
+        let file = find_servable_file(config, public_id).await?;
+        let download_count = caller.may_manage(&file).then(|| stats.downloads(&file));
+
+        Ok(Json(FileInfo {
+            success: true,
+            public_id: file.public_id,
+            slug: file.slug,
+            file_name: file.file_name,
+            size: file.size,
+            mime_type: file.mime_type,
+            created_at: file.created_at,
+            expires_at: None,
+            private: file.private,
+            sha256: file.sha256,
+            sanitized: file.sanitized,
+            download_count,
+        }))
+    }
+    .instrument(span.0)
+    .await
+}
+
+/// Download counts of a file, for its owner.
+#[get("/api/v1/files/<public_id>/stats")]
+pub async fn file_download_stats(public_id: &str, caller: Caller, config: &State<Config>, stats: &State<DownloadStats>, span: RequestSpan) -> Result<Json<FileStats>, Status> {
+    let (database_url, stats, owned_id, owner) = (config.database_url.clone(), stats.inner().clone(), public_id.to_string(), caller.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        let mut connection = establish_connection(&database_url);
+        let Some(file) = get_file_by_public_id(&mut connection, &owned_id) else {
+            return Ok(Err(Status::NotFound));
+        };
+        if let Err(status) = owner.check_manage(&file) {
+            return Ok(Err(status));
+        }
+        stats.for_file(&mut connection, &file).map(Ok)
+    })
+    .instrument(span.0.clone())
+    .await
+    .map_err(internal_error("download stats task failed"))?
+    .map_err(internal_error("failed to load download stats"))?;
+
+    if let Err(status) = result {
+        caller.audit_refusal(&span, config, public_id, status, "download stats").await;
+    }
+    result.map(Json)
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SlugRequest {
+    slug: String,
+}
+
+#[derive(Serialize)]
+pub struct SlugResponse {
+    success: bool,
+    slug: Option<String>,
+}
+
+#[derive(Responder)]
+pub enum SlugFailure {
+    #[response(status = 400)]
+    Rejected(Json<ErrorResponse>),
+    #[response(status = 409)]
+    Conflict(Json<ErrorResponse>),
+    Status(Status),
+}
+
+impl From<Status> for SlugFailure {
+    fn from(status: Status) -> Self {
+        SlugFailure::Status(status)
+    }
+}
+
+/// Gives a file a vanity slug its links can use instead of the public id, for its owner.
+#[put("/api/v1/files/<public_id>/slug", data = "<request>", format = "json")]
+pub async fn set_file_slug(public_id: &str, request: Json<SlugRequest>, caller: Caller, config: &State<Config>, span: RequestSpan) -> Result<Json<SlugResponse>, SlugFailure> {
+    change_slug(public_id, Some(request.into_inner().slug), caller, config, span).await
+}
+
+#[delete("/api/v1/files/<public_id>/slug")]
+pub async fn remove_file_slug(public_id: &str, caller: Caller, config: &State<Config>, span: RequestSpan) -> Result<Json<SlugResponse>, SlugFailure> {
+    change_slug(public_id, None, caller, config, span).await
+}
+
+async fn change_slug(public_id: &str, slug: Option<String>, caller: Caller, config: &Config, span: RequestSpan) -> Result<Json<SlugResponse>, SlugFailure> {
+    let (database_url, owned_id, owner, new_slug) = (config.database_url.clone(), public_id.to_string(), caller.clone(), slug.clone());
+    let request_span = span.0.clone();
+    let result = tokio::task::spawn_blocking(move || request_span.in_scope(|| {
+        let mut connection = establish_connection(&database_url);
+        let file = get_file_by_public_id(&mut connection, &owned_id).ok_or(Status::NotFound)?;
+        owner.check_manage(&file)?;
+
+        public_id::set_slug(&mut connection, &file, new_slug.as_deref()).map_err(|e| match e {
+            SlugError::Invalid | SlugError::Reserved => SlugFailure::Rejected(Json(ErrorResponse { success: false, error: e.to_string() })),
+            SlugError::Taken => SlugFailure::Conflict(Json(ErrorResponse { success: false, error: e.to_string() })),
+            SlugError::Database(e) => SlugFailure::Status(internal_error("failed to set slug")(e)),
+        })?;
+
+        let detail = match &new_slug {
+            Some(slug) => format!("set slug '{}'", slug),
+            None => "removed slug".to_string(),
+        };
+        if let Err(e) = audit::record(&mut connection, AuditEvent::PermissionChange, &owner.actor, Some(&file.public_id), Some(&detail)) {
+            tracing::error!(public_id = %file.public_id, error = %e, "failed to write audit event");
+        }
+        Ok(())
+    }))
+    .await
+    .map_err(internal_error("slug task failed"))?;
+
+    if let Err(SlugFailure::Status(status)) = &result {
+        caller.audit_refusal(&span, config, public_id, *status, "slug").await;
+    }
+    result.map(|()| Json(SlugResponse { success: true, slug }))
+}
+
+/// Audits a request refused for lacking the right credentials.
+async fn audit_refusal(req: &Request<'_>, detail: &str) {
+    let (Some(config), request::Outcome::Success(actor)) = (req.rocket().state::<Config>(), req.guard::<Actor>().await) else {
+        return;
+    };
+    let span = req.local_cache(|| RequestSpan(tracing::Span::none()));
+    audit::record_or_log(config, AuditEvent::AccessDenied, &actor, None, Some(detail)).instrument(span.0.clone()).await;
+}
+
+#[post("/api/v1/admin/fsck?<verify_hashes>&<remove_dangling>&<quarantine_orphans>")]
+pub async fn admin_fsck(_admin: Admin, verify_hashes: Option<&str>, remove_dangling: Option<&str>, quarantine_orphans: Option<&str>, config: &State<Config>, span: RequestSpan) -> Result<Json<FsckReport>, Status> {
+    async {
+        let options = FsckOptions {
+            verify_hashes: verify_hashes.is_none_or(|value| flag_enabled(Some(value))),
+            remove_dangling_rows: flag_enabled(remove_dangling),
+            quarantine_orphans: flag_enabled(quarantine_orphans),
+        };
+        let config = Config::clone(config);
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = establish_connection(&config.database_url);
+            fsck::check(&mut connection, &config.upload_dir(), &config.quarantine_dir(), options)
+        })
+        .await
+        .map_err(internal_error("integrity check task failed"))?
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(error = %e, "integrity check failed");
+            Status::InternalServerError
+        })
+    }
+    .instrument(span.0)
+    .await
+}
+
+#[derive(Serialize)]
+pub struct AuditPage {
+    events: Vec<AuditEntry>,
+    /// Pass as `before` to get the next, older page. Unset on the last page.
+    next_before: Option<i32>,
+}
+
+/// Audit filters shared by the query and export endpoints.
+#[derive(FromForm)]
+pub struct AuditQuery<'r> {
+    event: Option<&'r str>,
+    user_id: Option<i32>,
+    file: Option<&'r str>,
+    client_ip: Option<&'r str>,
+    since: Option<&'r str>,
+    until: Option<&'r str>,
+}
+
+impl AuditQuery<'_> {
+    fn filter(&self) -> Result<AuditFilter, BadRequest> {
+        let time = |name: &str, value: Option<&str>| match value {
+            Some(value) => audit::parse_time(value)
+                .map(Some)
+                .ok_or_else(|| BadRequest::new(format!("`{}` must be a date or an RFC 3339 time", name))),
+            None => Ok(None),
+        };
+        let event = match self.event {
+            Some(event) => Some(AuditEvent::parse(event).ok_or_else(|| BadRequest::new(format!("unknown event '{}'", event)))?),
+            None => None,
+        };
+
+        Ok(AuditFilter {
+            event,
+            user_id: self.user_id,
+            file_hash: self.file.map(str::to_string),
+            client_ip: self.client_ip.map(str::to_string),
+            since: time("since", self.since)?,
+            until: time("until", self.until)?,
+        })
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 400)]
+pub struct BadRequest(Json<ErrorResponse>);
+
+impl BadRequest {
+    fn new(error: String) -> Self {
+        BadRequest(Json(ErrorResponse { success: false, error }))
+    }
+}
+
+#[derive(Responder)]
+pub enum AuditError {
+    BadRequest(BadRequest),
+    Status(Status),
+}
+
+impl From<BadRequest> for AuditError {
+    fn from(error: BadRequest) -> Self {
+        AuditError::BadRequest(error)
+    }
+}
+
+impl From<Status> for AuditError {
+    fn from(status: Status) -> Self {
+        AuditError::Status(status)
+    }
+}
+
+/// Audit events newest first, filtered and paged with `before` and `limit`.
+#[get("/api/v1/admin/audit?<before>&<limit>&<query..>")]
+pub async fn admin_audit(_admin: Admin, query: AuditQuery<'_>, before: Option<i32>, limit: Option<i64>, config: &State<Config>) -> Result<Json<AuditPage>, AuditError> {
+    let filter = query.filter()?;
+    let limit = limit.unwrap_or(100).clamp(1, audit::MAX_PAGE_SIZE);
+    let database_url = config.database_url.clone();
+
+    let events = tokio::task::spawn_blocking(move || audit::query(&mut establish_connection(&database_url), &filter, before, limit))
+        .await
+        .map_err(internal_error("audit query task failed"))?
+        .map_err(internal_error("failed to query audit events"))?;
+
+    let next_before = (events.len() as i64 == limit).then(|| events.last().map(|event| event.id)).flatten();
+    Ok(Json(AuditPage { events, next_before }))
+}
+
+/// Every matching audit event oldest first, as JSON lines.
+#[get("/api/v1/admin/audit/export?<query..>")]
+pub async fn admin_audit_export(_admin: Admin, query: AuditQuery<'_>, config: &State<Config>) -> Result<(ContentType, Vec<u8>), AuditError> {
+    let filter = query.filter()?;
+    let database_url = config.database_url.clone();
+
+    let lines = tokio::task::spawn_blocking(move || {
+        let mut lines = Vec::new();
+        audit::export(&mut establish_connection(&database_url), &filter, &mut lines).map(|_| lines)
+    })
+    .await
+    .map_err(internal_error("audit export task failed"))?
+    .map_err(internal_error("failed to export audit events"))?;
+
+    Ok((ContentType::new("application", "x-ndjson"), lines))
+}
+
+/// Requests allowed to scrape `/metrics`, hidden entirely when metrics are disabled.
+pub struct MetricsScraper;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MetricsScraper {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(config) = req.rocket().state::<Config>().map(|config| &config.metrics) else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        if !config.enabled {
+            return request::Outcome::Error((Status::NotFound, ()));
+        }
+        let Some(expected) = config.token.as_deref() else {
+            return request::Outcome::Success(MetricsScraper);
+        };
+
+        match req.headers().get_one("Authorization").and_then(bearer_token) {
+            Some(token) if Sha256::digest(token) == Sha256::digest(expected) => request::Outcome::Success(MetricsScraper),
+            _ => {
+                metrics::global().reject(RejectReason::Unauthorized);
+                audit_refusal(req, "metrics").await;
+                request::Outcome::Error((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+#[get("/metrics")]
+pub async fn metrics_endpoint(_scraper: MetricsScraper, config: &State<Config>, span: RequestSpan) -> (ContentType, String) {
+    async {
+        let metrics = metrics::global();
+
+        // Stored totals come from the database so deletions by the CLI are reflected
+        let database_url = config.database_url.clone();
+        match tokio::task::spawn_blocking(move || file_stats(&mut establish_connection(&database_url))).await {
+            Ok(Ok((files, bytes))) => {
+                metrics.stored_files.set(files);
+                metrics.stored_bytes.set(bytes);
+            }
+            Ok(Err(e)) => tracing::error!(error = %e, "failed to count stored files"),
+            Err(e) => tracing::error!(error = %e, "failed to count stored files"),
+        }
+
+        (ContentType::new("text", "plain").with_params(("version", "0.0.4")), metrics.encode())
+    }
+    .instrument(span.0)
+    .await
+}
+
+#[derive(Serialize)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+/// Answers as long as the process is serving requests, without touching anything else.
+#[get("/healthz")]
+pub fn healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+/// Whether this instance can take uploads, with the result of each check.
+#[get("/readyz")]
+pub async fn readyz(config: &State<Config>) -> (Status, Json<Readiness>) {
+    let config = Config::clone(config);
+    let readiness = match tokio::task::spawn_blocking(move || health::check_readiness(&config)).await {
+        Ok(readiness) => readiness,
+        Err(e) => {
+            tracing::error!(error = %e, "readiness check task failed");
+            Readiness { ready: false, checks: Vec::new() }
+        }
+    };
+
+    if readiness.ready {
+        (Status::Ok, Json(readiness))
+    } else {
+        let failed: Vec<&str> = readiness.checks.iter().filter(|check| !check.ok).map(|check| check.name).collect();
+        tracing::warn!(failed = ?failed, "not ready");
+        (Status::ServiceUnavailable, Json(readiness))
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 429)]
+pub struct TooManyRequests {
+    inner: Json<ErrorResponse>,
+    retry_after: Header<'static>,
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> TooManyRequests {
+    let retry_after = req.local_cache(RetryAfter::default).seconds();
+    metrics::global().reject(RejectReason::RateLimited);
+
+    TooManyRequests {
+        inner: Json(ErrorResponse {
+            success: false,
+            error: format!("Too many requests, retry in {} seconds", retry_after),
+        }),
+        retry_after: Header::new("Retry-After", retry_after.to_string()),
+    }
+}
+
+#[get("/")]
+pub fn index() -> RawHtml<&'static str> {
+    RawHtml(ASSETS.get_file("index.html").map_or("Not found", |f| std::str::from_utf8(f.contents()).unwrap_or("Invalid UTF-8")))
+}
+
+/// Builds the server from the configuration file and environment.
+pub fn rocket() -> Result<Rocket<Build>, StartupError> {
+    build(Config::figment())
+}
+
+/// Why the server can't be built.
+#[derive(Debug)]
+pub enum StartupError {
+    Config(ConfigError),
+    /// Logging isn't set up yet, so this has to go to stderr.
+    Logging(String),
+    Migrations(Box<dyn std::error::Error + Send + Sync>),
+    Cors(rocket_cors::Error),
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartupError::Config(e) => write!(f, "Invalid configuration: {}", e),
+            StartupError::Logging(e) => write!(f, "Invalid log configuration: {}", e),
+            StartupError::Migrations(e) => write!(f, "failed to run migrations: {}", e),
+            StartupError::Cors(e) => write!(f, "invalid CORS configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Scans files a previous run left `pending`, whose scan task never finished.
+fn resume_pending_scans(scanner: &dyn Scanner, database_url: &str, quarantine_dir: &Path, started: chrono::NaiveDateTime) {
+    let mut connection = establish_connection(database_url);
+    let files = match scan::files_to_rescan(&mut connection, &[ScanStatus::Pending], started) {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to look up pending scans");
+            return;
+        }
+    };
+    if !files.is_empty() {
+        tracing::info!(files = files.len(), "resuming pending scans");
+    }
+    for file in files {
+        if let Err(e) = scan_and_record(&mut connection, scanner, &file, quarantine_dir) {
+            tracing::error!(file_hash = %file.file_hash, error = %e, "failed to scan file");
+        }
+    }
+}
+
+/// Queues thumbnails for images uploaded before `started` that have none.
+fn resume_thumbnails(queue: &ThumbnailQueue, database_url: &str, started: chrono::NaiveDateTime) {
+    let files = match thumbnail::files_missing_thumbnails(&mut establish_connection(database_url), started) {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to look up missing thumbnails");
+            return;
+        }
+    };
+    if !files.is_empty() {
+        tracing::info!(files = files.len(), "resuming thumbnail generation");
+    }
+    for file_id in files {
+        queue.enqueue(file_id);
+    }
+}
+
+/// Builds the server from `figment`, which also configures Rocket itself.
+pub fn build(figment: Figment) -> Result<Rocket<Build>, StartupError> {
+    let config = Config::from_figment(&figment).map_err(StartupError::Config)?;
+    logging::init(&config.logging).map_err(StartupError::Logging)?;
+
+    // Run database migrations on startup
+    run_migrations(&config.database_url).map_err(StartupError::Migrations)?;
+
+    let cors = config.cors.fairing(config.public_origin()).map_err(StartupError::Cors)?;
+
+    // Checked by `Config::validated`
+    let scanner = UploadScanner::from_config(&config.scanner).unwrap_or_default();
+    let (thumbnails, thumbnail_jobs) = ThumbnailQueue::new();
+    let (database_url, thumbnail_dir) = (config.database_url.clone(), config.thumbnail_dir());
+    let missing_thumbnails = (thumbnails.clone(), config.database_url.clone(), chrono::Utc::now().naive_utc());
+    let download_stats = DownloadStats::new(config.download_stats.clone());
+    let (stats_flusher, stats_database_url) = (download_stats.clone(), config.database_url.clone());
+    let pending_scans = (scanner.0.clone(), config.database_url.clone(), config.quarantine_dir(), chrono::Utc::now().naive_utc());
+
+    let rocket = rocket::custom(figment)
+        .mount("/", routes![index, static_files, upload_file, download_file, download_file_head, view_file, raw_file, thumbnail_file, landing_page, view_paste, create_paste, list_archive, extract_archive_member, put_upload, put_upload_root, post_raw_upload, admin_fsck, admin_audit, admin_audit_export, metrics_endpoint, healthz, readyz, file_info, file_download_stats, set_file_slug, remove_file_slug])
+        .register("/", catchers![too_many_requests])
+        .attach(RequestTracing)
+        .manage(RateLimiter::new(config.rate_limit.clone()))
+        .manage(Throttle::new(config.throttle.clone()))
+        .manage(config.upload_policy.clone())
+        .manage(scanner)
+        .manage(thumbnails)
+        .manage(config.metadata.strip)
+        .manage(config)
+        .manage(download_stats)
+        .attach(AdHoc::on_liftoff("Thumbnail worker", move |_| Box::pin(async move {
+            tokio::spawn(thumbnail::run_worker(thumbnail_jobs, database_url, thumbnail_dir));
+            let (queue, database_url, started) = missing_thumbnails;
+            tokio::task::spawn_blocking(move || resume_thumbnails(&queue, &database_url, started));
+        })))
+        .attach(AdHoc::on_liftoff("Download stats", |_| Box::pin(async move {
+            tokio::spawn(stats_flusher.run_flusher(stats_database_url));
+        })))
+        .attach(AdHoc::on_liftoff("Pending scans", |_| Box::pin(async move {
+            if let (Some(scanner), database_url, quarantine_dir, started) = pending_scans {
+                tokio::task::spawn_blocking(move || resume_pending_scans(scanner.as_ref(), &database_url, &quarantine_dir, started));
+            }
+        })))
+        // Counts since the last flush would otherwise be lost on a clean shutdown
+        .attach(AdHoc::on_shutdown("Download stats", |rocket| Box::pin(async move {
+            if let (Some(stats), Some(config)) = (rocket.state::<DownloadStats>(), rocket.state::<Config>()) {
+                stats.flush_in_background(&config.database_url).await;
+            }
+        })));
+
+    // Without a CORS fairing browsers keep the API to same-origin pages
+    Ok(match cors {
+        Some(cors) => rocket.attach(cors),
+        None => rocket,
+    })
+}
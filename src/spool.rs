@@ -0,0 +1,92 @@
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use sha2::{Digest, Sha256};
+use tempfile::TempPath;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::checksum::Sha256Digest;
+use crate::filetype::SNIFF_LEN;
+
+/// A temporary file an upload is written to as it arrives, hashed on the way.
+///
+/// The file is deleted when dropped, unless it is finished and persisted.
+pub struct Spool {
+    file: tokio::fs::File,
+    path: TempPath,
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: u64,
+}
+
+impl Spool {
+    /// Creates an empty spool file in `dir`, so it can be renamed into place.
+    pub fn create(dir: &Path) -> io::Result<Spool> {
+        let (file, path) = tempfile::Builder::new().prefix(".upload-").tempfile_in(dir)?.into_parts();
+        Ok(Spool {
+            file: tokio::fs::File::from_std(file),
+            path,
+            hasher: Sha256::new(),
+            head: Vec::new(),
+            size: 0,
+        })
+    }
+
+    /// Flushes what was written and returns the finished file.
+    pub async fn finish(mut self) -> io::Result<Spooled> {
+        self.file.flush().await?;
+        Ok(Spooled {
+            size: self.size,
+            sha256: self.hasher.finalize().into(),
+            head: self.head,
+            path: self.path,
+        })
+    }
+}
+
+impl AsyncWrite for Spool {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = match Pin::new(&mut this.file).poll_write(cx, buf) {
+            Poll::Ready(Ok(written)) => written,
+            other => return other,
+        };
+
+        let chunk = &buf[..written];
+        this.hasher.update(chunk);
+        let wanted = SNIFF_LEN.saturating_sub(this.head.len()).min(chunk.len());
+        this.head.extend_from_slice(&chunk[..wanted]);
+        this.size += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+/// A fully written [`Spool`].
+pub struct Spooled {
+    pub size: u64,
+    pub sha256: Sha256Digest,
+    /// The first bytes of the file, enough to sniff its type.
+    pub head: Vec<u8>,
+    path: TempPath,
+}
+
+impl Spooled {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file to `destination`, which should be in the same directory.
+    pub fn persist(self, destination: &Path) -> io::Result<()> {
+        self.path.persist(destination).map_err(|e| e.error)
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::establish_connection;
+use crate::models::File;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DownloadStatsConfig {
+    /// Also keep a count per file and UTC day.
+    pub per_day: bool,
+    /// Seconds between writes of the counters to the database.
+    pub flush_interval: u64,
+}
+
+impl Default for DownloadStatsConfig {
+    fn default() -> Self {
+        DownloadStatsConfig {
+            per_day: true,
+            flush_interval: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Pending {
+    downloads: i32,
+    bytes: i64,
+    last_downloaded_at: Option<NaiveDateTime>,
+    days: HashMap<NaiveDate, i32>,
+}
+
+impl Pending {
+    fn merge(&mut self, other: Pending) {
+        self.downloads += other.downloads;
+        self.bytes += other.bytes;
+        self.last_downloaded_at = self.last_downloaded_at.max(other.last_downloaded_at);
+        for (day, downloads) in other.days {
+            *self.days.entry(day).or_default() += downloads;
+        }
+    }
+}
+
+/// Download counters kept in memory and written to the database in batches,
+/// so serving a file never waits on a write.
+#[derive(Clone, Default)]
+pub struct DownloadStats {
+    config: DownloadStatsConfig,
+    pending: Arc<Mutex<HashMap<i32, Pending>>>,
+}
+
+impl DownloadStats {
+    pub fn new(config: DownloadStatsConfig) -> DownloadStats {
+        DownloadStats {
+            config,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Records `bytes` served from a file. Only responses from the start of the
+    /// file count as a download, so resumed transfers aren't counted twice.
+    pub fn record(&self, file_id: i32, from_start: bool, bytes: u64, now: NaiveDateTime) {
+        let mut pending = self.pending.lock().unwrap();
+        let entry = pending.entry(file_id).or_default();
+        entry.bytes += bytes as i64;
+        if from_start {
+            entry.downloads += 1;
+            entry.last_downloaded_at = entry.last_downloaded_at.max(Some(now));
+            if self.config.per_day {
+                *entry.days.entry(now.date()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Starts counting a download at `now`, recorded once its body is finished or dropped.
+    pub fn start(&self, file_id: i32, from_start: bool, now: NaiveDateTime) -> CountedDownload {
+        CountedDownload {
+            stats: self.clone(),
+            file_id,
+            from_start,
+            started: now,
+        }
+    }
+
+    /// Writes the pending counters in one transaction, returning how many files were updated.
+    ///
+    /// Counters are kept for the next flush if the write fails.
+    pub fn flush(&self, conn: &mut SqliteConnection) -> QueryResult<usize> {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let _timer = crate::metrics::global().db_timer("flush_download_stats");
+        let result = conn.transaction(|conn| {
+            for (file_id, pending) in &batch {
+                write_pending(conn, *file_id, pending)?;
+            }
+            Ok(batch.len())
+        });
+
+        if result.is_err() {
+            let mut current = self.pending.lock().unwrap();
+            for (file_id, pending) in batch {
+                current.entry(file_id).or_default().merge(pending);
+            }
+        }
+        result
+    }
+
+    /// Flushes every `flush_interval` seconds, for as long as the server runs.
+    pub async fn run_flusher(self, database_url: String) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.flush_interval));
+        loop {
+            interval.tick().await;
+            self.flush_in_background(&database_url).await;
+        }
+    }
+
+    /// Flushes on a blocking thread, logging failures.
+    pub async fn flush_in_background(&self, database_url: &str) {
+        let (stats, database_url) = (self.clone(), database_url.to_string());
+        match tokio::task::spawn_blocking(move || stats.flush(&mut establish_connection(&database_url))).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::error!(error = %e, "failed to save download stats"),
+            Err(e) => tracing::error!(error = %e, "download stats task failed"),
+        }
+    }
+
+    /// How often `file` was downloaded, including downloads not yet flushed.
+    pub fn downloads(&self, file: &File) -> i32 {
+        let pending = self.pending.lock().unwrap().get(&file.id).map_or(0, |pending| pending.downloads);
+        file.download_count + pending
+    }
+
+    /// Statistics for `file` including counts not yet flushed.
+    pub fn for_file(&self, conn: &mut SqliteConnection, file: &File) -> QueryResult<FileStats> {
+        use crate::schema::daily_downloads;
+
+        let mut days: HashMap<NaiveDate, i32> = HashMap::new();
+        if self.config.per_day {
+            let rows = daily_downloads::table
+                .filter(daily_downloads::file_id.eq(file.id))
+                .select((daily_downloads::day, daily_downloads::downloads))
+                .load::<(NaiveDate, i32)>(conn)?;
+            days.extend(rows);
+        }
+
+        let mut stats = Pending {
+            downloads: file.download_count,
+            bytes: file.bytes_served,
+            last_downloaded_at: file.last_downloaded_at,
+            days,
+        };
+        if let Some(pending) = self.pending.lock().unwrap().get(&file.id) {
+            stats.merge(pending.clone());
+        }
+
+        let mut daily: Vec<DailyDownloads> = stats
+            .days
+            .into_iter()
+            .map(|(day, downloads)| DailyDownloads { day, downloads })
+            .collect();
+        daily.sort_by_key(|entry| entry.day);
+
+        Ok(FileStats {
+            downloads: stats.downloads,
+            bytes_served: stats.bytes,
+            last_downloaded_at: stats.last_downloaded_at,
+            daily: self.config.per_day.then_some(daily),
+        })
+    }
+}
+
+/// A download in progress, see [`DownloadStats::start`].
+pub struct CountedDownload {
+    stats: DownloadStats,
+    file_id: i32,
+    from_start: bool,
+    started: NaiveDateTime,
+}
+
+impl CountedDownload {
+    /// Records the download with the bytes that were actually sent.
+    pub fn finish(self, bytes: u64) {
+        self.stats.record(self.file_id, self.from_start, bytes, self.started);
+    }
+}
+
+fn write_pending(conn: &mut SqliteConnection, file_id: i32, pending: &Pending) -> QueryResult<()> {
+    use crate::schema::{daily_downloads, files};
+
+    // A file deleted since the download has no row left to count against
+    let updated = diesel::update(files::table.find(file_id))
+        .set((
+            files::download_count.eq(files::download_count + pending.downloads),
+            files::bytes_served.eq(files::bytes_served + pending.bytes),
+        ))
+        .execute(conn)?;
+    if updated == 0 {
+        return Ok(());
+    }
+    if let Some(last) = pending.last_downloaded_at {
+        diesel::update(files::table.find(file_id))
+            .set(files::last_downloaded_at.eq(last))
+            .execute(conn)?;
+    }
+
+    for (day, downloads) in &pending.days {
+        diesel::insert_into(daily_downloads::table)
+            .values((
+                daily_downloads::file_id.eq(file_id),
+                daily_downloads::day.eq(day),
+                daily_downloads::downloads.eq(downloads),
+            ))
+            .on_conflict((daily_downloads::file_id, daily_downloads::day))
+            .do_update()
+            .set(daily_downloads::downloads.eq(daily_downloads::downloads + downloads))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DailyDownloads {
+    pub day: NaiveDate,
+    pub downloads: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FileStats {
+    /// Responses that started at the beginning of the file.
+    pub downloads: i32,
+    /// Bytes sent, including partial and resumed downloads.
+    pub bytes_served: i64,
+    pub last_downloaded_at: Option<NaiveDateTime>,
+    /// Oldest day first, unset when per-day counts are disabled.
+    pub daily: Option<Vec<DailyDownloads>>,
+}
@@ -1,11 +1,28 @@
 #[cfg(test)]
+pub(crate) mod support {
+    use crate::server;
+    use rocket::figment::{Figment, Provider};
+    use rocket::local::blocking::Client;
+
+    /// A client for the whole server with logging off, configured by `overrides`:
+    /// a pair such as `("data_dir", dir.path())` or a `Figment` merging several.
+    pub fn test_client(overrides: impl Provider) -> Client {
+        let figment = Figment::from(rocket::Config::debug_default())
+            .merge(("log_level", "off"))
+            .merge(("logging.filter", "off"))
+            .merge(overrides);
+        Client::untracked(server::build(figment).unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use tempfile::TempDir;
     use std::fs;
     use std::env;
     use serial_test::serial;
     use sha2::{Sha256, Digest};
-    use hex;
 
     fn setup_test_env() -> TempDir {
         let temp_dir = TempDir::new().expect("Failed to create temp directory");
@@ -214,36 +231,24 @@ mod tests {
 
 #[cfg(test)]
 mod database_tests {
-    use crate::{establish_connection, create_file, get_file_by_hash};
+    use crate::{establish_connection, create_file, get_file_by_hash, MIGRATIONS};
     use crate::models::NewFile;
     use diesel::prelude::*;
-    use std::env;
+    use diesel_migrations::MigrationHarness;
     use serial_test::serial;
 
     fn setup_test_database() -> SqliteConnection {
-        unsafe {
-            env::set_var("DATABASE_URL", ":memory:");
-        }
-        let mut conn = establish_connection();
-
-        // Run migrations manually for in-memory database
-        diesel::sql_query(
-            "CREATE TABLE files (
-                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-                file_hash VARCHAR NOT NULL,
-                file_name VARCHAR NOT NULL,
-                file_path VARCHAR NOT NULL,
-                size INTEGER NOT NULL,
-                private BOOLEAN NOT NULL DEFAULT 1,
-                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
-            )"
-        ).execute(&mut conn).expect("Failed to create table");
+        let mut conn = establish_connection(":memory:");
+
+        // Run migrations for the in-memory database
+        conn.run_pending_migrations(MIGRATIONS).expect("Failed to run migrations");
 
         conn
     }
 
     #[test]
     #[serial]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_create_file_in_database() {
         let mut conn = setup_test_database();
 
@@ -253,9 +258,16 @@ mod database_tests {
             file_path: "/tmp/test_file",
             size: 1024,
             private: true,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "test_hash_123456789",
         };
 
-        let created_file = create_file(&mut conn, new_file);
+        let created_file = create_file(&mut conn, new_file).unwrap();
 
         assert_eq!(created_file.file_hash, "test_hash_123456789");
         assert_eq!(created_file.file_name, "test_file");
@@ -276,9 +288,16 @@ mod database_tests {
             file_path: "/tmp/existing_file",
             size: 2048,
             private: false,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "existing_hash_123",
         };
 
-        let created_file = create_file(&mut conn, new_file);
+        let created_file = create_file(&mut conn, new_file).unwrap();
         let retrieved_file = get_file_by_hash(&mut conn, "existing_hash_123");
 
         assert!(retrieved_file.is_some());
@@ -286,6 +305,7 @@ mod database_tests {
         assert_eq!(file.id, created_file.id);
         assert_eq!(file.file_hash, "existing_hash_123");
         assert_eq!(file.file_name, "existing_file");
+        assert_eq!(file.mime_type, "application/octet-stream");
     }
 
     #[test]
@@ -299,6 +319,7 @@ mod database_tests {
 
     #[test]
     #[serial]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_create_multiple_files() {
         let mut conn = setup_test_database();
 
@@ -308,6 +329,13 @@ mod database_tests {
             file_path: "/tmp/file1",
             size: 100,
             private: true,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "hash1",
         };
 
         let file2 = NewFile {
@@ -316,10 +344,17 @@ mod database_tests {
             file_path: "/tmp/file2",
             size: 200,
             private: false,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "hash2",
         };
 
-        let created1 = create_file(&mut conn, file1);
-        let created2 = create_file(&mut conn, file2);
+        let created1 = create_file(&mut conn, file1).unwrap();
+        let created2 = create_file(&mut conn, file2).unwrap();
 
         assert_ne!(created1.id, created2.id);
 
@@ -331,4 +366,3195 @@ mod database_tests {
         assert_eq!(retrieved1.private, true);
         assert_eq!(retrieved2.private, false);
     }
+
+    #[test]
+    #[serial]
+    fn test_sizes_over_2_gib() {
+        let mut conn = setup_test_database();
+        let size = 5 * 1024 * 1024 * 1024_i64;
+
+        create_file(&mut conn, NewFile {
+            file_hash: "large_hash",
+            file_name: "disk.img",
+            file_path: "/tmp/disk.img",
+            size,
+            private: false,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "large_hash",
+        }).unwrap();
+
+        assert_eq!(get_file_by_hash(&mut conn, "large_hash").unwrap().size, size);
+        assert_eq!(crate::file_stats(&mut conn).unwrap(), (1, size));
+    }
+}
+
+#[cfg(test)]
+mod ratelimit_tests {
+    use crate::ratelimit::{
+        bearer_token, parse_trusted_proxies, resolve_client_ip, ClientKey, RateLimitConfig,
+        RateLimiter, RetryAfter, TokenBucket,
+    };
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_token_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 1.0, start);
+
+        assert!(bucket.try_take(1.0, start).is_ok());
+        assert!(bucket.try_take(1.0, start).is_ok());
+
+        let wait = bucket.try_take(1.0, start).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        assert!(bucket.try_take(1.0, start + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_token_bucket_debt() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(100.0, 10.0, start);
+
+        assert!(bucket.debt_wait(start).is_none());
+        bucket.charge(150.0, start);
+        assert_eq!(bucket.debt_wait(start), Some(Duration::from_secs(5)));
+        assert!(bucket.debt_wait(start + Duration::from_secs(5)).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter_request_budget() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_minute: 60,
+            request_burst: 2,
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        let client = [ClientKey::Ip(ip("203.0.113.7"))];
+        let other = [ClientKey::Ip(ip("203.0.113.8"))];
+
+        assert!(limiter.check(&client, start).is_ok());
+        assert!(limiter.check(&client, start).is_ok());
+        assert_eq!(limiter.check(&client, start), Err(Duration::from_secs(1)));
+
+        // Other clients have their own budget
+        assert!(limiter.check(&other, start).is_ok());
+        assert!(limiter.check(&client, start + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limiter_token_and_ip_budgets_combine() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_minute: 60,
+            request_burst: 1,
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        let ip_key = ClientKey::Ip(ip("198.51.100.1"));
+
+        assert!(limiter.check(&[ip_key.clone(), ClientKey::Token(1)], start).is_ok());
+        // A fresh token doesn't lift the exhausted IP budget
+        assert!(limiter.check(&[ip_key, ClientKey::Token(2)], start).is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_bandwidth_budget() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_minute: 0,
+            bytes_per_minute: 600,
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        let client = [ClientKey::Ip(ip("192.0.2.1"))];
+
+        assert!(limiter.check(&client, start).is_ok());
+        limiter.charge_bytes(&client, 700, start);
+        assert_eq!(limiter.check(&client, start), Err(Duration::from_secs(10)));
+        assert!(limiter.check(&client, start + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_client_ip() {
+        let entries = |values: &[&str]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>();
+        assert!(parse_trusted_proxies(&entries(&["10.0.0.0/8", "not-an-ip"])).is_err());
+        let trusted = parse_trusted_proxies(&entries(&["10.0.0.0/8", " 192.0.2.10"])).unwrap();
+        assert_eq!(trusted.len(), 2);
+
+        // Untrusted peers can't spoof their address
+        assert_eq!(
+            resolve_client_ip(ip("203.0.113.5"), Some("1.2.3.4"), &trusted),
+            ip("203.0.113.5")
+        );
+        // Trusted proxies are skipped from the right
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), Some("1.2.3.4, 203.0.113.9, 10.1.1.1"), &trusted),
+            ip("203.0.113.9")
+        );
+        assert_eq!(resolve_client_ip(ip("192.0.2.10"), None, &trusted), ip("192.0.2.10"));
+        assert_eq!(
+            resolve_client_ip(ip("192.0.2.10"), Some("garbage"), &trusted),
+            ip("192.0.2.10")
+        );
+    }
+
+    #[test]
+    fn test_bearer_token_and_retry_after() {
+        assert_eq!(bearer_token("Bearer abc123"), Some("abc123"));
+        assert_eq!(bearer_token("bearer  abc123 "), Some("abc123"));
+        assert_eq!(bearer_token("Basic abc123"), None);
+        assert_eq!(bearer_token("Bearer "), None);
+
+        assert_eq!(RetryAfter(Some(Duration::from_millis(1500))).seconds(), 2);
+        assert_eq!(RetryAfter(Some(Duration::ZERO)).seconds(), 1);
+        assert_eq!(RetryAfter(None).seconds(), 1);
+    }
+}
+
+#[cfg(test)]
+mod throttle_tests {
+    use crate::auth::{create_token, create_user};
+    use crate::establish_connection;
+    use crate::tests::support::test_client;
+    use crate::throttle::{Throttle, ThrottleConfig};
+    use rocket::figment::Figment;
+    use rocket::http::Header;
+    use serial_test::serial;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+    use tokio::time::{Duration, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_reader_limits_rate() {
+        let throttle = Throttle::new(ThrottleConfig {
+            anonymous_bytes_per_sec: 1000,
+            ..ThrottleConfig::default()
+        });
+        let data = vec![7u8; 3000];
+
+        let start = Instant::now();
+        let mut output = Vec::new();
+        throttle
+            .reader(&data[..], false)
+            .read_to_end(&mut output)
+            .await
+            .unwrap();
+
+        // One second of burst is available immediately, the rest at 1000 bytes/s
+        assert_eq!(output, data);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_reader_tiers() {
+        let throttle = Throttle::new(ThrottleConfig {
+            anonymous_bytes_per_sec: 100,
+            authenticated_bytes_per_sec: 0,
+            ..ThrottleConfig::default()
+        });
+        let data = vec![1u8; 10_000];
+
+        let start = Instant::now();
+        let mut output = Vec::new();
+        throttle
+            .reader(&data[..], true)
+            .read_to_end(&mut output)
+            .await
+            .unwrap();
+
+        assert_eq!(output.len(), data.len());
+        assert!(start.elapsed() < Duration::from_millis(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_budget_is_shared() {
+        let throttle = Throttle::new(ThrottleConfig {
+            global_bytes_per_sec: 1000,
+            ..ThrottleConfig::default()
+        });
+        let data = vec![0u8; 1500];
+
+        let start = Instant::now();
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        throttle.reader(&data[..], false).read_to_end(&mut first).await.unwrap();
+        throttle.reader(&data[..], true).read_to_end(&mut second).await.unwrap();
+
+        // 3000 bytes through a 1000 bytes/s budget with a one second burst
+        assert_eq!(first.len() + second.len(), 3000);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[test]
+    #[serial]
+    fn test_made_up_token_gets_anonymous_rate() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(
+            Figment::new()
+                .merge(("data_dir", data.path()))
+                .merge(("throttle.anonymous_bytes_per_sec", 2000)),
+        );
+        let token = {
+            let mut conn = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+            create_user(&mut conn, "alice").unwrap();
+            create_token(&mut conn, "alice", "laptop").unwrap().1
+        };
+        let url = client.put("/api/v1/upload/big.bin").body(vec![1u8; 5000]).dispatch().into_string().unwrap();
+        let path = url[url.find("/download/").unwrap()..].trim().to_string();
+
+        let download = |token: &str| {
+            let start = std::time::Instant::now();
+            let response = client.get(path.as_str()).header(Header::new("Authorization", format!("Bearer {}", token))).dispatch();
+            assert_eq!(response.into_bytes().unwrap().len(), 5000);
+            start.elapsed()
+        };
+
+        // 5000 bytes at 2000 bytes/s with a one second burst
+        assert!(download("nd_made-up-token") >= std::time::Duration::from_secs(1));
+        assert!(download(&token) < std::time::Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod filetype_tests {
+    use crate::filetype::{sniff_mime_type, PolicyError, UploadPolicy};
+
+    #[test]
+    fn test_sniff_prefers_magic_bytes() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(sniff_mime_type(png, "holiday.txt"), "image/png");
+
+        let mut elf = b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0\x03\0>\0\x01\0\0\0".to_vec();
+        elf.resize(64, 0);
+        assert_eq!(sniff_mime_type(&elf, "innocent.pdf"), "application/x-executable");
+    }
+
+    #[test]
+    fn test_sniff_falls_back_to_extension_and_content() {
+        assert_eq!(sniff_mime_type(b"a,b\n1,2\n", "export.csv"), "text/csv");
+        assert_eq!(sniff_mime_type(b"just some notes", "notes"), "text/plain");
+        assert_eq!(sniff_mime_type(b"", "empty"), "application/octet-stream");
+
+        // An extension doesn't change what binary data is
+        assert_eq!(sniff_mime_type(&[0u8, 1, 2, 3, 255], "data.txt"), "application/octet-stream");
+        assert_eq!(sniff_mime_type(&[0u8, 1, 2, 3, 255], "report.pdf"), "application/octet-stream");
+        // A multi-byte character cut off by the sniff window is still text
+        let mut text = "x".repeat(8191).into_bytes();
+        text.extend_from_slice("é".as_bytes());
+        assert_eq!(sniff_mime_type(&text, "notes"), "text/plain");
+    }
+
+    #[test]
+    fn test_upload_policy() {
+        let policy = UploadPolicy::default();
+        assert!(policy.check("application/x-executable", "a.out").is_ok());
+
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        let mut policy = UploadPolicy {
+            allowed: strings(&["image/*", "application/pdf", ".txt"]),
+            blocked: strings(&["image/svg+xml", " .EXE"]),
+        };
+        policy.normalize().unwrap();
+        assert!(policy.check("image/png", "cat.png").is_ok());
+        assert!(policy.check("application/pdf", "doc.pdf").is_ok());
+        assert!(policy.check("text/plain", "readme.txt").is_ok());
+        assert_eq!(
+            policy.check("image/svg+xml", "logo.svg"),
+            Err(PolicyError::Blocked("image/svg+xml".to_string()))
+        );
+        assert_eq!(
+            policy.check("image/png", "setup.exe"),
+            Err(PolicyError::Blocked("image/png".to_string()))
+        );
+        assert_eq!(
+            policy.check("application/zip", "bundle.zip"),
+            Err(PolicyError::NotAllowed("application/zip".to_string()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod scan_tests {
+    use crate::models::NewFile;
+    use crate::scan::{
+        clamd_instream, files_to_rescan, scan_and_record, ClamdAddress, ClamdScanner, CommandScanner,
+        ScanError, ScanStatus, Scanner, Verdict,
+    };
+    use crate::tests::support::test_client;
+    use crate::{create_file, establish_connection, get_file_by_hash, MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+    use rocket::figment::Figment;
+    use serial_test::serial;
+    use std::io::{Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::path::{Path, PathBuf};
+    use std::{fs, thread};
+    use tempfile::TempDir;
+
+    // Accepts one INSTREAM session and answers with `reply`, returning the streamed bytes
+    fn fake_clamd(reply: &'static str) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut received = Vec::new();
+            loop {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len).unwrap();
+                let len = u32::from_be_bytes(len) as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                stream.read_exact(&mut chunk).unwrap();
+                received.extend_from_slice(&chunk);
+            }
+            stream.write_all(reply.as_bytes()).unwrap();
+            stream.write_all(b"\0").unwrap();
+            received
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn test_clamd_scanner_clean_and_infected() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("upload");
+        fs::write(&path, vec![42u8; 200_000]).unwrap();
+
+        let (address, server) = fake_clamd("stream: OK");
+        let scanner = ClamdScanner::new(ClamdAddress::parse(&address));
+        assert_eq!(scanner.scan(&path).unwrap(), Verdict::Clean);
+        assert_eq!(server.join().unwrap(), vec![42u8; 200_000]);
+
+        let (address, server) = fake_clamd("stream: Win.Test.EICAR_HDB-1 FOUND");
+        let scanner = ClamdScanner::new(ClamdAddress::parse(&format!("tcp:{}", address)));
+        assert_eq!(
+            scanner.scan(&path).unwrap(),
+            Verdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_clamd_error_reply() {
+        struct Session(Cursor<Vec<u8>>);
+        impl Read for Session {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Session {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let session = Session(Cursor::new(b"INSTREAM size limit exceeded. ERROR\0".to_vec()));
+        let result = clamd_instream(session, &b"data"[..]);
+        assert!(matches!(result, Err(ScanError::Scanner(_))));
+    }
+
+    #[test]
+    fn test_clamd_address_parsing() {
+        assert_eq!(
+            ClamdAddress::parse("unix:/run/clamav/clamd.ctl"),
+            ClamdAddress::Unix(PathBuf::from("/run/clamav/clamd.ctl"))
+        );
+        assert_eq!(
+            ClamdAddress::parse("/tmp/clamd.sock"),
+            ClamdAddress::Unix(PathBuf::from("/tmp/clamd.sock"))
+        );
+        assert_eq!(
+            ClamdAddress::parse("clamav:3310"),
+            ClamdAddress::Tcp("clamav:3310".to_string())
+        );
+    }
+
+    #[test]
+    fn test_command_scanner_exit_codes() {
+        let scanner = |script: &str| CommandScanner {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string(), "scan".to_string()],
+        };
+        let path = Path::new("/dev/null");
+
+        assert_eq!(scanner("test -e \"$1\"").scan(path).unwrap(), Verdict::Clean);
+        assert_eq!(
+            scanner("echo \"$1: Eicar FOUND\"; exit 1").scan(path).unwrap(),
+            Verdict::Infected("/dev/null: Eicar FOUND".to_string())
+        );
+        assert!(scanner("echo broken >&2; exit 2").scan(path).is_err());
+
+        let parsed = CommandScanner::parse("clamdscan --no-summary --fdpass").unwrap();
+        assert_eq!(parsed.program, "clamdscan");
+        assert_eq!(parsed.args, vec!["--no-summary", "--fdpass"]);
+        assert!(CommandScanner::parse("   ").is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_scan_and_record_quarantines_infected_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let stored = temp_dir.path().join("abcdef0123456789");
+        fs::write(&stored, b"X5O!P%@AP").unwrap();
+        let file = create_file(&mut conn, NewFile {
+            file_hash: "abcdef0123456789",
+            file_name: "eicar.com",
+            file_path: stored.to_str().unwrap(),
+            size: 9,
+            private: true,
+            mime_type: "text/plain",
+            scan_status: ScanStatus::Pending.as_str(),
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "abcdef0123456789",
+        }).unwrap();
+
+        let scanner = CommandScanner {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "echo Eicar FOUND; exit 1".to_string(), "scan".to_string()],
+        };
+        let quarantine = temp_dir.path().join("quarantine");
+        let status = scan_and_record(&mut conn, &scanner, &file, &quarantine).unwrap();
+        assert_eq!(status, ScanStatus::Infected);
+
+        let file = get_file_by_hash(&mut conn, "abcdef0123456789").unwrap();
+        assert_eq!(file.scan_status, "infected");
+        assert_eq!(file.scan_verdict.as_deref(), Some("Eicar FOUND"));
+        assert!(file.scanned_at.is_some());
+        assert!(!stored.exists());
+        assert!(quarantine.join("abcdef0123456789").exists());
+        assert_eq!(file.file_path, quarantine.join("abcdef0123456789").to_str().unwrap());
+    }
+
+    #[test]
+    fn test_clamd_refuses_files_over_stream_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("upload");
+        fs::write(&path, vec![42u8; 2000]).unwrap();
+
+        // Nothing listens on the port, the size is checked before connecting
+        let scanner = ClamdScanner {
+            max_stream_size: 1000,
+            ..ClamdScanner::new(ClamdAddress::parse("127.0.0.1:1"))
+        };
+        match scanner.scan(&path) {
+            Err(ScanError::Scanner(message)) => assert!(message.contains("stream limit"), "{}", message),
+            other => panic!("expected a scanner error, got {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_pending_scans_resume_on_startup() {
+        let data = TempDir::new().unwrap();
+        let database_url = data.path().join("netdrop.db").to_string_lossy().into_owned();
+        let stored = data.path().join("left-pending");
+        fs::write(&stored, b"scanned after a restart").unwrap();
+        {
+            let mut conn = establish_connection(&database_url);
+            conn.run_pending_migrations(MIGRATIONS).unwrap();
+            for (hash, status) in [("pending000000000", ScanStatus::Pending), ("failed0000000000", ScanStatus::Error)] {
+                create_file(&mut conn, NewFile {
+                    file_hash: hash,
+                    file_name: "notes.txt",
+                    file_path: stored.to_str().unwrap(),
+                    size: 23,
+                    private: false,
+                    mime_type: "text/plain",
+                    scan_status: status.as_str(),
+                    sanitized: false,
+                    language: None,
+                    sha256: None,
+                    owner_id: None,
+                    public_id: hash,
+                }).unwrap();
+            }
+            let later = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1);
+            let hashes = |files: Vec<crate::models::File>| files.into_iter().map(|file| file.file_hash).collect::<Vec<_>>();
+            assert_eq!(hashes(files_to_rescan(&mut conn, &[ScanStatus::Error], later).unwrap()), ["failed0000000000"]);
+            assert!(files_to_rescan(&mut conn, &[ScanStatus::Pending], later - chrono::Duration::hours(1)).unwrap().is_empty());
+        }
+
+        let _client = test_client(
+            Figment::new()
+                .merge(("data_dir", data.path()))
+                .merge(("scanner.kind", "command"))
+                .merge(("scanner.command", "true")),
+        );
+        let mut conn = establish_connection(&database_url);
+        let mut status = String::new();
+        for _ in 0..100 {
+            status = get_file_by_hash(&mut conn, "pending000000000").unwrap().scan_status;
+            if status != "pending" {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(status, "clean");
+        // Failed scans wait for `netdrop rescan`
+        assert_eq!(get_file_by_hash(&mut conn, "failed0000000000").unwrap().scan_status, "error");
+    }
+
+    #[test]
+    fn test_scan_status_downloadable() {
+        assert!(ScanStatus::Unscanned.is_downloadable());
+        assert!(ScanStatus::Clean.is_downloadable());
+        assert!(!ScanStatus::Pending.is_downloadable());
+        assert!(!ScanStatus::Infected.is_downloadable());
+        assert!(!ScanStatus::Error.is_downloadable());
+        assert_eq!(ScanStatus::parse("infected"), Some(ScanStatus::Infected));
+        assert_eq!(ScanStatus::parse("bogus"), None);
+    }
+}
+
+#[cfg(test)]
+mod preview_tests {
+    use crate::preview::{
+        content_disposition, content_security_policy, flag_enabled, inline_content_type,
+        Disposition, PDF_CSP, SANDBOX_CSP,
+    };
+    use crate::html::PAGE_CSP;
+    use crate::tests::support::test_client;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn upload(client: &Client, name: &str, body: &str) -> String {
+        let url = client.put(format!("/api/v1/upload/{}", name)).body(body).dispatch().into_string().unwrap();
+        url.trim().rsplit('/').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_inline_content_types() {
+        assert_eq!(inline_content_type("image/png").as_deref(), Some("image/png"));
+        assert_eq!(inline_content_type("application/pdf").as_deref(), Some("application/pdf"));
+        assert_eq!(inline_content_type("video/mp4").as_deref(), Some("video/mp4"));
+        assert_eq!(inline_content_type("audio/mpeg").as_deref(), Some("audio/mpeg"));
+        assert_eq!(
+            inline_content_type("text/csv").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            inline_content_type("application/json").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+
+        // Anything a browser could execute must be downloaded
+        assert_eq!(inline_content_type("text/html"), None);
+        assert_eq!(inline_content_type("TEXT/HTML; charset=utf-8"), None);
+        assert_eq!(inline_content_type("image/svg+xml"), None);
+        assert_eq!(inline_content_type("application/rss+xml"), None);
+        assert_eq!(inline_content_type("text/javascript"), None);
+        assert_eq!(inline_content_type("application/octet-stream"), None);
+        assert_eq!(inline_content_type("application/x-executable"), None);
+    }
+
+    #[test]
+    fn test_content_security_policy() {
+        assert_eq!(content_security_policy("image/png"), SANDBOX_CSP);
+        assert_eq!(content_security_policy("text/plain; charset=utf-8"), SANDBOX_CSP);
+        assert_eq!(content_security_policy("application/pdf"), PDF_CSP);
+        assert!(SANDBOX_CSP.contains("sandbox"));
+    }
+
+    #[test]
+    fn test_content_disposition_escaping() {
+        assert_eq!(
+            content_disposition(Disposition::Attachment, "report.pdf"),
+            "attachment; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            content_disposition(Disposition::Inline, "a\"b\r\nSet-Cookie: x.txt"),
+            "inline; filename=\"a_b__Set-Cookie: x.txt\"; filename*=UTF-8''a%22b%0D%0ASet-Cookie%3A%20x.txt"
+        );
+        assert_eq!(
+            content_disposition(Disposition::Attachment, "fotka č.jpg"),
+            "attachment; filename=\"fotka _.jpg\"; filename*=UTF-8''fotka%20%C4%8D.jpg"
+        );
+    }
+
+    #[test]
+    fn test_flag_enabled() {
+        assert!(flag_enabled(Some("1")));
+        assert!(flag_enabled(Some("true")));
+        assert!(flag_enabled(Some("")));
+        assert!(!flag_enabled(Some("0")));
+        assert!(!flag_enabled(Some("false")));
+        assert!(!flag_enabled(None));
+    }
+
+    #[test]
+    #[serial]
+    fn test_inline_responses_are_locked_down() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let notes = upload(&client, "notes.txt", "plain text");
+        for url in [format!("/download/{}?inline", notes), format!("/view/{}", notes)] {
+            let response = client.get(url.as_str()).dispatch();
+            assert_eq!(response.status(), Status::Ok, "{}", url);
+            let headers = response.headers();
+            assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"), "{}", url);
+            assert_eq!(headers.get_one("Content-Security-Policy"), Some(SANDBOX_CSP), "{}", url);
+            assert_eq!(headers.get_one("Content-Disposition"), Some("inline; filename=\"notes.txt\""), "{}", url);
+        }
+
+        // Rendered pages get their own policy
+        let readme = upload(&client, "README.md", "# Notes");
+        let response = client.get(format!("/view/{}", readme)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(response.headers().get_one("Content-Security-Policy"), Some(PAGE_CSP));
+    }
+
+    #[test]
+    #[serial]
+    fn test_html_and_svg_are_always_attachments() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let page = upload(&client, "page.html", "<html><script>alert(1)</script></html>");
+        let svg = upload(
+            &client,
+            "logo.svg",
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><script>alert(1)</script></svg>"#,
+        );
+        for (id, name) in [(&page, "page.html"), (&svg, "logo.svg")] {
+            for url in [format!("/download/{}?inline", id), format!("/view/{}", id)] {
+                let response = client.get(url.as_str()).dispatch();
+                assert_eq!(response.status(), Status::Ok, "{}", url);
+                let headers = response.headers();
+                assert_eq!(
+                    headers.get_one("Content-Disposition"),
+                    Some(format!("attachment; filename=\"{}\"", name).as_str()),
+                    "{}",
+                    url
+                );
+                assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"), "{}", url);
+                assert_eq!(headers.get_one("Content-Security-Policy"), Some(SANDBOX_CSP), "{}", url);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod thumbnail_tests {
+    use crate::models::NewFile;
+    use crate::preview::IfNoneMatch;
+    use crate::thumbnail::{
+        files_missing_thumbnails, generate_thumbnails, get_thumbnail, is_supported, nearest_size,
+        render_thumbnail, THUMBNAIL_SIZES,
+    };
+    use crate::{create_file, establish_connection, MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+    use image::{DynamicImage, ImageFormat, RgbImage, RgbaImage};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_nearest_size() {
+        assert_eq!(nearest_size(None), 256);
+        assert_eq!(nearest_size(Some(1)), 128);
+        assert_eq!(nearest_size(Some(128)), 128);
+        assert_eq!(nearest_size(Some(200)), 256);
+        assert_eq!(nearest_size(Some(4096)), 512);
+    }
+
+    #[test]
+    fn test_supported_types() {
+        assert!(is_supported("image/jpeg"));
+        assert!(is_supported("image/png"));
+        assert!(!is_supported("image/svg+xml"));
+        assert!(!is_supported("application/pdf"));
+    }
+
+    #[test]
+    fn test_render_thumbnail_formats() {
+        let photo = DynamicImage::ImageRgb8(RgbImage::new(800, 400));
+        let (data, mime_type) = render_thumbnail(&photo, 256).unwrap();
+        assert_eq!(mime_type, "image/jpeg");
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 128));
+
+        // Transparency survives and small images aren't upscaled
+        let icon = DynamicImage::ImageRgba8(RgbaImage::new(32, 32));
+        let (data, mime_type) = render_thumbnail(&icon, 256).unwrap();
+        assert_eq!(mime_type, "image/webp");
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (32, 32));
+        assert!(decoded.color().has_alpha());
+    }
+
+    #[test]
+    #[serial]
+    fn test_generate_thumbnails() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let source = temp_dir.path().join("0123456789abcdef");
+        DynamicImage::ImageRgb8(RgbImage::new(1024, 768))
+            .save_with_format(&source, ImageFormat::Png)
+            .unwrap();
+        let file = create_file(&mut conn, NewFile {
+            file_hash: "0123456789abcdef",
+            file_name: "photo.png",
+            file_path: source.to_str().unwrap(),
+            size: 1,
+            private: true,
+            mime_type: "image/png",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "0123456789abcdef",
+        }).unwrap();
+
+        let thumbnail_dir = temp_dir.path().join("thumbnails");
+        let generated = generate_thumbnails(&mut conn, &file, &thumbnail_dir).unwrap();
+        assert_eq!(generated.len(), THUMBNAIL_SIZES.len());
+
+        let thumbnail = get_thumbnail(&mut conn, file.id, 128).unwrap();
+        assert_eq!(thumbnail.mime_type, "image/jpeg");
+        assert!(thumbnail.file_path.ends_with("0123456789abcdef_128.jpg"));
+        let decoded = image::open(&thumbnail.file_path).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (128, 96));
+
+        assert!(get_thumbnail(&mut conn, file.id, 64).is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_files_missing_thumbnails() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let mut store = |file_hash: &str, mime_type: &str, scan_status: &str| {
+            let path = temp_dir.path().join(file_hash);
+            DynamicImage::ImageRgb8(RgbImage::new(64, 64)).save_with_format(&path, ImageFormat::Png).unwrap();
+            create_file(&mut conn, NewFile {
+                file_hash,
+                file_name: "photo.png",
+                file_path: path.to_str().unwrap(),
+                size: 1,
+                private: false,
+                mime_type,
+                scan_status,
+                sanitized: false,
+                language: None,
+                sha256: None,
+                owner_id: None,
+                public_id: file_hash,
+            }).unwrap()
+        };
+        let photo = store("photo00000000000", "image/png", "clean");
+        store("document00000000", "application/pdf", "clean");
+        store("infected00000000", "image/png", "infected");
+
+        let later = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1);
+        assert_eq!(files_missing_thumbnails(&mut conn, later).unwrap(), vec![photo.id]);
+        // Uploads after the server started queue their own jobs
+        assert!(files_missing_thumbnails(&mut conn, photo.created_at).unwrap().is_empty());
+
+        generate_thumbnails(&mut conn, &photo, &temp_dir.path().join("thumbnails")).unwrap();
+        assert!(files_missing_thumbnails(&mut conn, later).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        assert!(IfNoneMatch(Some("\"abc-128\"".into())).matches("\"abc-128\""));
+        assert!(IfNoneMatch(Some("W/\"x\", \"abc-128\"".into())).matches("\"abc-128\""));
+        assert!(IfNoneMatch(Some("*".into())).matches("\"abc-128\""));
+        assert!(!IfNoneMatch(Some("\"abc-256\"".into())).matches("\"abc-128\""));
+        assert!(!IfNoneMatch(None).matches("\"abc-128\""));
+    }
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use crate::metadata::{exif_orientation, strip_metadata, MetadataPolicy};
+    use crate::tests::support::test_client;
+    use crate::{establish_connection, list_files};
+    use rocket::figment::Figment;
+    use rocket::http::{ContentType, Header};
+    use serial_test::serial;
+    use tempfile::TempDir;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::webp::WebPEncoder;
+    use image::{DynamicImage, ImageFormat, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, (x + y) as u8]))
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        // Chunks are copied verbatim, so the CRC isn't checked here
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    #[test]
+    fn test_metadata_policy() {
+        assert!(!MetadataPolicy::Never.should_strip(Some(true)));
+        assert!(!MetadataPolicy::OptIn.should_strip(None));
+        assert!(MetadataPolicy::OptIn.should_strip(Some(true)));
+        assert!(MetadataPolicy::OptOut.should_strip(None));
+        assert!(!MetadataPolicy::OptOut.should_strip(Some(false)));
+        assert!(MetadataPolicy::Always.should_strip(Some(false)));
+        assert_eq!(MetadataPolicy::parse("Opt-Out"), Some(MetadataPolicy::OptOut));
+        assert_eq!(MetadataPolicy::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn test_strip_jpeg_exif_and_gps() {
+        let mut encoded = Vec::new();
+        gradient(64, 48)
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, 90))
+            .unwrap();
+
+        // Insert EXIF with a GPS IFD marker, XMP and a comment after the JFIF header
+        let exif = b"Exif\0\0MM\0*GPSLatitude 46.05";
+        let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>";
+        let mut photo = encoded[..2].to_vec();
+        for (marker, payload) in [(0xE1u8, &exif[..]), (0xE1, &xmp[..]), (0xFE, b"shot on my phone")] {
+            photo.extend_from_slice(&[0xFF, marker]);
+            photo.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+            photo.extend_from_slice(payload);
+        }
+        photo.extend_from_slice(&encoded[2..]);
+        // Trailing data after the end of image, like an MPF preview
+        photo.extend_from_slice(b"\xFF\xD8Exif trailer");
+
+        let stripped = strip_metadata(&photo, "image/jpeg").unwrap().unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(!contains(&stripped, b"GPSLatitude"));
+        assert!(!contains(&stripped, b"xmpmeta"));
+        assert!(!contains(&stripped, b"shot on my phone"));
+        assert!(contains(&stripped, b"JFIF"));
+
+        let original = image::load_from_memory(&encoded).unwrap();
+        let sanitized = image::load_from_memory(&stripped).unwrap();
+        assert_eq!(original.to_rgb8(), sanitized.to_rgb8());
+    }
+
+    #[test]
+    fn test_strip_jpeg_keeps_orientation() {
+        let mut encoded = Vec::new();
+        gradient(32, 16)
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, 90))
+            .unwrap();
+
+        // Little-endian EXIF with the camera model and Orientation 6 (rotate 90° clockwise)
+        let mut exif = b"Exif\0\0II\x2A\0\x08\0\0\0\x02\0".to_vec();
+        exif.extend_from_slice(&[0x10, 0x01, 2, 0, 6, 0, 0, 0, 38, 0, 0, 0]);
+        exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        exif.extend_from_slice(b"Pixel\0");
+        assert_eq!(exif_orientation(&exif), Some(6));
+
+        let mut photo = encoded[..2].to_vec();
+        photo.extend_from_slice(&[0xFF, 0xE1]);
+        photo.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        photo.extend_from_slice(&exif);
+        photo.extend_from_slice(&encoded[2..]);
+
+        let stripped = strip_metadata(&photo, "image/jpeg").unwrap().unwrap();
+        assert!(!contains(&stripped, b"Pixel"));
+        let app1 = stripped.windows(2).position(|marker| marker == [0xFF, 0xE1]).unwrap();
+        let length = u16::from_be_bytes([stripped[app1 + 2], stripped[app1 + 3]]) as usize;
+        assert_eq!(exif_orientation(&stripped[app1 + 4..app1 + 2 + length]), Some(6));
+
+        let decoded = image::load_from_memory(&stripped).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (32, 16));
+
+        // The default orientation needs no EXIF at all
+        let upright_exif = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x01\0\0\0\0\0\0";
+        assert_eq!(exif_orientation(upright_exif), Some(1));
+        let upright = [&encoded[..2], &[0xFF, 0xE1, 0, 34], &upright_exif[..], &encoded[2..]].concat();
+        assert!(!contains(&strip_metadata(&upright, "image/jpeg").unwrap().unwrap(), b"Exif"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_always_policy_refuses_photos_it_cant_clean() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("metadata.strip", "always")));
+
+        let response = client.put("/api/v1/upload/broken.jpg").body(&b"\xFF\xD8\xFF\xE1\xFF\xFF"[..]).dispatch();
+        assert!(response.into_string().unwrap().contains("Could not remove metadata"));
+        let mut conn = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+        assert!(list_files(&mut conn, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_raw_uploads_request_stripping() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("metadata.strip", "opt-in")));
+
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgb8(gradient(20, 10))
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            .unwrap();
+        let mut photo = encoded[..33].to_vec();
+        photo.extend(png_chunk(b"tEXt", b"Author\0Jane"));
+        photo.extend_from_slice(&encoded[33..]);
+
+        let upload = |request: rocket::local::blocking::LocalRequest| {
+            let url = request.body(&photo).dispatch().into_string().unwrap();
+            url.trim().rsplit('/').next().unwrap().to_string()
+        };
+        let info = |public_id: &str| {
+            let response = client.get(format!("/api/v1/files/{}", public_id)).dispatch();
+            serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+        };
+
+        let kept = upload(client.put("/api/v1/upload/kept.png"));
+        assert_eq!(info(&kept)["sanitized"], false);
+        assert!(!client.get(format!("/f/{}", kept)).dispatch().into_string().unwrap().contains("metadata removed"));
+
+        let by_query = upload(client.put("/api/v1/upload/query.png?strip_metadata=1"));
+        assert_eq!(info(&by_query)["sanitized"], true);
+        assert_eq!(info(&by_query)["size"], encoded.len());
+        assert!(client.get(format!("/f/{}", by_query)).dispatch().into_string().unwrap().contains("metadata removed"));
+
+        let by_header = upload(
+            client.post("/api/v1/upload?name=header.png")
+                .header(ContentType::Binary)
+                .header(Header::new("X-Strip-Metadata", "true")),
+        );
+        assert_eq!(info(&by_header)["sanitized"], true);
+
+        let declined = upload(client.put("/declined.png?strip_metadata=0"));
+        assert_eq!(info(&declined)["sanitized"], false);
+    }
+
+    #[test]
+    fn test_strip_png_text_chunks() {
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgb8(gradient(20, 10))
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            .unwrap();
+
+        // Put metadata chunks right after IHDR
+        let ihdr_end = 8 + 12 + 13;
+        let mut photo = encoded[..ihdr_end].to_vec();
+        photo.extend(png_chunk(b"eXIf", b"MM\0*GPS"));
+        photo.extend(png_chunk(b"tEXt", b"Author\0Jane"));
+        photo.extend_from_slice(&encoded[ihdr_end..]);
+
+        let stripped = strip_metadata(&photo, "image/png").unwrap().unwrap();
+        assert_eq!(stripped, encoded);
+    }
+
+    #[test]
+    fn test_strip_webp_exif_and_xmp() {
+        let pixels = RgbaImage::from_fn(8, 8, |x, y| image::Rgba([x as u8 * 30, y as u8 * 30, 0, 255]));
+        let mut encoded = Vec::new();
+        pixels
+            .write_with_encoder(WebPEncoder::new_lossless(&mut encoded))
+            .unwrap();
+        let bitstream = &encoded[12..];
+
+        // Wrap the image in the extended format with EXIF and XMP chunks
+        let mut vp8x = vec![0x08 | 0x04 | 0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&7u32.to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&7u32.to_le_bytes()[..3]);
+        let mut body = b"WEBP".to_vec();
+        for (kind, data) in [(&b"VP8X"[..], &vp8x[..]), (b"EXIF", b"MM\0*GPS"), (b"XMP ", b"<x:xmpmeta/>")] {
+            body.extend_from_slice(kind);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        body.extend_from_slice(bitstream);
+        let mut photo = b"RIFF".to_vec();
+        photo.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        photo.extend(body);
+
+        let stripped = strip_metadata(&photo, "image/webp").unwrap().unwrap();
+        assert!(!contains(&stripped, b"EXIF"));
+        assert!(!contains(&stripped, b"xmpmeta"));
+        // Only the alpha flag is left in VP8X
+        assert_eq!(stripped[20], 0x10);
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+        assert_eq!(image::load_from_memory(&stripped).unwrap().to_rgba8(), pixels);
+    }
+
+    #[test]
+    fn test_strip_unsupported_and_malformed() {
+        assert_eq!(strip_metadata(b"%PDF-1.7", "application/pdf"), Ok(None));
+        assert!(strip_metadata(b"\xFF\xD8\xFF\xE1\xFF\xFF", "image/jpeg").is_err());
+        assert!(strip_metadata(b"\x89PNG\r\n\x1a\n\0\0\0\x10IHDR", "image/png").is_err());
+        assert!(strip_metadata(b"RIFF\xFF\xFF\xFF\xFFWEBP", "image/webp").is_err());
+    }
+}
+
+#[cfg(test)]
+mod paste_tests {
+    use crate::filetype::is_textual;
+    use crate::html::escape;
+    use crate::paste::{highlight_lines, is_valid_language, render_paste_page, MAX_PASTE_SIZE};
+    use crate::tests::support::test_client;
+    use rocket::http::Status;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_valid_language() {
+        assert!(is_valid_language("rust"));
+        assert!(is_valid_language("c++"));
+        assert!(is_valid_language("C#"));
+        assert!(!is_valid_language(""));
+        assert!(!is_valid_language("rust<script>"));
+        assert!(!is_valid_language(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn test_highlight_lines_anchors_and_escapes() {
+        let rows = highlight_lines("fn main() {}\nlet x = \"<b>\";\n", Some("rust"), "paste.txt");
+        assert!(rows.contains("<tr id=\"L1\">"));
+        assert!(rows.contains("<a href=\"#L2\">2</a>"));
+        assert!(!rows.contains("id=\"L3\""));
+        assert!(rows.contains("style="));
+        assert!(!rows.contains("<b>"));
+    }
+
+    #[test]
+    fn test_highlight_falls_back_to_extension_and_plain_text() {
+        let by_extension = highlight_lines("fn main() {}\n", None, "main.rs");
+        assert!(by_extension.contains("style="));
+
+        let plain = highlight_lines("<script>\n", Some("not-a-language"), "paste.txt");
+        assert!(plain.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn test_large_paste_is_not_highlighted() {
+        let text = "let x = 1;\n".repeat(60_000);
+        let rows = highlight_lines(&text, Some("rust"), "paste.rs");
+        assert!(rows.contains("id=\"L60000\""));
+        assert!(!rows.contains("style="));
+    }
+
+    #[test]
+    fn test_render_paste_page_escapes_name() {
+        let page = render_paste_page("hello\n", None, "<x>.txt", "/raw/abc");
+        assert!(page.contains("&lt;x&gt;.txt"));
+        assert!(page.contains("href=\"/raw/abc\""));
+    }
+
+    #[test]
+    fn test_is_textual() {
+        assert!(is_textual("text/plain"));
+        assert!(is_textual("application/json"));
+        assert!(!is_textual("image/png"));
+        assert!(!is_textual("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("<a href=\"x\">'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+    }
+
+    #[test]
+    #[serial]
+    fn test_create_paste() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = client.post("/api/v1/paste?language=rust").body("fn main() {}").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["size"], 12);
+
+        let response = client.post("/api/v1/paste").body(&b"caf\xE9"[..]).dispatch();
+        assert!(response.into_string().unwrap().contains("Paste must be valid UTF-8 text"));
+        let response = client.post("/api/v1/paste").body(vec![b'a'; MAX_PASTE_SIZE as usize + 1]).dispatch();
+        assert!(response.into_string().unwrap().contains("Paste is too large"));
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use crate::render::{markdown_to_html, read_table_page, render_table_page, Rendering, CSV_PAGE_SIZE, MAX_TABLE_PAGE};
+    use crate::tests::support::test_client;
+    use rocket::http::{ContentType, Status};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rendering_for_file() {
+        assert_eq!(Rendering::for_file("text/markdown", "README.md"), Some(Rendering::Markdown));
+        assert_eq!(Rendering::for_file("text/csv", "export.csv"), Some(Rendering::Delimited(b',')));
+        assert_eq!(Rendering::for_file("text/plain", "export.TSV"), Some(Rendering::Delimited(b'\t')));
+        assert_eq!(Rendering::for_file("text/plain", "notes.txt"), None);
+        assert_eq!(Rendering::for_file("image/png", "data.tsv"), None);
+    }
+
+    #[test]
+    fn test_markdown_is_rendered() {
+        let html = markdown_to_html("# Title\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n~~old~~");
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<del>old</del>"));
+    }
+
+    #[test]
+    fn test_markdown_is_sanitized() {
+        let html = markdown_to_html(
+            "<script>alert(1)</script>\n\n<img src=x onerror=alert(1)>\n\n[link](javascript:alert(1))",
+        );
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn test_read_table_page() {
+        let mut csv = String::from("id,name\n");
+        for i in 1..=250 {
+            csv.push_str(&format!("{},row {}\n", i, i));
+        }
+
+        let first = read_table_page(csv.as_bytes(), b',', 1).unwrap().unwrap();
+        assert_eq!(first.headers, vec!["id", "name"]);
+        assert_eq!(first.rows.len(), CSV_PAGE_SIZE);
+        assert_eq!(first.rows[0], vec!["1", "row 1"]);
+        assert!(first.has_next);
+        assert!(first.bytes_read < csv.len() as u64);
+
+        let last = read_table_page(csv.as_bytes(), b',', 3).unwrap().unwrap();
+        assert_eq!(last.rows.len(), 50);
+        assert_eq!(last.rows[0], vec!["201", "row 201"]);
+        assert!(!last.has_next);
+
+        assert!(read_table_page(csv.as_bytes(), b',', 9).unwrap().is_none());
+        assert!(read_table_page(csv.as_bytes(), b',', usize::MAX).unwrap().is_none());
+        // An empty file still has a first page
+        assert!(read_table_page(&b"id,name\n"[..], b',', 1).unwrap().unwrap().rows.is_empty());
+    }
+
+    #[test]
+    fn test_read_table_page_stops_at_page_cap() {
+        // Pages past the cap are refused without reading anything
+        let endless = std::io::repeat(b'\n');
+        assert!(read_table_page(endless, b',', MAX_TABLE_PAGE + 1).unwrap().is_none());
+
+        let mut csv = String::from("id\n");
+        for i in 0..=MAX_TABLE_PAGE * CSV_PAGE_SIZE {
+            csv.push_str(&format!("{}\n", i));
+        }
+        let last = read_table_page(csv.as_bytes(), b',', MAX_TABLE_PAGE).unwrap().unwrap();
+        assert_eq!(last.rows.len(), CSV_PAGE_SIZE);
+        assert!(!last.has_next);
+    }
+
+    #[test]
+    fn test_read_table_page_rejects_huge_records() {
+        let huge = "x".repeat(2 * 1024 * 1024);
+        assert!(read_table_page(huge.as_bytes(), b',', 1).is_err());
+        let row = format!("id\n1\n{}\n", huge);
+        assert!(read_table_page(row.as_bytes(), b',', 1).is_err());
+        assert!(read_table_page(row.as_bytes(), b',', 2).is_err());
+
+        // Long files are fine as long as each record is short
+        let mut csv = String::from("id\n");
+        while csv.len() < 3 * 1024 * 1024 {
+            csv.push_str("some short row\n");
+        }
+        let page = read_table_page(csv.as_bytes(), b',', 2).unwrap().unwrap();
+        assert!(page.has_next);
+    }
+
+    #[test]
+    fn test_read_tsv_with_ragged_rows() {
+        let table = read_table_page(&b"a\tb\n1\t2\t3\n4\n"[..], b'\t', 0).unwrap().unwrap();
+        assert_eq!(table.page, 1);
+        assert_eq!(table.rows, vec![vec!["1", "2", "3"], vec!["4"]]);
+    }
+
+    #[test]
+    fn test_render_table_page_escapes_and_links() {
+        let table = read_table_page(&b"<h>\n<script>\n"[..], b',', 1).unwrap().unwrap();
+        let html = render_table_page(&table, "x.csv", "/download/abc", |page| format!("/view/abc?page={}", page));
+        assert!(html.contains("<th>&lt;h&gt;</th>"));
+        assert!(html.contains("<td>&lt;script&gt;</td>"));
+        assert!(!html.contains("Next"));
+        assert!(!html.contains("Previous"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_view_route_renders_markdown_and_tables() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+        let upload = |name: &str, body: String| {
+            let url = client.put(format!("/api/v1/upload/{}", name)).body(body).dispatch().into_string().unwrap();
+            url.trim().rsplit('/').next().unwrap().to_string()
+        };
+
+        let readme = upload("README.md", "# Netdrop\n\n<script>alert(1)</script>".to_string());
+        let response = client.get(format!("/view/{}", readme)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let html = response.into_string().unwrap();
+        assert!(html.contains("<h1>Netdrop</h1>"));
+        assert!(!html.contains("<script>alert"));
+
+        let mut csv = String::from("id,name\n");
+        for i in 1..=150 {
+            csv.push_str(&format!("{},row {}\n", i, i));
+        }
+        let table = upload("export.csv", csv);
+        let html = client.get(format!("/view/{}", table)).dispatch().into_string().unwrap();
+        assert!(html.contains("<td>row 1</td>"));
+        assert!(html.contains(&format!("href=\"/view/{}?page=2\"", table)));
+
+        let response = client.get(format!("/view/{}?page=2", table)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let html = response.into_string().unwrap();
+        assert!(html.contains("<td>row 150</td>"));
+        assert!(!html.contains("<td>row 100</td>"));
+
+        // Pages past the end, including ones whose row numbers would overflow
+        assert_eq!(client.get(format!("/view/{}?page=3", table)).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get(format!("/view/{}?page={}", table, usize::MAX)).dispatch().status(), Status::NotFound);
+    }
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use crate::archive::{extract_member, list_entries, safe_entry_path, ArchiveError, ArchiveKind};
+    use crate::tests::support::test_client;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::Value;
+    use serial_test::serial;
+    use std::io::Write;
+    use std::path::Path;
+    use zip::write::SimpleFileOptions;
+
+    fn write_zip(path: &Path, members: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, data) in members {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn tar_bytes(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(1_700_000_000);
+            // Write the name directly so unsafe paths can be tested
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_archive_kind_for_file() {
+        assert_eq!(ArchiveKind::for_file("application/zip", "a.zip"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::for_file("application/x-tar", "a.tar"), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::for_file("application/gzip", "a.TGZ"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::for_file("application/zstd", "a.tar.zst"), Some(ArchiveKind::TarZst));
+        assert_eq!(ArchiveKind::for_file("application/gzip", "notes.txt.gz"), None);
+        assert_eq!(ArchiveKind::for_file("text/plain", "a.zip"), None);
+    }
+
+    #[test]
+    fn test_safe_entry_path() {
+        assert_eq!(safe_entry_path("docs/readme.md").as_deref(), Some("docs/readme.md"));
+        assert_eq!(safe_entry_path("./docs//a\\b.txt").as_deref(), Some("docs/a/b.txt"));
+        assert_eq!(safe_entry_path("../etc/passwd"), None);
+        assert_eq!(safe_entry_path("docs/../../x"), None);
+        assert_eq!(safe_entry_path("/etc/passwd"), None);
+        assert_eq!(safe_entry_path("C:\\Windows\\x"), None);
+        assert_eq!(safe_entry_path("a\0b"), None);
+        assert_eq!(safe_entry_path("./"), None);
+    }
+
+    #[test]
+    fn test_list_and_extract_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.zip");
+        write_zip(&path, &[("docs/readme.md", b"# hello"), ("../evil.sh", b"rm -rf /")]);
+
+        let listing = list_entries(&path, ArchiveKind::Zip).unwrap();
+        assert!(!listing.truncated);
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].path, "docs/readme.md");
+        assert_eq!(listing.entries[0].size, 7);
+        assert!(listing.entries[0].compressed_size.is_some());
+        assert!(listing.entries[0].modified.is_some());
+
+        let mut output = Vec::new();
+        let mut found = None;
+        let copied = extract_member(&path, ArchiveKind::Zip, "docs/readme.md", |size| {
+            found = Some(size);
+            &mut output
+        });
+        assert_eq!(copied.unwrap(), 7);
+        assert_eq!(found, Some(7));
+        assert_eq!(output, b"# hello");
+
+        // Missing members never get a writer
+        assert!(matches!(
+            extract_member(&path, ArchiveKind::Zip, "evil.sh", |_| -> Vec<u8> { unreachable!() }),
+            Err(ArchiveError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_list_and_extract_compressed_tarballs() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = tar_bytes(&[("src/main.rs", b"fn main() {}"), ("/etc/passwd", b"root")]);
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        let tarballs = [
+            (ArchiveKind::Tar, tar.clone()),
+            (ArchiveKind::TarGz, gz.finish().unwrap()),
+            (ArchiveKind::TarZst, zstd::encode_all(&tar[..], 3).unwrap()),
+        ];
+
+        for (kind, data) in tarballs {
+            let path = dir.path().join("archive");
+            std::fs::write(&path, data).unwrap();
+
+            let listing = list_entries(&path, kind).unwrap();
+            assert_eq!(listing.entries.len(), 1, "{:?}", kind);
+            assert_eq!(listing.entries[0].path, "src/main.rs");
+            assert_eq!(listing.entries[0].size, 12);
+            assert_eq!(listing.entries[0].compressed_size, None);
+            assert_eq!(listing.entries[0].modified.unwrap().and_utc().timestamp(), 1_700_000_000);
+
+            let mut output = Vec::new();
+            assert_eq!(extract_member(&path, kind, "src/main.rs", |_| &mut output).unwrap(), 12);
+            assert_eq!(output, b"fn main() {}");
+            assert!(matches!(
+                extract_member(&path, kind, "etc/passwd", |_| -> Vec<u8> { unreachable!() }),
+                Err(ArchiveError::NotFound)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_archive_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.zip");
+        std::fs::write(&path, b"PK\x03\x04 not really a zip").unwrap();
+        assert!(list_entries(&path, ArchiveKind::Zip).is_err());
+    }
+
+    fn upload(client: &Client, name: &str, body: Vec<u8>) -> String {
+        let url = client.put(format!("/api/v1/upload/{}", name)).body(body).dispatch().into_string().unwrap();
+        url.trim().rsplit('/').next().unwrap().to_string()
+    }
+
+    #[test]
+    #[serial]
+    fn test_archive_routes() {
+        let data = tempfile::tempdir().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let zip_path = data.path().join("a.zip");
+        write_zip(&zip_path, &[("docs/readme.md", b"# hello"), ("../evil.sh", b"rm -rf /")]);
+        let zip = upload(&client, "a.zip", std::fs::read(&zip_path).unwrap());
+
+        let response = client.get(format!("/api/v1/archive/{}", zip)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let listing: Value = response.into_json().unwrap();
+        let paths: Vec<&str> = listing["entries"].as_array().unwrap().iter().map(|e| e["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["docs/readme.md"]);
+        assert_eq!(listing["truncated"], false);
+
+        let response = client.get(format!("/archive/{}?path=docs/readme.md", zip)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("attachment; filename=\"readme.md\"")
+        );
+        assert_eq!(response.into_bytes().unwrap(), b"# hello");
+
+        // The member that would escape can't be named directly, nor found under its cleaned up name
+        let response = client.get(format!("/archive/{}?path=../evil.sh", zip)).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get(format!("/archive/{}?path=evil.sh", zip)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get(format!("/archive/{}?path=docs/missing.md", zip)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar_bytes(&[("src/main.rs", b"fn main() {}")])).unwrap();
+        let tarball = upload(&client, "src.tar.gz", gz.finish().unwrap());
+        let response = client.get(format!("/archive/{}?path=src/main.rs", tarball)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_bytes().unwrap(), b"fn main() {}");
+        let response = client.get(format!("/archive/{}?path=src/lib.rs", tarball)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Files that aren't archives have nothing to list
+        let notes = upload(&client, "notes.txt", b"plain".to_vec());
+        assert_eq!(client.get(format!("/api/v1/archive/{}", notes)).dispatch().status(), Status::NotFound);
+    }
+}
+
+#[cfg(test)]
+mod raw_upload_tests {
+    use crate::tests::support::test_client;
+    use rocket::figment::Figment;
+    use rocket::http::uri::Host;
+    use rocket::http::{Accept, ContentType, Header, Status};
+    use rocket::local::blocking::{Client, LocalRequest};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    // The local client doesn't take the host from a Host header
+    fn with_host<'c>(mut request: LocalRequest<'c>, host: &'static str) -> LocalRequest<'c> {
+        request.inner_mut().set_host(Host::parse(host).unwrap());
+        request
+    }
+
+    fn file_name(client: &Client, link: &str) -> String {
+        let public_id = link.trim().rsplit('/').next().unwrap();
+        let info: serde_json::Value = client.get(format!("/api/v1/files/{}", public_id)).dispatch().into_json().unwrap();
+        info["file_name"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    #[serial]
+    fn test_put_replies_with_a_link() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = with_host(client.put("/api/v1/upload/notes.txt"), "drop.local:8000").body("raw body").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        let link = response.into_string().unwrap();
+        // Without `public_url` links are built on the Host header, always as http
+        assert!(link.starts_with("http://drop.local:8000/download/"), "{}", link);
+        assert!(link.ends_with('\n'));
+        assert_eq!(file_name(&client, &link), "notes.txt");
+
+        let path = link.trim().trim_start_matches("http://drop.local:8000");
+        assert_eq!(client.get(path).dispatch().into_string().unwrap(), "raw body");
+    }
+
+    #[test]
+    #[serial]
+    fn test_root_put_and_octet_stream_post() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        // What `curl -T hello.txt https://host/` sends
+        let link = client.put("/hello.txt").body("hi").dispatch().into_string().unwrap();
+        assert_eq!(file_name(&client, &link), "hello.txt");
+
+        let response = client
+            .post("/api/v1/upload?name=report.bin")
+            .header(ContentType::Binary)
+            .body(vec![0u8, 1, 2, 3])
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(file_name(&client, &response.into_string().unwrap()), "report.bin");
+
+        let link = client.post("/api/v1/upload").header(ContentType::Binary).body("unnamed").dispatch().into_string().unwrap();
+        assert_eq!(file_name(&client, &link), "uploaded_file");
+    }
+
+    #[test]
+    #[serial]
+    fn test_replies_follow_accept() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = client.put("/api/v1/upload/notes.txt").header(Accept::JSON).body("as json").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["success"], true);
+        assert!(body["file_id"].as_i64().is_some());
+        let public_id = body["public_id"].as_str().unwrap();
+        assert_eq!(client.get(format!("/download/{}", public_id)).dispatch().into_string().unwrap(), "as json");
+
+        // Errors follow the same choice
+        let response = client.put("/api/v1/upload/%20").body("nameless").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.into_string().unwrap(), "Missing filename\n");
+        let response = client.put("/api/v1/upload/%20").header(Accept::JSON).body("nameless").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "Missing filename");
+    }
+
+    #[test]
+    #[serial]
+    fn test_public_url_wins_over_host() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(
+            Figment::new()
+                .merge(("data_dir", data.path()))
+                .merge(("public_url", "https://drop.example.com")),
+        );
+
+        // A client chooses its Host header, so links use the configured address whenever there is one
+        let link = with_host(client.put("/api/v1/upload/notes.txt"), "attacker.example").body("pinned").dispatch().into_string().unwrap();
+        assert!(link.starts_with("https://drop.example.com/download/"), "{}", link);
+    }
+
+    #[test]
+    #[serial]
+    fn test_uploads_leave_no_temporary_files() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("max_upload_size", 128)));
+
+        let link = client.put("/api/v1/upload/small.txt").body("fits").dispatch().into_string().unwrap();
+        assert_eq!(file_name(&client, &link), "small.txt");
+        let response = client.put("/api/v1/upload/large.txt").body("x".repeat(256)).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.into_string().unwrap(), "File is too large\n");
+
+        let body = "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"form.txt\"\r\n\r\nvia form\r\n--X--\r\n";
+        let response = client
+            .post("/api/v1/upload")
+            .header(Header::new("Content-Type", "multipart/form-data; boundary=X"))
+            .body(body)
+            .dispatch();
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(file_name(&client, body["public_id"].as_str().unwrap()), "form.txt", "{}", body);
+
+        let mut stored: Vec<String> = std::fs::read_dir(data.path().join("uploads"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        stored.sort();
+        assert_eq!(stored.len(), 2, "{:?}", stored);
+        assert!(stored.iter().all(|name| !name.starts_with('.')), "{:?}", stored);
+    }
+}
+
+#[cfg(test)]
+mod spool_tests {
+    use crate::spool::Spool;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_spool_hashes_and_persists() {
+        let dir = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+
+        let mut spool = Spool::create(dir.path()).unwrap();
+        for chunk in content.chunks(3000) {
+            spool.write_all(chunk).await.unwrap();
+        }
+        let spooled = spool.finish().await.unwrap();
+        assert_eq!(spooled.size, content.len() as u64);
+        assert_eq!(spooled.sha256, <[u8; 32]>::from(Sha256::digest(&content)));
+        assert_eq!(spooled.head, content[..crate::filetype::SNIFF_LEN]);
+        assert!(spooled.path().starts_with(dir.path()));
+
+        let destination = dir.path().join("0123456789abcdef");
+        spooled.persist(&destination).unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), content);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_spool_is_removed() {
+        let dir = TempDir::new().unwrap();
+
+        let mut spool = Spool::create(dir.path()).unwrap();
+        spool.write_all(b"abandoned").await.unwrap();
+        drop(spool);
+        let spooled = Spool::create(dir.path()).unwrap().finish().await.unwrap();
+        assert_eq!((spooled.size, spooled.head.len()), (0, 0));
+        drop(spooled);
+
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod checksum_tests {
+    use crate::checksum::{from_headers, parse_content_digest, parse_digest, parse_hex, write_hashed, ChecksumError};
+    use crate::tests::support::test_client;
+    use crate::{establish_connection, list_files};
+    use rocket::http::{Header, Status};
+    use serial_test::serial;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    // SHA-256 of "hello"
+    const HELLO_HEX: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const HELLO_BASE64: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    fn hello() -> [u8; 32] {
+        Sha256::digest(b"hello").into()
+    }
+
+    #[test]
+    fn test_parse_content_digest() {
+        let header = format!("sha-512=:AAAA:, sha-256=:{}:", HELLO_BASE64);
+        assert_eq!(parse_content_digest(&header), Some(Ok(hello())));
+        assert_eq!(parse_content_digest(&format!("sha-256={}", HELLO_BASE64)), Some(Err(ChecksumError::Malformed)));
+        assert_eq!(parse_content_digest("sha-512=:AAAA:"), None);
+    }
+
+    #[test]
+    fn test_parse_digest() {
+        assert_eq!(parse_digest(&format!("MD5=abc, SHA-256={}", HELLO_BASE64)), Some(Ok(hello())));
+        assert_eq!(parse_digest("SHA-256=not base64"), Some(Err(ChecksumError::Malformed)));
+        assert_eq!(parse_digest("SHA-256=AAAA"), Some(Err(ChecksumError::Malformed)));
+    }
+
+    #[test]
+    fn test_content_digest_takes_precedence() {
+        let content_digest = format!("sha-256=:{}:", HELLO_BASE64);
+        let other = format!("SHA-256={}", "A".repeat(43) + "=");
+        assert_eq!(from_headers(Some(&content_digest), Some(&other)), Ok(Some(hello())));
+        assert_eq!(from_headers(None, Some(&format!("SHA-256={}", HELLO_BASE64))), Ok(Some(hello())));
+        assert_eq!(from_headers(Some("sha-512=:AAAA:"), None), Ok(None));
+        assert_eq!(from_headers(None, None), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex(HELLO_HEX), Ok(hello()));
+        assert_eq!(parse_hex(&HELLO_HEX.to_uppercase()), Ok(hello()));
+        assert_eq!(parse_hex("abcd"), Err(ChecksumError::Malformed));
+        assert_eq!(parse_hex("zz"), Err(ChecksumError::Malformed));
+    }
+
+    #[test]
+    fn test_write_hashed() {
+        let data = vec![7u8; 200_000];
+        let mut written = Vec::new();
+        let digest = write_hashed(&mut written, &data).unwrap();
+        assert_eq!(written, data);
+        assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(&data)));
+    }
+
+    fn multipart_body(boundary: &str, sha256: &str) -> String {
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"sha256\"\r\n\r\n{sha256}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"hello.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nhello\r\n--{b}--\r\n",
+            b = boundary,
+            sha256 = sha256,
+        )
+    }
+
+    #[test]
+    #[serial]
+    fn test_wrong_checksum_leaves_nothing_behind() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = client
+            .post("/api/v1/upload")
+            .header(Header::new("Content-Type", "multipart/form-data; boundary=X-BOUNDARY"))
+            .body(multipart_body("X-BOUNDARY", &"0".repeat(64)))
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client
+            .put("/api/v1/upload/hello.txt")
+            .header(Header::new("Content-Digest", format!("sha-256=:{}:", "A".repeat(43) + "=")))
+            .body("hello")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let mut connection = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+        assert!(list_files(&mut connection, 10).unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(data.path().join("uploads")).unwrap().count(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_matching_checksum_is_accepted() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = client
+            .put("/api/v1/upload/hello.txt")
+            .header(Header::new("Content-Digest", format!("sha-256=:{}:", HELLO_BASE64)))
+            .body("hello")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post("/api/v1/upload")
+            .header(Header::new("Content-Type", "multipart/form-data; boundary=X-BOUNDARY"))
+            .body(multipart_body("X-BOUNDARY", HELLO_HEX))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut connection = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+        let files = list_files(&mut connection, 10).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|file| file.sha256.as_deref() == Some(HELLO_HEX)));
+    }
+}
+
+#[cfg(test)]
+mod fsck_tests {
+    use crate::fsck::{check, collect_garbage, FsckOptions, GcOptions, IssueKind, ORPHAN_GRACE_PERIOD};
+    use crate::models::{NewFile, NewThumbnail};
+    use crate::{create_file, delete_file, establish_connection, get_file_by_hash, list_files, MIGRATIONS};
+    use diesel::prelude::*;
+    use diesel::SqliteConnection;
+    use diesel_migrations::MigrationHarness;
+    use serial_test::serial;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn store(conn: &mut SqliteConnection, dir: &Path, hash: &str, content: &[u8], recorded: &[u8]) {
+        let path = dir.join(hash);
+        fs::write(&path, content).unwrap();
+        let sha256 = hex::encode(Sha256::digest(recorded));
+        create_file(conn, NewFile {
+            file_hash: hash,
+            file_name: "file.txt",
+            file_path: path.to_str().unwrap(),
+            size: recorded.len() as i64,
+            private: true,
+            mime_type: "text/plain",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: Some(&sha256),
+            owner_id: None,
+            public_id: hash,
+        }).unwrap();
+    }
+
+    // Writes a file that no upload in progress could still be about to claim
+    fn write_stale(path: &Path, content: &[u8]) {
+        fs::write(path, content).unwrap();
+        let modified = SystemTime::now() - ORPHAN_GRACE_PERIOD - Duration::from_secs(60);
+        fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    fn setup() -> (TempDir, SqliteConnection) {
+        let temp_dir = TempDir::new().unwrap();
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        fs::create_dir_all(temp_dir.path().join("uploads")).unwrap();
+        (temp_dir, conn)
+    }
+
+    #[test]
+    #[serial]
+    fn test_fsck_reports_problems() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        let quarantine = temp_dir.path().join("quarantine");
+
+        store(&mut conn, &uploads, "good000000000000", b"hello", b"hello");
+        store(&mut conn, &uploads, "flipped000000000", b"hellp", b"hello");
+        store(&mut conn, &uploads, "truncated0000000", b"hel", b"hello");
+        store(&mut conn, &uploads, "missing000000000", b"hello", b"hello");
+        fs::remove_file(uploads.join("missing000000000")).unwrap();
+        write_stale(&uploads.join("orphan0000000000"), b"stray");
+
+        let options = FsckOptions {
+            verify_hashes: true,
+            ..FsckOptions::default()
+        };
+        let report = check(&mut conn, &uploads, &quarantine, options).unwrap();
+        assert_eq!(report.checked, 4);
+        assert!(!report.is_clean());
+
+        let kinds: Vec<(IssueKind, &str)> = report.issues.iter().map(|i| (i.kind, i.file_hash.as_str())).collect();
+        assert_eq!(kinds, vec![
+            (IssueKind::HashMismatch, "flipped000000000"),
+            (IssueKind::SizeMismatch, "truncated0000000"),
+            (IssueKind::Missing, "missing000000000"),
+        ]);
+        assert_eq!(report.orphans, vec![uploads.join("orphan0000000000").to_string_lossy()]);
+
+        // Nothing is changed without repair options
+        assert!(get_file_by_hash(&mut conn, "missing000000000").is_some());
+        assert!(uploads.join("orphan0000000000").exists());
+
+        // Hash checks can be skipped
+        let report = check(&mut conn, &uploads, &quarantine, FsckOptions::default()).unwrap();
+        assert!(report.issues.iter().all(|issue| issue.kind != IssueKind::HashMismatch));
+    }
+
+    #[test]
+    #[serial]
+    fn test_fsck_repairs() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        let quarantine = temp_dir.path().join("quarantine");
+
+        store(&mut conn, &uploads, "good000000000000", b"hello", b"hello");
+        store(&mut conn, &uploads, "missing000000000", b"hello", b"hello");
+        fs::remove_file(uploads.join("missing000000000")).unwrap();
+        write_stale(&uploads.join("orphan0000000000"), b"stray");
+
+        let options = FsckOptions {
+            verify_hashes: true,
+            remove_dangling_rows: true,
+            quarantine_orphans: true,
+        };
+        let report = check(&mut conn, &uploads, &quarantine, options).unwrap();
+        assert_eq!(report.removed_rows, 1);
+        assert_eq!(report.quarantined_orphans, 1);
+        assert!(get_file_by_hash(&mut conn, "missing000000000").is_none());
+        assert!(get_file_by_hash(&mut conn, "good000000000000").is_some());
+        assert!(!uploads.join("orphan0000000000").exists());
+        assert!(quarantine.join("orphans/orphan0000000000").exists());
+
+        let report = check(&mut conn, &uploads, &quarantine, options).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.checked, 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_list_and_delete_files() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        store(&mut conn, &uploads, "first00000000000", b"one", b"one");
+        store(&mut conn, &uploads, "second0000000000", b"two", b"two");
+
+        let thumbnail = temp_dir.path().join("second0000000000_128.jpg");
+        fs::write(&thumbnail, b"jpeg").unwrap();
+        let file = get_file_by_hash(&mut conn, "second0000000000").unwrap();
+        diesel::insert_into(crate::schema::thumbnails::table)
+            .values(&NewThumbnail {
+                file_id: file.id,
+                size: 128,
+                mime_type: "image/jpeg",
+                file_path: thumbnail.to_str().unwrap(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let listed: Vec<String> = list_files(&mut conn, 10).unwrap().into_iter().map(|f| f.file_hash).collect();
+        assert_eq!(listed, vec!["second0000000000", "first00000000000"]);
+        assert_eq!(list_files(&mut conn, 1).unwrap().len(), 1);
+
+        delete_file(&mut conn, &file).unwrap();
+        assert!(get_file_by_hash(&mut conn, "second0000000000").is_none());
+        assert!(!uploads.join("second0000000000").exists());
+        assert!(!thumbnail.exists());
+        let thumbnails: i64 = crate::schema::thumbnails::table.count().get_result(&mut conn).unwrap();
+        assert_eq!(thumbnails, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_collect_garbage() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        let thumbnails = temp_dir.path().join("thumbnails");
+        fs::create_dir_all(&thumbnails).unwrap();
+
+        store(&mut conn, &uploads, "good000000000000", b"hello", b"hello");
+        store(&mut conn, &uploads, "missing000000000", b"hello", b"hello");
+        fs::remove_file(uploads.join("missing000000000")).unwrap();
+        write_stale(&uploads.join("orphan0000000000"), b"stray");
+        write_stale(&thumbnails.join("gone_128.jpg"), b"jpeg");
+
+        let remove_missing = GcOptions {
+            remove_missing: true,
+            ..GcOptions::default()
+        };
+        let report = collect_garbage(&mut conn, &uploads, &thumbnails, GcOptions { dry_run: true, ..remove_missing }).unwrap();
+        assert_eq!(report.removed_rows, vec!["missing000000000"]);
+        assert_eq!(report.removed_files.len(), 2);
+        assert_eq!(report.freed_bytes, 9);
+        assert!(uploads.join("orphan0000000000").exists());
+        assert!(get_file_by_hash(&mut conn, "missing000000000").is_some());
+
+        // Rows are only removed when asked for
+        let report = collect_garbage(&mut conn, &uploads, &thumbnails, GcOptions::default()).unwrap();
+        assert!(report.removed_rows.is_empty());
+        assert!(!uploads.join("orphan0000000000").exists());
+        assert!(!thumbnails.join("gone_128.jpg").exists());
+        assert!(get_file_by_hash(&mut conn, "missing000000000").is_some());
+
+        let report = collect_garbage(&mut conn, &uploads, &thumbnails, remove_missing).unwrap();
+        assert_eq!(report.removed_rows, vec!["missing000000000"]);
+        assert!(get_file_by_hash(&mut conn, "missing000000000").is_none());
+        assert!(uploads.join("good000000000000").exists());
+
+        let report = collect_garbage(&mut conn, &uploads, &thumbnails, remove_missing).unwrap();
+        assert!(report.removed_rows.is_empty() && report.removed_files.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_missing_upload_dir_removes_nothing() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        let thumbnails = temp_dir.path().join("thumbnails");
+        store(&mut conn, &uploads, "good000000000000", b"hello", b"hello");
+        let unmounted = temp_dir.path().join("unmounted");
+
+        let remove_missing = GcOptions {
+            remove_missing: true,
+            ..GcOptions::default()
+        };
+        assert!(collect_garbage(&mut conn, &unmounted, &thumbnails, remove_missing).is_err());
+        let options = FsckOptions {
+            remove_dangling_rows: true,
+            ..FsckOptions::default()
+        };
+        assert!(check(&mut conn, &unmounted, &temp_dir.path().join("quarantine"), options).is_err());
+        assert!(get_file_by_hash(&mut conn, "good000000000000").is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_recent_files_without_a_row_are_kept() {
+        let (temp_dir, mut conn) = setup();
+        let uploads = temp_dir.path().join("uploads");
+        let thumbnails = temp_dir.path().join("thumbnails");
+        let quarantine = temp_dir.path().join("quarantine");
+        fs::create_dir_all(&thumbnails).unwrap();
+
+        // An upload that has written its blob but not yet its row
+        fs::write(uploads.join("inflight00000000"), b"new").unwrap();
+        fs::write(thumbnails.join("inflight_128.jpg"), b"jpeg").unwrap();
+        write_stale(&uploads.join("orphan0000000000"), b"stray");
+
+        let options = FsckOptions {
+            quarantine_orphans: true,
+            ..FsckOptions::default()
+        };
+        let report = check(&mut conn, &uploads, &quarantine, options).unwrap();
+        assert_eq!(report.orphans, vec![uploads.join("orphan0000000000").to_string_lossy()]);
+        assert_eq!(report.quarantined_orphans, 1);
+        assert!(uploads.join("inflight00000000").exists());
+
+        let report = collect_garbage(&mut conn, &uploads, &thumbnails, GcOptions::default()).unwrap();
+        assert!(report.removed_files.is_empty());
+        assert!(uploads.join("inflight00000000").exists());
+        assert!(thumbnails.join("inflight_128.jpg").exists());
+    }
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use crate::auth::{authenticate, create_token, create_user, generate_token, hash_token, is_valid_username, AuthError, TOKEN_PREFIX};
+    use crate::{establish_connection, MIGRATIONS};
+    use diesel::prelude::*;
+    use diesel_migrations::MigrationHarness;
+    use serial_test::serial;
+
+    #[test]
+    fn test_is_valid_username() {
+        assert!(is_valid_username("alice"));
+        assert!(is_valid_username("ops-bot.2"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("Alice"));
+        assert!(!is_valid_username("-alice"));
+        assert!(!is_valid_username("a b"));
+        assert!(!is_valid_username(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 43);
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token).len(), 64);
+    }
+
+    #[test]
+    #[serial]
+    fn test_users_and_tokens() {
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let alice = create_user(&mut conn, "alice").unwrap();
+        assert!(matches!(create_user(&mut conn, "alice"), Err(AuthError::UsernameTaken)));
+        assert!(matches!(create_user(&mut conn, "Bad Name"), Err(AuthError::InvalidUsername)));
+        assert!(matches!(create_token(&mut conn, "bob", "laptop"), Err(AuthError::UnknownUser)));
+
+        let (row, token) = create_token(&mut conn, "alice", "laptop").unwrap();
+        assert_eq!(row.user_id, alice.id);
+        assert_eq!(row.name, "laptop");
+        assert_eq!(row.token_hash, hash_token(&token));
+        assert!(row.last_used_at.is_none());
+
+        let user = authenticate(&mut conn, &token).unwrap().unwrap();
+        assert_eq!(user.username, "alice");
+        assert!(authenticate(&mut conn, "nd_wrong").unwrap().is_none());
+
+        use crate::schema::api_tokens;
+        let last_used = api_tokens::table
+            .find(row.id)
+            .select(api_tokens::last_used_at)
+            .first::<Option<chrono::NaiveDateTime>>(&mut conn)
+            .unwrap();
+        assert!(last_used.is_some());
+    }
+}
+
+#[cfg(test)]
+mod range_tests {
+    use crate::range::{ByteRange, FileSlice};
+    use std::io::{Cursor, SeekFrom};
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(ByteRange::parse(None, 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=0-9"), 100), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(ByteRange::parse(Some("bytes=90-"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=-10"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=50-500"), 100), ByteRange::Partial { start: 50, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=0-"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=-500"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+
+        // Anything we don't handle falls back to the whole file
+        assert_eq!(ByteRange::parse(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=9-3"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("items=0-1"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=abc"), 100), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_file_slice() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut slice = FileSlice::new(Cursor::new(data), 10, 20).await.unwrap();
+
+        let mut read = Vec::new();
+        slice.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, (10..30).collect::<Vec<u8>>());
+
+        // Seeks are relative to the slice
+        assert_eq!(slice.seek(SeekFrom::Start(15)).await.unwrap(), 15);
+        let mut rest = Vec::new();
+        slice.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, (25..30).collect::<Vec<u8>>());
+        assert_eq!(slice.seek(SeekFrom::End(-2)).await.unwrap(), 18);
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_filename_from_disposition() {
+        use crate::client::filename_from_disposition;
+
+        assert_eq!(filename_from_disposition("attachment; filename=\"a b.txt\"").as_deref(), Some("a b.txt"));
+        assert_eq!(
+            filename_from_disposition("attachment; filename=\"x.txt\"; filename*=UTF-8''caf%C3%A9.txt").as_deref(),
+            Some("café.txt")
+        );
+        assert_eq!(filename_from_disposition("attachment; filename=\"../../etc/passwd\"").as_deref(), Some("passwd"));
+        assert_eq!(filename_from_disposition("attachment; filename=\"..\"").as_deref(), None);
+        assert_eq!(filename_from_disposition("inline").as_deref(), None);
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::{Config, ConfigError};
+    use crate::metadata::MetadataPolicy;
+    use crate::scan::ScannerKind;
+    use crate::server::{self, StartupError};
+    use rocket::data::ByteUnit;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+
+    fn load(toml: &str) -> Result<Config, ConfigError> {
+        Config::from_figment(&Figment::new().merge(Toml::string(toml)))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load("").unwrap();
+        assert_eq!(config.data_dir.to_str(), Some("data"));
+        assert_eq!(config.database_url, "data/netdrop.db");
+        assert_eq!(config.max_upload_size, ByteUnit::Megabyte(1000));
+        assert!(config.public_url.is_none());
+        assert!(config.admin_token.is_none());
+        assert_eq!(config.upload_dir().to_str(), Some("data/uploads"));
+    }
+
+    #[test]
+    fn test_build_reports_invalid_config() {
+        let figment = Figment::from(rocket::Config::debug_default()).merge(("logging.filter", "netdrop=loud"));
+        let error = server::build(figment).unwrap_err();
+        assert!(matches!(error, StartupError::Config(ConfigError::Invalid { key: "logging.filter", .. })));
+        assert!(error.to_string().starts_with("Invalid configuration: invalid `logging.filter`"));
+    }
+
+    #[test]
+    fn test_values_from_toml() {
+        let config = load(
+            r#"
+            data_dir = "/srv/netdrop"
+            max_upload_size = "5 GiB"
+            public_url = "https://drop.example/"
+            admin_token = ""
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.database_url, "/srv/netdrop/netdrop.db");
+        assert_eq!(config.max_upload_size, ByteUnit::Gibibyte(5));
+        assert_eq!(config.public_url.as_deref(), Some("https://drop.example"));
+        assert!(config.admin_token.is_none());
+
+        let config = load("database_url = \":memory:\"\nmax_upload_size = 1024").unwrap();
+        assert_eq!(config.database_url, ":memory:");
+        assert_eq!(config.max_upload_size, 1024);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let error = load("max_upload_size = 0").unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { key: "max_upload_size", .. }));
+
+        let error = load("public_url = \"drop.example\"").unwrap_err();
+        assert_eq!(error.to_string(), "invalid `public_url`: 'drop.example' is not an http(s) URL");
+
+        let error = load("max_upload_size = \"lots\"").unwrap_err();
+        assert!(matches!(error, ConfigError::Figment(_)));
+        assert!(error.to_string().contains("max_upload_size"), "{}", error);
+
+        assert!(matches!(load("data_dir = \"Cargo.toml\""), Err(ConfigError::Invalid { key: "data_dir", .. })));
+    }
+
+    #[test]
+    fn test_upload_and_download_sections() {
+        let config = load(
+            r#"
+            [rate_limit]
+            requests_per_minute = 120
+            trusted_proxies = ["10.0.0.0/8", "192.0.2.10"]
+            [throttle]
+            anonymous_bytes_per_sec = 1000
+            [upload_policy]
+            blocked = ["Application/X-Executable", ".EXE"]
+            [scanner]
+            kind = "command"
+            command = "clamdscan --no-summary"
+            [metadata]
+            strip = "opt-out"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rate_limit.requests_per_minute, 120);
+        assert_eq!(config.rate_limit.request_burst, 20);
+        assert_eq!(config.throttle.anonymous_bytes_per_sec, 1000);
+        assert_eq!(config.upload_policy.blocked, ["application/x-executable", ".exe"]);
+        assert_eq!(config.scanner.kind, ScannerKind::Command);
+        assert_eq!(config.metadata.strip, MetadataPolicy::OptOut);
+
+        let error = load("[rate_limit]\ntrusted_proxies = [\"proxy.internal\"]").unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { key: "rate_limit.trusted_proxies", .. }));
+        let error = load("[upload_policy]\nallowed = [\"images\"]").unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { key: "upload_policy", .. }));
+        let error = load("[scanner]\nkind = \"command\"").unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { key: "scanner", .. }));
+        assert!(matches!(load("[metadata]\nstrip = \"sometimes\""), Err(ConfigError::Figment(_))));
+    }
+}
+
+#[cfg(test)]
+mod cors_tests {
+    use crate::config::{Config, ConfigError};
+    use crate::cors::CorsConfig;
+    use crate::tests::support::test_client;
+    use rocket::figment::Figment;
+    use rocket::http::{Header, Method, Status};
+    use rocket::local::blocking::Client;
+    use serial_test::serial;
+
+    fn client(cors: CorsConfig, public_url: Option<&str>) -> Client {
+        test_client(
+            Figment::new()
+                .merge(("database_url", ":memory:"))
+                .merge(("public_url", public_url.unwrap_or("")))
+                .merge(("cors", cors)),
+        )
+    }
+
+    fn allowing(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            ..CorsConfig::default()
+        }
+    }
+
+    fn preflight<'c>(client: &'c Client, origin: &str, method: &str) -> rocket::local::blocking::LocalResponse<'c> {
+        client
+            .req(Method::Options, "/api/v1/upload/notes.txt")
+            .header(Header::new("Origin", origin.to_string()))
+            .header(Header::new("Access-Control-Request-Method", method.to_string()))
+            .header(Header::new("Access-Control-Request-Headers", "authorization, content-type"))
+            .dispatch()
+    }
+
+    #[test]
+    #[serial]
+    fn test_same_origin_only_by_default() {
+        let client = client(CorsConfig::default(), None);
+
+        let response = preflight(&client, "https://evil.example", "PUT");
+        assert!(response.headers().get_one("Access-Control-Allow-Origin").is_none());
+
+        let response = client.get("/").header(Header::new("Origin", "https://evil.example")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Access-Control-Allow-Origin").is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_preflight_for_allowed_origin() {
+        let client = client(allowing(&["https://app.example"]), None);
+
+        for method in ["PUT", "DELETE", "PATCH", "HEAD", "POST"] {
+            let response = preflight(&client, "https://app.example", method);
+            assert!(response.status().class().is_success(), "{} got {}", method, response.status());
+            let headers = response.headers();
+            assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some("https://app.example"));
+            assert!(headers.get_one("Access-Control-Allow-Methods").unwrap().contains(method));
+            assert_eq!(headers.get_one("Access-Control-Max-Age"), Some("3600"));
+            assert!(headers.get_one("Access-Control-Allow-Credentials").is_none());
+        }
+
+        let response = client.get("/").header(Header::new("Origin", "https://app.example")).dispatch();
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("https://app.example"));
+        assert!(response.headers().get_one("Access-Control-Expose-Headers").unwrap().contains("Content-Disposition"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_preflight_refused() {
+        let client = client(allowing(&["https://app.example"]), None);
+
+        let response = preflight(&client, "https://evil.example", "PUT");
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response.headers().get_one("Access-Control-Allow-Origin").is_none());
+
+        let response = preflight(&client, "https://app.example", "TRACE");
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .req(Method::Options, "/api/v1/upload/notes.txt")
+            .header(Header::new("Origin", "https://app.example"))
+            .header(Header::new("Access-Control-Request-Method", "PUT"))
+            .header(Header::new("Access-Control-Request-Headers", "x-not-allowed"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[test]
+    #[serial]
+    fn test_public_url_is_always_allowed() {
+        let client = client(allowing(&["https://app.example"]), Some("https://drop.example/netdrop"));
+
+        let response = client.get("/").header(Header::new("Origin", "https://drop.example")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("https://drop.example"));
+    }
+
+    #[test]
+    fn test_invalid_cors_config() {
+        let validate = |cors: CorsConfig| Config { cors, ..Config::default() }.validated();
+
+        let wildcard_credentials = CorsConfig {
+            allow_credentials: true,
+            ..allowing(&["*"])
+        };
+        assert!(matches!(validate(wildcard_credentials), Err(ConfigError::Invalid { key: "cors", .. })));
+        assert!(validate(allowing(&["*"])).is_ok());
+        assert!(validate(allowing(&["app.example"])).is_err());
+        assert!(validate(allowing(&["https://app.example/path"])).is_err());
+
+        let bad_method = CorsConfig {
+            allowed_methods: vec!["FETCH".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(validate(bad_method).is_err());
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use crate::metrics::{self, Metrics, MeteredReader, MetricsConfig, RejectReason};
+    use crate::tests::support::test_client;
+    use rocket::figment::Figment;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+    use serial_test::serial;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+
+    fn client(data: &TempDir, metrics: MetricsConfig) -> Client {
+        test_client(Figment::new().merge(("data_dir", data.path())).merge(("metrics", metrics)))
+    }
+
+    // The value of a sample line such as `netdrop_uploads_total{status="success"} 3`
+    fn sample(body: &str, series: &str) -> Option<f64> {
+        body.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .and_then(|value| value.parse().ok())
+    }
+
+    #[test]
+    fn test_encode() {
+        let metrics = Metrics::new();
+        metrics.reject(RejectReason::TooLarge);
+        metrics.record_download("raw", 404);
+        drop(metrics.db_timer("get_file_by_hash"));
+
+        let body = metrics.encode();
+        assert_eq!(sample(&body, "netdrop_rejected_requests_total{reason=\"too_large\"}"), Some(1.0));
+        assert_eq!(sample(&body, "netdrop_downloads_total{route=\"raw\",status=\"404\"}"), Some(1.0));
+        assert_eq!(sample(&body, "netdrop_db_query_duration_seconds_count{query=\"get_file_by_hash\"}"), Some(1.0));
+        assert!(body.contains("# TYPE netdrop_upload_duration_seconds histogram"));
+    }
+
+    #[tokio::test]
+    async fn test_metered_reader() {
+        let active = metrics::global().active_transfers.with_label_values(&["download"]);
+        let sent = metrics::global().bytes_sent.get();
+
+        let mut reader = MeteredReader::new(Cursor::new(vec![7u8; 1000]));
+        assert!(active.get() >= 1);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).await.unwrap();
+        drop(reader);
+
+        assert!(metrics::global().bytes_sent.get() >= sent + 1000);
+    }
+
+    #[test]
+    #[serial]
+    fn test_metrics_endpoint_counts_transfers() {
+        let data = TempDir::new().unwrap();
+        let client = client(&data, MetricsConfig::default());
+
+        let response = client.put("/api/v1/upload/hello.txt").body("hello metrics").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let url = response.into_string().unwrap();
+        let path = url[url.find("/download/").unwrap()..].trim();
+
+        assert_eq!(client.get(path).dispatch().into_string().as_deref(), Some("hello metrics"));
+        assert_eq!(client.get("/download/0000000000000000").dispatch().status(), Status::NotFound);
+
+        let response = client.get("/metrics").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.content_type().unwrap().to_string().starts_with("text/plain"));
+        let body = response.into_string().unwrap();
+
+        assert!(sample(&body, "netdrop_uploads_total{status=\"success\"}").unwrap() >= 1.0);
+        assert!(sample(&body, "netdrop_downloads_total{route=\"download\",status=\"200\"}").unwrap() >= 1.0);
+        assert!(sample(&body, "netdrop_downloads_total{route=\"download\",status=\"404\"}").unwrap() >= 1.0);
+        assert!(sample(&body, "netdrop_received_bytes_total").unwrap() >= 13.0);
+        assert!(sample(&body, "netdrop_sent_bytes_total").unwrap() >= 13.0);
+        assert_eq!(sample(&body, "netdrop_stored_files"), Some(1.0));
+        assert_eq!(sample(&body, "netdrop_stored_bytes"), Some(13.0));
+        assert!(sample(&body, "netdrop_db_query_duration_seconds_count{query=\"create_file\"}").unwrap() >= 1.0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_metrics_access() {
+        let data = TempDir::new().unwrap();
+
+        let disabled = client(&data, MetricsConfig { enabled: false, token: None });
+        assert_eq!(disabled.get("/metrics").dispatch().status(), Status::NotFound);
+
+        let protected = client(&data, MetricsConfig { enabled: true, token: Some("scrape-me".to_string()) });
+        assert_eq!(protected.get("/metrics").dispatch().status(), Status::Unauthorized);
+        let response = protected
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer wrong"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = protected
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer scrape-me"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(sample(&response.into_string().unwrap(), "netdrop_rejected_requests_total{reason=\"unauthorized\"}").unwrap() >= 2.0);
+    }
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use crate::config::{Config, ConfigError};
+    use crate::logging::{LogConfig, LogFormat, RequestId, REQUEST_ID_HEADER};
+    use crate::tests::support::test_client;
+    use rocket::figment::Figment;
+    use rocket::http::Header;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_request_id_from_header() {
+        assert_eq!(RequestId::from_header(Some("abc-123_x.y")).0, "abc-123_x.y");
+        assert_eq!(RequestId::from_header(Some("  padded  ")).0, "padded");
+
+        // Anything that could break a log line is replaced
+        for value in [None, Some(""), Some("has space"), Some("line\nbreak"), Some("\"quoted\"")] {
+            let id = RequestId::from_header(value).0;
+            assert_eq!(id.len(), 32, "{:?}", value);
+            assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+        assert_eq!(RequestId::from_header(Some(&"a".repeat(129))).0.len(), 32);
+        assert_ne!(RequestId::generate(), RequestId::generate());
+    }
+
+    #[test]
+    fn test_log_config() {
+        let config: LogConfig = Figment::from(("format", "json")).extract().unwrap();
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(config.filter, LogConfig::default().filter);
+
+        let figment = Figment::from(rocket::Config::debug_default()).merge(("logging.filter", "netdrop=loud"));
+        assert!(matches!(Config::from_figment(&figment), Err(ConfigError::Invalid { key: "logging.filter", .. })));
+        let figment = Figment::from(rocket::Config::debug_default()).merge(("logging.format", "xml"));
+        assert!(matches!(Config::from_figment(&figment), Err(ConfigError::Figment(_))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_responses_carry_request_id() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let response = client
+            .get("/download/0000000000000000")
+            .header(Header::new(REQUEST_ID_HEADER, "trace-42"))
+            .dispatch();
+        assert_eq!(response.headers().get_one(REQUEST_ID_HEADER), Some("trace-42"));
+
+        let first = client.get("/").dispatch().headers().get_one(REQUEST_ID_HEADER).map(str::to_string);
+        let second = client.get("/").dispatch().headers().get_one(REQUEST_ID_HEADER).map(str::to_string);
+        assert_eq!(first.as_ref().map(String::len), Some(32));
+        assert_ne!(first, second);
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use crate::config::Config;
+    use crate::health::{check_readiness, HealthConfig};
+    use crate::tests::support::test_client;
+    use rocket::data::ByteUnit;
+    use rocket::figment::Figment;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn client(data: &TempDir, min_free_space: &str) -> Client {
+        test_client(Figment::new().merge(("data_dir", data.path())).merge(("health.min_free_space", min_free_space)))
+    }
+
+    fn check<'a>(body: &'a serde_json::Value, name: &str) -> &'a serde_json::Value {
+        body["checks"].as_array().unwrap().iter().find(|check| check["name"] == name).unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn test_healthz() {
+        let data = TempDir::new().unwrap();
+        let client = client(&data, "0");
+        let response = client.get("/healthz").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().as_deref(), Some(r#"{"status":"ok"}"#));
+    }
+
+    #[test]
+    #[serial]
+    fn test_readyz_reports_each_check() {
+        let data = TempDir::new().unwrap();
+        let client = client(&data, "1 MB");
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["ready"], true);
+        for name in ["database", "migrations", "uploads_writable", "free_space"] {
+            assert_eq!(check(&body, name)["ok"], true, "{}", name);
+        }
+        assert!(check(&body, "free_space")["detail"].as_str().unwrap().ends_with("available"));
+
+        // The probe file is cleaned up
+        assert_eq!(std::fs::read_dir(data.path().join("uploads")).unwrap().count(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_readyz_unavailable_when_disk_is_short() {
+        let data = TempDir::new().unwrap();
+        let client = client(&data, "1000 PB");
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["ready"], false);
+        assert_eq!(check(&body, "database")["ok"], true);
+        assert_eq!(check(&body, "free_space")["ok"], false);
+        assert!(check(&body, "free_space")["detail"].as_str().unwrap().contains("required"));
+    }
+
+    #[test]
+    fn test_pending_migrations_and_unwritable_uploads() {
+        let data = TempDir::new().unwrap();
+        // A file where the uploads directory should be
+        std::fs::write(data.path().join("uploads"), b"").unwrap();
+        let config = Config {
+            data_dir: data.path().to_path_buf(),
+            health: HealthConfig { min_free_space: ByteUnit::from(0) },
+            ..Config::default()
+        }
+        .validated()
+        .unwrap();
+
+        let readiness = check_readiness(&config);
+        assert!(!readiness.ready);
+        let ok = |name: &str| readiness.checks.iter().find(|check| check.name == name).unwrap().ok;
+        assert!(ok("database"));
+        assert!(!ok("migrations"));
+        assert!(!ok("uploads_writable"));
+    }
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use crate::audit::{self, parse_time, Actor, AuditEvent, AuditFilter};
+    use crate::auth::{create_token, create_user};
+    use crate::tests::support::test_client;
+    use crate::{establish_connection, MIGRATIONS};
+    use diesel::prelude::*;
+    use diesel_migrations::MigrationHarness;
+    use rocket::figment::Figment;
+    use rocket::http::{Header, Status};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn actor(ip: &str) -> Actor {
+        Actor {
+            client_ip: Some(ip.to_string()),
+            ..Actor::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("2026-10-18").unwrap().to_string(), "2026-10-18 00:00:00");
+        assert_eq!(parse_time("2026-10-18T09:30:00+02:00").unwrap().to_string(), "2026-10-18 07:30:00");
+        assert!(parse_time("yesterday").is_none());
+        assert_eq!(AuditEvent::parse("permission_change"), Some(AuditEvent::PermissionChange));
+        assert_eq!(AuditEvent::parse("nope"), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_record_query_and_export() {
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        audit::record(&mut conn, AuditEvent::Upload, &actor("10.0.0.1"), Some("aaaa"), Some("a.txt")).unwrap();
+        audit::record(&mut conn, AuditEvent::Download, &actor("10.0.0.2"), Some("aaaa"), Some("download")).unwrap();
+        audit::record(&mut conn, AuditEvent::AccessDenied, &actor("10.0.0.2"), None, Some("admin api")).unwrap();
+
+        let all = audit::query(&mut conn, &AuditFilter::default(), None, 100).unwrap();
+        assert_eq!(all.iter().map(|event| event.event.as_str()).collect::<Vec<_>>(), ["access_denied", "download", "upload"]);
+        // The file doesn't exist, so only the hash is kept
+        assert_eq!(all[2].file_hash.as_deref(), Some("aaaa"));
+        assert_eq!(all[2].file_id, None);
+
+        let by_ip = AuditFilter { client_ip: Some("10.0.0.2".to_string()), ..AuditFilter::default() };
+        assert_eq!(audit::query(&mut conn, &by_ip, None, 100).unwrap().len(), 2);
+        let by_event = AuditFilter { event: Some(AuditEvent::Upload), ..AuditFilter::default() };
+        assert_eq!(audit::query(&mut conn, &by_event, None, 100).unwrap().len(), 1);
+        let future = AuditFilter { since: parse_time("2999-01-01"), ..AuditFilter::default() };
+        assert!(audit::query(&mut conn, &future, None, 100).unwrap().is_empty());
+
+        let page = audit::query(&mut conn, &AuditFilter::default(), None, 2).unwrap();
+        let rest = audit::query(&mut conn, &AuditFilter::default(), Some(page[1].id), 2).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].id, all[2].id);
+
+        let mut output = Vec::new();
+        assert_eq!(audit::export(&mut conn, &AuditFilter::default(), &mut output).unwrap(), 3);
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["event"], "upload");
+        assert_eq!(lines[0]["client_ip"], "10.0.0.1");
+        assert_eq!(lines[2]["detail"], "admin api");
+    }
+
+    #[test]
+    #[serial]
+    fn test_events_are_append_only() {
+        use crate::schema::audit_events;
+
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        audit::record(&mut conn, AuditEvent::Delete, &Actor::local(), None, None).unwrap();
+
+        assert!(diesel::update(audit_events::table).set(audit_events::detail.eq("edited")).execute(&mut conn).is_err());
+        assert!(diesel::delete(audit_events::table).execute(&mut conn).is_err());
+        assert_eq!(audit_events::table.count().get_result::<i64>(&mut conn).unwrap(), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_handlers_record_events() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("admin_token", "admin-secret")));
+        let admin = Header::new("Authorization", "Bearer admin-secret");
+
+        let token = {
+            let mut conn = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+            let alice = create_user(&mut conn, "alice").unwrap();
+            (alice.id, create_token(&mut conn, "alice", "laptop").unwrap().1)
+        };
+
+        let response = client
+            .put("/api/v1/upload/notes.txt")
+            .header(Header::new("Authorization", format!("Bearer {}", token.1)))
+            .header(Header::new("User-Agent", "curl/8.0"))
+            .body("audited")
+            .dispatch();
+        let url = response.into_string().unwrap();
+        let path = url[url.find("/download/").unwrap()..].trim().to_string();
+        let file_hash = path.trim_start_matches("/download/").to_string();
+
+        let response = client.get(path.as_str()).remote("203.0.113.9:4000".parse().unwrap()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(client.get("/api/v1/admin/audit").dispatch().status(), Status::Unauthorized);
+
+        let response = client.get("/api/v1/admin/audit").header(admin.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let events = body["events"].as_array().unwrap();
+        let kinds: Vec<&str> = events.iter().map(|event| event["event"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["access_denied", "download", "upload"]);
+
+        let upload = &events[2];
+        assert_eq!(upload["user_id"], token.0);
+        assert_eq!(upload["user_agent"], "curl/8.0");
+        assert_eq!(upload["file_hash"], file_hash.as_str());
+        assert!(upload["file_id"].is_number());
+        assert_eq!(events[1]["user_id"], serde_json::Value::Null);
+        assert_eq!(events[1]["client_ip"], "203.0.113.9");
+
+        let response = client
+            .get(format!("/api/v1/admin/audit/export?event=download&file={}", file_hash))
+            .header(admin.clone())
+            .dispatch();
+        assert_eq!(response.content_type().unwrap().to_string(), "application/x-ndjson");
+        assert_eq!(response.into_string().unwrap().lines().count(), 1);
+
+        let response = client.get("/api/v1/admin/audit?since=soon").header(admin.clone()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get("/api/v1/admin/audit?event=nope").header(admin).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use crate::auth::{create_token, create_user};
+    use crate::models::NewFile;
+    use crate::tests::support::test_client;
+    use crate::stats::{DownloadStats, DownloadStatsConfig};
+    use crate::{create_file, delete_file, establish_connection, MIGRATIONS};
+    use diesel::prelude::*;
+    use diesel_migrations::MigrationHarness;
+    use rocket::figment::Figment;
+    use rocket::http::{Header, Status};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn at(time: &str) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn test_counters_are_batched_and_flushed() {
+        use crate::schema::daily_downloads;
+
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let file = create_file(&mut conn, NewFile {
+            file_hash: "stats_hash",
+            file_name: "stats.bin",
+            file_path: "/tmp/stats.bin",
+            size: 100,
+            private: false,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "stats_hash",
+        }).unwrap();
+
+        let stats = DownloadStats::new(DownloadStatsConfig::default());
+        stats.record(file.id, true, 100, at("2026-10-17 23:50"));
+        stats.record(file.id, true, 100, at("2026-10-18 08:00"));
+        // A resumed download adds bytes but isn't a new download
+        stats.record(file.id, false, 40, at("2026-10-18 08:05"));
+        // Counts for a file deleted in the meantime are dropped
+        stats.record(file.id + 1, true, 10, at("2026-10-18 08:00"));
+
+        let unflushed = stats.for_file(&mut conn, &file).unwrap();
+        assert_eq!((unflushed.downloads, unflushed.bytes_served), (2, 240));
+        assert_eq!(stats.flush(&mut conn).unwrap(), 2);
+        assert_eq!(stats.flush(&mut conn).unwrap(), 0);
+
+        stats.record(file.id, true, 100, at("2026-10-18 09:00"));
+        stats.flush(&mut conn).unwrap();
+        let file = crate::get_file_by_hash(&mut conn, "stats_hash").unwrap();
+        assert_eq!((file.download_count, file.bytes_served), (3, 340));
+        assert_eq!(file.last_downloaded_at, Some(at("2026-10-18 09:00")));
+
+        let flushed = stats.for_file(&mut conn, &file).unwrap();
+        let daily: Vec<(String, i32)> = flushed.daily.unwrap().iter().map(|day| (day.day.to_string(), day.downloads)).collect();
+        assert_eq!(daily, [("2026-10-17".to_string(), 1), ("2026-10-18".to_string(), 2)]);
+
+        delete_file(&mut conn, &file).unwrap();
+        assert_eq!(daily_downloads::table.count().get_result::<i64>(&mut conn).unwrap(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_per_day_counts_can_be_disabled() {
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let file = create_file(&mut conn, NewFile {
+            file_hash: "no_days",
+            file_name: "a.bin",
+            file_path: "/tmp/a.bin",
+            size: 1,
+            private: false,
+            mime_type: "application/octet-stream",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id: "no_days",
+        }).unwrap();
+
+        let stats = DownloadStats::new(DownloadStatsConfig { per_day: false, ..DownloadStatsConfig::default() });
+        stats.record(file.id, true, 1, at("2026-10-18 08:00"));
+        stats.flush(&mut conn).unwrap();
+        let file = crate::get_file_by_hash(&mut conn, "no_days").unwrap();
+        let file_stats = stats.for_file(&mut conn, &file).unwrap();
+        assert_eq!(file_stats.downloads, 1);
+        assert_eq!(file_stats.daily, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_stats_endpoint_is_owner_only() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("admin_token", "admin-secret")));
+
+        let (alice, bob) = {
+            let mut conn = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+            create_user(&mut conn, "alice").unwrap();
+            create_user(&mut conn, "bob").unwrap();
+            let alice = create_token(&mut conn, "alice", "laptop").unwrap().1;
+            let bob = create_token(&mut conn, "bob", "laptop").unwrap().1;
+            (Header::new("Authorization", format!("Bearer {}", alice)), Header::new("Authorization", format!("Bearer {}", bob)))
+        };
+
+        let response = client.put("/api/v1/upload/counted.txt").header(alice.clone()).body("0123456789").dispatch();
+        let url = response.into_string().unwrap();
+        let path = url[url.find("/download/").unwrap()..].trim().to_string();
+        let stats_path = format!("/api/v1/files/{}/stats", path.trim_start_matches("/download/"));
+
+        let response = client.get(path.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "0123456789");
+        let response = client.get(path.as_str()).header(Header::new("Range", "bytes=6-")).dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.into_string().unwrap(), "6789");
+        // Abandoned before the body was read, so no bytes were served
+        assert_eq!(client.get(path.as_str()).dispatch().status(), Status::Ok);
+        // HEAD requests, which the CLI sends before each download, aren't downloads
+        assert_eq!(client.head(path.as_str()).dispatch().status(), Status::Ok);
+        let audited: i64 = crate::schema::audit_events::table
+            .filter(crate::schema::audit_events::event.eq("download"))
+            .count()
+            .get_result(&mut establish_connection(data.path().join("netdrop.db").to_str().unwrap()))
+            .unwrap();
+        assert_eq!(audited, 3);
+
+        let response = client.get(stats_path.as_str()).header(alice).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["downloads"], 2);
+        assert_eq!(body["bytes_served"], 14);
+        assert!(body["last_downloaded_at"].is_string());
+        assert_eq!(body["daily"].as_array().unwrap().len(), 1);
+
+        assert_eq!(client.get(stats_path.as_str()).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get(stats_path.as_str()).header(bob).dispatch().status(), Status::Forbidden);
+        let admin = Header::new("Authorization", "Bearer admin-secret");
+        assert_eq!(client.get(stats_path.as_str()).header(admin.clone()).dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/api/v1/files/missing/stats").header(admin).dispatch().status(), Status::NotFound);
+    }
+}
+
+#[cfg(test)]
+mod file_info_tests {
+    use crate::auth::{create_token, create_user};
+    use crate::establish_connection;
+    use crate::tests::support::test_client;
+    use rocket::http::{Header, Status};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    #[serial]
+    fn test_file_info() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(("data_dir", data.path()));
+
+        let owner = {
+            let mut conn = establish_connection(data.path().join("netdrop.db").to_str().unwrap());
+            create_user(&mut conn, "alice").unwrap();
+            Header::new("Authorization", format!("Bearer {}", create_token(&mut conn, "alice", "laptop").unwrap().1))
+        };
+
+        let response = client.put("/api/v1/upload/report.txt").header(owner.clone()).body("quarterly numbers").dispatch();
+        let url = response.into_string().unwrap();
+        let path = url[url.find("/download/").unwrap()..].trim().to_string();
+        let file_hash = path.trim_start_matches("/download/");
+        assert_eq!(client.get(path.as_str()).dispatch().status(), Status::Ok);
+
+        let response = client.get(format!("/api/v1/files/{}", file_hash)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let info: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(info["public_id"], file_hash);
+        assert_eq!(info["slug"], serde_json::Value::Null);
+        assert_eq!(info["file_name"], "report.txt");
+        assert_eq!(info["size"], 17);
+        assert_eq!(info["mime_type"], "text/plain");
+        assert_eq!(info["sha256"].as_str().unwrap().len(), 64);
+        assert!(info["created_at"].is_string());
+        assert_eq!(info["expires_at"], serde_json::Value::Null);
+        assert_eq!(info["sanitized"], false);
+        // Only the owner sees how often it was downloaded
+        assert!(info.get("download_count").is_none());
+
+        let response = client.get(format!("/api/v1/files/{}", file_hash)).header(owner).dispatch();
+        let info: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(info["download_count"], 1);
+
+        assert_eq!(client.get("/api/v1/files/missing").dispatch().status(), Status::NotFound);
+    }
+}
+
+#[cfg(test)]
+mod landing_tests {
+    use crate::landing::{render_landing_page, Landing};
+    use crate::tests::support::test_client;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use rocket::figment::Figment;
+    use rocket::http::{ContentType, Status};
+    use serial_test::serial;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_landing_page() {
+        let html = render_landing_page(&Landing {
+            file_name: "\"><script>x</script>.txt",
+            size: 1536,
+            mime_type: "text/plain",
+            page_url: "https://drop.example.com/f/abc",
+            download_url: "https://drop.example.com/download/abc",
+            view_url: None,
+            thumbnail_url: None,
+            sanitized: false,
+        });
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("metadata removed"));
+        assert!(html.contains("<meta property=\"og:title\" content=\"&quot;&gt;&lt;script&gt;x&lt;/script&gt;.txt\">"));
+        assert!(html.contains("<meta property=\"og:url\" content=\"https://drop.example.com/f/abc\">"));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary\">"));
+        assert!(html.contains("1.5KiB · text/plain"));
+        assert!(!html.contains("og:image"));
+        assert!(!html.contains("Open in browser"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_landing_page_for_image() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("public_url", "https://drop.example.com")));
+
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(64, 64)).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        let url = client.put("/api/v1/upload/photo.png").body(png).dispatch().into_string().unwrap();
+        let file_hash = url.trim().rsplit('/').next().unwrap().to_string();
+
+        let response = client.get(format!("/f/{}", file_hash)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let html = response.into_string().unwrap();
+        let thumbnail = format!("https://drop.example.com/thumb/{}?size=512", file_hash);
+        assert!(html.contains(&format!("<meta property=\"og:image\" content=\"{}\">", thumbnail)));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary_large_image\">"));
+        assert!(html.contains(&format!("href=\"https://drop.example.com/download/{}\"", file_hash)));
+        assert!(html.contains(&format!("href=\"https://drop.example.com/view/{}\"", file_hash)));
+
+        assert_eq!(client.get("/f/missing").dispatch().status(), Status::NotFound);
+    }
+}
+
+#[cfg(test)]
+mod public_id_tests {
+    use crate::auth::{create_token, create_user};
+    use crate::config::Config;
+    use crate::models::NewFile;
+    use crate::public_id::{generate, set_slug, validate_slug, PublicIdConfig, SlugError};
+    use crate::tests::support::test_client;
+    use crate::{create_file, establish_connection, get_file_by_hash, get_file_by_public_id, MIGRATIONS};
+    use diesel::prelude::*;
+    use diesel_migrations::MigrationHarness;
+    use rocket::figment::Figment;
+    use rocket::http::{ContentType, Header, Status};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_generate_and_validate() {
+        let id = generate(16);
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(id, generate(16));
+
+        assert_eq!(validate_slug("q3-report"), Ok(()));
+        assert_eq!(validate_slug("ab"), Err(SlugError::Invalid));
+        assert_eq!(validate_slug("Report"), Err(SlugError::Invalid));
+        assert_eq!(validate_slug("-report"), Err(SlugError::Invalid));
+        assert_eq!(validate_slug("q3--report"), Err(SlugError::Invalid));
+        assert_eq!(validate_slug("../etc"), Err(SlugError::Invalid));
+        assert_eq!(validate_slug("admin"), Err(SlugError::Reserved));
+
+        let config = Config { public_ids: PublicIdConfig { length: 4 }, ..Config::default() };
+        assert!(config.validated().is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_ids_and_slugs() {
+        let data = TempDir::new().unwrap();
+        let client = test_client(Figment::new().merge(("data_dir", data.path())).merge(("public_ids.length", 24)));
+        let database_url = data.path().join("netdrop.db").to_string_lossy().into_owned();
+
+        let (alice, bob) = {
+            let mut conn = establish_connection(&database_url);
+            create_user(&mut conn, "alice").unwrap();
+            create_user(&mut conn, "bob").unwrap();
+            let alice = create_token(&mut conn, "alice", "laptop").unwrap().1;
+            let bob = create_token(&mut conn, "bob", "laptop").unwrap().1;
+            (Header::new("Authorization", format!("Bearer {}", alice)), Header::new("Authorization", format!("Bearer {}", bob)))
+        };
+
+        let response = client.put("/api/v1/upload/q3.csv").header(alice.clone()).body("a,b\n1,2\n").dispatch();
+        let url = response.into_string().unwrap();
+        let public_id = url.trim().rsplit('/').next().unwrap().to_string();
+        assert_eq!(public_id.len(), 24);
+
+        // Links don't give away the name the file is stored under
+        let file = get_file_by_public_id(&mut establish_connection(&database_url), &public_id).unwrap();
+        assert_ne!(file.file_hash, public_id);
+        assert!(!file.file_path.contains(&public_id));
+        assert_eq!(client.get(format!("/download/{}", file.file_hash)).dispatch().status(), Status::NotFound);
+
+        let slug_path = format!("/api/v1/files/{}/slug", public_id);
+        let set_slug = |slug: &str, auth: Option<&Header<'static>>| {
+            let mut request = client.put(slug_path.as_str()).header(ContentType::JSON).body(format!("{{\"slug\":\"{}\"}}", slug));
+            if let Some(auth) = auth {
+                request = request.header(auth.clone());
+            }
+            request.dispatch().status()
+        };
+
+        assert_eq!(set_slug("q3-report", None), Status::Unauthorized);
+        assert_eq!(set_slug("q3-report", Some(&bob)), Status::Forbidden);
+        assert_eq!(set_slug("Q3 report", Some(&alice)), Status::BadRequest);
+        assert_eq!(set_slug("download", Some(&alice)), Status::BadRequest);
+        assert_eq!(set_slug("q3-report", Some(&alice)), Status::Ok);
+        // Setting the same slug again is a no-op
+        assert_eq!(set_slug("q3-report", Some(&alice)), Status::Ok);
+
+        let response = client.get("/download/q3-report").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "a,b\n1,2\n");
+        assert_eq!(client.get("/f/q3-report").dispatch().status(), Status::Ok);
+
+        let other = client.put("/api/v1/upload/q4.csv").header(alice.clone()).body("c").dispatch().into_string().unwrap();
+        let other_id = other.trim().rsplit('/').next().unwrap();
+        let response = client
+            .put(format!("/api/v1/files/{}/slug", other_id))
+            .header(ContentType::JSON)
+            .header(alice.clone())
+            .body(r#"{"slug":"q3-report"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client.delete(slug_path.as_str()).header(alice).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(client.get("/download/q3-report").dispatch().status(), Status::NotFound);
+        assert_eq!(client.get(format!("/download/{}", public_id)).dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    #[serial]
+    fn test_slugs_and_public_ids_share_one_namespace() {
+        let mut conn = establish_connection(":memory:");
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let new_file = |public_id| NewFile {
+            file_hash: public_id,
+            file_name: "a.txt",
+            file_path: "/tmp/a.txt",
+            size: 1,
+            private: false,
+            mime_type: "text/plain",
+            scan_status: "unscanned",
+            sanitized: false,
+            language: None,
+            sha256: None,
+            owner_id: None,
+            public_id,
+        };
+        let first = create_file(&mut conn, new_file("report2026")).unwrap();
+        let second = create_file(&mut conn, new_file("k3x9q2m7w1z8")).unwrap();
+
+        // Another file's public id is as taken as its slug
+        assert_eq!(set_slug(&mut conn, &second, Some("report2026")), Err(SlugError::Taken));
+        assert_eq!(set_slug(&mut conn, &first, Some("q3-report")), Ok(()));
+        assert_eq!(set_slug(&mut conn, &second, Some("q3-report")), Err(SlugError::Taken));
+
+        // Renaming frees the old slug, and a stale row doesn't free the new one
+        assert_eq!(set_slug(&mut conn, &first, Some("q4-report")), Ok(()));
+        assert_eq!(set_slug(&mut conn, &second, Some("q3-report")), Ok(()));
+        assert_eq!(set_slug(&mut conn, &first, Some("q3-report")), Err(SlugError::Taken));
+        assert_eq!(get_file_by_public_id(&mut conn, "q4-report").unwrap().id, first.id);
+
+        crate::delete_file(&mut conn, &second).unwrap();
+        assert_eq!(set_slug(&mut conn, &first, Some("q3-report")), Ok(()));
+        assert!(!crate::public_id::is_taken(&mut conn, "q4-report").unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_legacy_files_get_random_ids_and_old_links_redirect() {
+        let data = TempDir::new().unwrap();
+        let database_url = data.path().join("netdrop.db").to_string_lossy().into_owned();
+        let file_hash = "a".repeat(64);
+        let blob = data.path().join("uploads").join(&file_hash[..16]);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, b"hello").unwrap();
+
+        // A row written before any later column existed
+        {
+            let mut conn = establish_connection(&database_url);
+            let create_files = conn.pending_migrations(MIGRATIONS).unwrap().remove(0);
+            conn.run_migration(&*create_files).unwrap();
+            diesel::sql_query(format!(
+                "INSERT INTO files (file_hash, file_name, file_path, size, private) VALUES ('{}', 'old.txt', '{}', 5, 0)",
+                file_hash,
+                blob.display()
+            ))
+            .execute(&mut conn)
+            .unwrap();
+            conn.run_pending_migrations(MIGRATIONS).unwrap();
+        }
+
+        let file = get_file_by_hash(&mut establish_connection(&database_url), &file_hash).unwrap();
+        assert_ne!(file.public_id, file_hash);
+        assert_eq!(file.public_id.len(), 16);
+        assert_eq!(file.legacy_id.as_deref(), Some(file_hash.as_str()));
+
+        let client = test_client(("data_dir", data.path()));
+        let response = client.get(format!("/download/{}", file_hash)).dispatch();
+        assert_eq!(response.status(), Status::PermanentRedirect);
+        assert_eq!(response.headers().get_one("Location"), Some(format!("/download/{}", file.public_id).as_str()));
+        let response = client.get(format!("/download/{}?inline", file_hash)).dispatch();
+        assert_eq!(response.headers().get_one("Location"), Some(format!("/download/{}?inline=", file.public_id).as_str()));
+
+        let response = client.get(format!("/download/{}", file.public_id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "hello");
+        // Only downloads redirect, the hash isn't an id anywhere else
+        assert_eq!(client.get(format!("/f/{}", file_hash)).dispatch().status(), Status::NotFound);
+    }
 }
@@ -0,0 +1,187 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::ratelimit::TokenBucket;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ThrottleConfig {
+    /// Bytes per second for each anonymous download, 0 is unlimited.
+    pub anonymous_bytes_per_sec: u64,
+    /// Bytes per second for each download made with an API token, 0 is unlimited.
+    pub authenticated_bytes_per_sec: u64,
+    /// Bytes per second shared by all downloads, 0 is unlimited.
+    pub global_bytes_per_sec: u64,
+}
+
+fn bucket(bytes_per_sec: u64) -> Option<TokenBucket> {
+    // Buckets hold one second of transfer, so a connection can't save up a large burst
+    (bytes_per_sec > 0).then(|| {
+        let rate = bytes_per_sec as f64;
+        TokenBucket::new(rate, rate, Instant::now().into_std())
+    })
+}
+
+/// Hands out throttled readers that share the global egress budget.
+pub struct Throttle {
+    config: ThrottleConfig,
+    global: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        let global = bucket(config.global_bytes_per_sec).map(|b| Arc::new(Mutex::new(b)));
+        Throttle { config, global }
+    }
+
+    pub fn config(&self) -> &ThrottleConfig {
+        &self.config
+    }
+
+    /// Wraps `inner` in the per-connection limit for the client's tier and the global limit.
+    pub fn reader<R>(&self, inner: R, authenticated: bool) -> ThrottledReader<R> {
+        let rate = if authenticated {
+            self.config.authenticated_bytes_per_sec
+        } else {
+            self.config.anonymous_bytes_per_sec
+        };
+
+        ThrottledReader::new(inner, bucket(rate), self.global.clone())
+    }
+}
+
+/// An `AsyncRead` that only yields bytes as fast as its token buckets allow.
+pub struct ThrottledReader<R> {
+    inner: R,
+    connection: Option<TokenBucket>,
+    global: Option<Arc<Mutex<TokenBucket>>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(
+        inner: R,
+        connection: Option<TokenBucket>,
+        global: Option<Arc<Mutex<TokenBucket>>>,
+    ) -> Self {
+        ThrottledReader {
+            inner,
+            connection,
+            global,
+            sleep: None,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Bytes that may be read right now, or how long to wait before trying again
+    fn allowance(&mut self) -> Result<Option<usize>, std::time::Duration> {
+        let now = Instant::now().into_std();
+        let mut allowance: Option<f64> = None;
+        let mut wait = std::time::Duration::ZERO;
+
+        if let Some(connection) = self.connection.as_mut() {
+            let available = connection.available(now);
+            allowance = Some(available);
+            if available < 1.0 {
+                wait = wait.max(connection.wait_for(1.0, now));
+            }
+        }
+        if let Some(global) = self.global.as_ref() {
+            let mut global = global.lock().unwrap();
+            let available = global.available(now);
+            allowance = Some(allowance.map_or(available, |a| a.min(available)));
+            if available < 1.0 {
+                wait = wait.max(global.wait_for(1.0, now));
+            }
+        }
+
+        match allowance {
+            None => Ok(None),
+            Some(_) if !wait.is_zero() => Err(wait),
+            Some(bytes) => Ok(Some(bytes as usize)),
+        }
+    }
+
+    fn charge(&mut self, amount: usize) {
+        let now = Instant::now().into_std();
+        if let Some(connection) = self.connection.as_mut() {
+            connection.charge(amount as f64, now);
+        }
+        if let Some(global) = self.global.as_ref() {
+            global.lock().unwrap().charge(amount as f64, now);
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+            }
+
+            match this.allowance() {
+                Ok(None) => return Pin::new(&mut this.inner).poll_read(cx, buf),
+                Ok(Some(allowance)) => {
+                    let mut limited = buf.take(allowance.min(buf.remaining()));
+                    let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+                    let read = limited.filled().len();
+
+                    if let Poll::Ready(Ok(())) = result {
+                        // SAFETY: the inner reader initialized these bytes through `limited`
+                        unsafe { buf.assume_init(read) };
+                        buf.advance(read);
+                        this.charge(read);
+                    }
+                    return result;
+                }
+                Err(wait) => {
+                    this.sleep = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for ThrottledReader<R> {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+impl<'r, R> Responder<'r, 'static> for ThrottledReader<R>
+where
+    R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+{
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build().sized_body(None, self).ok()
+    }
+}
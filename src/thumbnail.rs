@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageReader, Limits};
+use tokio::sync::mpsc;
+
+use crate::establish_connection;
+use crate::models::{File, NewThumbnail, Thumbnail};
+use crate::scan::ScanStatus;
+
+/// Edge lengths, in pixels, of the thumbnails generated for each image.
+pub const THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+const JPEG_QUALITY: u8 = 80;
+
+// Refuse to decode images that would need more memory than this
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+const MAX_DIMENSION: u32 = 16_384;
+
+/// Shown for files without a thumbnail.
+pub const PLACEHOLDER_SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="256" height="256" viewBox="0 0 24 24" fill="none" stroke="#9ca3af" stroke-width="1.5" stroke-linecap="round" stroke-linejoin="round"><path d="M14 2H6a2 2 0 0 0-2 2v16a2 2 0 0 0 2 2h12a2 2 0 0 0 2-2V8z"/><path d="M14 2v6h6"/></svg>"##;
+
+const SUPPORTED_TYPES: [&str; 5] = ["image/png", "image/jpeg", "image/gif", "image/webp", "image/bmp"];
+
+/// Whether thumbnails can be generated for files of this type.
+pub fn is_supported(mime_type: &str) -> bool {
+    SUPPORTED_TYPES.contains(&mime_type)
+}
+
+/// Picks the smallest generated size that is at least `requested` pixels.
+pub fn nearest_size(requested: Option<u32>) -> u32 {
+    let requested = requested.unwrap_or(THUMBNAIL_SIZES[1]);
+    THUMBNAIL_SIZES
+        .iter()
+        .copied()
+        .find(|&size| size >= requested)
+        .unwrap_or(THUMBNAIL_SIZES[THUMBNAIL_SIZES.len() - 1])
+}
+
+#[derive(Debug)]
+pub enum ThumbnailError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Database(diesel::result::Error),
+}
+
+impl std::fmt::Display for ThumbnailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThumbnailError::Io(e) => write!(f, "I/O error: {}", e),
+            ThumbnailError::Image(e) => write!(f, "image error: {}", e),
+            ThumbnailError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ThumbnailError {}
+
+impl From<std::io::Error> for ThumbnailError {
+    fn from(e: std::io::Error) -> Self {
+        ThumbnailError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ThumbnailError {
+    fn from(e: image::ImageError) -> Self {
+        ThumbnailError::Image(e)
+    }
+}
+
+impl From<diesel::result::Error> for ThumbnailError {
+    fn from(e: diesel::result::Error) -> Self {
+        ThumbnailError::Database(e)
+    }
+}
+
+pub fn decode_image(path: &Path) -> Result<DynamicImage, ThumbnailError> {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// Resizes `image` to fit within `size` pixels, never upscaling.
+///
+/// Images with transparency are encoded as lossless WebP, everything else as JPEG.
+pub fn render_thumbnail(image: &DynamicImage, size: u32) -> Result<(Vec<u8>, &'static str), ThumbnailError> {
+    let resized = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image.clone()
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    if resized.color().has_alpha() {
+        resized
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut buffer))?;
+        Ok((buffer.into_inner(), "image/webp"))
+    } else {
+        resized
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY))?;
+        Ok((buffer.into_inner(), "image/jpeg"))
+    }
+}
+
+/// Generates every thumbnail size for `file`, storing them under `thumbnail_dir`.
+pub fn generate_thumbnails(
+    conn: &mut SqliteConnection,
+    file: &File,
+    thumbnail_dir: &Path,
+) -> Result<Vec<Thumbnail>, ThumbnailError> {
+    use crate::schema::thumbnails;
+
+    fs::create_dir_all(thumbnail_dir)?;
+    let image = decode_image(Path::new(&file.file_path))?;
+
+    let mut generated = Vec::new();
+    for size in THUMBNAIL_SIZES {
+        let (data, mime_type) = render_thumbnail(&image, size)?;
+        let extension = if mime_type == "image/webp" { "webp" } else { "jpg" };
+        let path = thumbnail_dir.join(format!("{}_{}.{}", file.file_hash, size, extension));
+        fs::write(&path, data)?;
+
+        let thumbnail = diesel::insert_into(thumbnails::table)
+            .values(&NewThumbnail {
+                file_id: file.id,
+                size: size as i32,
+                mime_type,
+                file_path: &path.to_string_lossy(),
+            })
+            .returning(Thumbnail::as_returning())
+            .get_result(conn)?;
+        generated.push(thumbnail);
+    }
+
+    Ok(generated)
+}
+
+pub fn get_thumbnail(conn: &mut SqliteConnection, file_id: i32, size: u32) -> Option<Thumbnail> {
+    use crate::schema::thumbnails;
+
+    thumbnails::table
+        .filter(thumbnails::file_id.eq(file_id))
+        .filter(thumbnails::size.eq(size as i32))
+        .first::<Thumbnail>(conn)
+        .ok()
+}
+
+/// Ids of images uploaded before `uploaded_before` that have no thumbnails yet.
+///
+/// The queue only lives in memory, so these are the jobs a restart dropped.
+/// Infected files are skipped, their blob has been moved to quarantine.
+pub fn files_missing_thumbnails(conn: &mut SqliteConnection, uploaded_before: NaiveDateTime) -> QueryResult<Vec<i32>> {
+    use crate::schema::{files, thumbnails};
+    use diesel::dsl::{exists, not};
+
+    files::table
+        .filter(files::mime_type.eq_any(SUPPORTED_TYPES))
+        .filter(files::scan_status.ne(ScanStatus::Infected.as_str()))
+        .filter(files::created_at.lt(uploaded_before))
+        .filter(not(exists(thumbnails::table.filter(thumbnails::file_id.eq(files::id)))))
+        .order(files::id.asc())
+        .select(files::id)
+        .load(conn)
+}
+
+/// Queues uploads for thumbnail generation by a background worker.
+#[derive(Clone)]
+pub struct ThumbnailQueue {
+    sender: mpsc::UnboundedSender<i32>,
+}
+
+impl ThumbnailQueue {
+    /// Creates the queue and the receiving end to hand to [`run_worker`].
+    pub fn new() -> (ThumbnailQueue, mpsc::UnboundedReceiver<i32>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (ThumbnailQueue { sender }, receiver)
+    }
+
+    pub fn enqueue(&self, file_id: i32) {
+        // The worker only stops when the server shuts down
+        let _ = self.sender.send(file_id);
+    }
+}
+
+/// Generates thumbnails for queued files one at a time, so uploads don't compete for CPU.
+pub async fn run_worker(mut receiver: mpsc::UnboundedReceiver<i32>, database_url: String, thumbnail_dir: PathBuf) {
+    while let Some(file_id) = receiver.recv().await {
+        let (database_url, thumbnail_dir) = (database_url.clone(), thumbnail_dir.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            use crate::schema::files;
+
+            let mut conn = establish_connection(&database_url);
+            let file = files::table.find(file_id).first::<File>(&mut conn)?;
+            generate_thumbnails(&mut conn, &file, &thumbnail_dir)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::error!(file_id, error = %e, "failed to generate thumbnails"),
+            Err(e) => tracing::error!(file_id, error = %e, "thumbnail task failed"),
+        }
+    }
+}
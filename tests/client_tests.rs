@@ -0,0 +1,153 @@
+// Drives netdrop-cli's client against an in-process server
+
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use netdrop::client::{Client, ClientError};
+use tempfile::TempDir;
+
+struct TestServer {
+    url: String,
+    _data: TempDir,
+}
+
+fn server() -> &'static TestServer {
+    static SERVER: OnceLock<TestServer> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let data = TempDir::new().unwrap();
+        unsafe {
+            std::env::set_var("DATA_DIR", data.path());
+            std::env::set_var("DATABASE_URL", data.path().join("netdrop.db"));
+            std::env::set_var("NETDROP_LOGGING__FILTER", "off");
+        }
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        thread::spawn(move || {
+            let config = rocket::Config {
+                port,
+                address: [127, 0, 0, 1].into(),
+                log_level: rocket::config::LogLevel::Off,
+                ..rocket::Config::debug_default()
+            };
+            rocket::execute(netdrop::server::rocket().unwrap().configure(config).launch()).unwrap();
+        });
+
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        for _ in 0..100 {
+            if TcpStream::connect(address).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        TestServer {
+            url: format!("http://{}", address),
+            _data: data,
+        }
+    })
+}
+
+fn client() -> Client {
+    let mut client = Client::new(&server().url, None).unwrap();
+    client.retries = 0;
+    client
+}
+
+fn upload(client: &Client, path: &Path, name: &str) -> netdrop::client::Uploaded {
+    client.upload_file(path, name, |_| {}).unwrap()
+}
+
+#[test]
+fn test_upload_and_download_round_trip() {
+    let client = client();
+    let dir = TempDir::new().unwrap();
+    let source = dir.path().join("notes.txt");
+    let content = "round trip ".repeat(1000);
+    fs::write(&source, &content).unwrap();
+
+    let uploaded = upload(&client, &source, "notes.txt");
+    assert_eq!(uploaded.url, client.share_url(&uploaded.file_hash));
+    assert!(uploaded.sha256.is_some());
+
+    let output = dir.path().join("out");
+    fs::create_dir(&output).unwrap();
+    let saved = client.download(&uploaded.url, &output, &|_, _| {}).unwrap();
+    assert_eq!(saved, output.join("notes.txt"));
+    assert_eq!(fs::read_to_string(&saved).unwrap(), content);
+    assert!(!output.join("notes.txt.part").exists());
+}
+
+#[test]
+fn test_download_resumes_partial_file() {
+    let client = client();
+    let dir = TempDir::new().unwrap();
+    let source = dir.path().join("data.bin");
+    let content: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&source, &content).unwrap();
+    let uploaded = upload(&client, &source, "data.bin");
+
+    // Half of the file is already there from an interrupted run
+    let output = dir.path().join("copy.bin");
+    fs::write(dir.path().join("copy.bin.part"), &content[..20_000]).unwrap();
+
+    let first_progress = std::sync::Mutex::new(None);
+    let saved = client
+        .download(&uploaded.file_hash, &output, &|received, total| {
+            first_progress.lock().unwrap().get_or_insert((received, total));
+        })
+        .unwrap();
+    assert_eq!(saved, output);
+    assert_eq!(fs::read(&output).unwrap(), content);
+    assert_eq!(*first_progress.lock().unwrap(), Some((20_000, Some(50_000))));
+
+    // A complete .part file is just renamed
+    fs::write(dir.path().join("again.bin.part"), &content).unwrap();
+    let again = client.download(&uploaded.url, &dir.path().join("again.bin"), &|_, _| {}).unwrap();
+    assert_eq!(fs::read(again).unwrap(), content);
+}
+
+#[test]
+fn test_upload_directory() {
+    let client = client();
+    let dir = TempDir::new().unwrap();
+    let tree = dir.path().join("photos");
+    fs::create_dir_all(tree.join("2026")).unwrap();
+    fs::write(tree.join("2026/a.txt"), "a").unwrap();
+    fs::write(tree.join("b.txt"), "b").unwrap();
+
+    let tarball = netdrop::client::pack_directory(&tree).unwrap();
+    let uploaded = upload(&client, tarball.path(), "photos.tar.gz");
+
+    let saved = client.download(&uploaded.url, dir.path(), &|_, _| {}).unwrap();
+    assert_eq!(saved.file_name().unwrap(), "photos.tar.gz");
+
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(saved).unwrap()));
+    let mut names: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert!(names.contains(&"photos/2026/a.txt".to_string()));
+    assert!(names.contains(&"photos/b.txt".to_string()));
+}
+
+#[test]
+fn test_download_links() {
+    let client = Client::new("http://drop.example/", None).unwrap();
+    assert_eq!(client.share_url("abc"), "http://drop.example/download/abc");
+    assert_eq!(client.download_url("abc").unwrap(), "http://drop.example/download/abc");
+    assert_eq!(
+        client.download_url("https://other.example/sub/view/abc").unwrap(),
+        "https://other.example/sub/download/abc"
+    );
+    assert!(matches!(client.download_url("https://other.example/"), Err(ClientError::InvalidLink(_))));
+    assert!(matches!(client.download_url("a b"), Err(ClientError::InvalidLink(_))));
+
+    let missing = self::client().download("0000000000", Path::new("."), &|_, _| {});
+    assert!(matches!(missing, Err(ClientError::Server(_))));
+}
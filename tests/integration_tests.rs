@@ -245,7 +245,12 @@ fn test_cors_configuration() {
 
     // The actual CORS headers would be tested in full integration tests
     // with a running server, but this ensures the configuration is valid
-    assert!(true, "CORS configuration compiles successfully");
+    let cors = netdrop::cors::CorsConfig {
+        allowed_origins: vec!["https://app.example.com".to_string()],
+        ..Default::default()
+    };
+    assert!(cors.validate().is_ok(), "CORS configuration is valid");
+    assert!(cors.fairing(None).unwrap().is_some(), "CORS fairing builds successfully");
 }
 
 #[test]
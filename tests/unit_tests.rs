@@ -1,5 +1,4 @@
 use sha2::{Sha256, Digest};
-use hex;
 use std::fs;
 use tempfile::TempDir;
 use std::env;